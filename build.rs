@@ -0,0 +1,26 @@
+use std::path::Path;
+use std::process::Command;
+
+// Embeds the git commit and target triple, for --version.
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=PARTY_SAVER_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=PARTY_SAVER_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+
+    // Re-run when the checked out commit changes, but not on every build.
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::config::{BankConfig, LedConfig};
+use crate::led::{Led, LedColor};
+use crate::sink::MidiSink;
+use crate::{log_send, Result, CONTROL_CHANGE, DECK_COUNT};
+
+// A bank of knobs (e.g. EQs) shared between the decks, which controls whichever deck is focused.
+pub struct FocusedBank {
+    config: BankConfig,
+    leds: LedConfig,
+    pub deck_index: usize,
+    // Each deck's knob values, indexed by deck and then knob.
+    pub values: Vec<Vec<u8>>,
+    // Where each physical knob was last seen.
+    prev_values: Vec<u8>,
+}
+
+impl FocusedBank {
+    pub fn new(config: BankConfig, leds: LedConfig) -> Self {
+        Self {
+            deck_index: 0,
+            values: vec![vec![63; config.ccs.len()]; DECK_COUNT],
+            prev_values: vec![63; config.ccs.len()],
+            config,
+            leds,
+        }
+    }
+
+    pub fn select_deck(&mut self, note: u8, color_out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(i) = self.config.select_notes.iter().position(|&x| x == note) else {
+            return Ok(false);
+        };
+
+        self.deck_index = i;
+        self.light_selected(color_out)?;
+        Ok(true)
+    }
+
+    // Lights the focused deck's LED, and turns off the others.
+    pub fn light_selected(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for (i, &note) in self.config.select_notes.iter().enumerate() {
+            let color = if i == self.deck_index {
+                self.leds.bank_deck_focused
+            } else {
+                self.leds.bank_deck_unfocused
+            };
+
+            Led::new(note).set(color, color_out)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn adjust(&mut self, cc: u8, data: u8, out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(knob) = self.config.ccs.iter().position(|&x| x == cc) else {
+            return Ok(false);
+        };
+
+        let data = data.min(127);
+        let prev_value = std::mem::replace(&mut self.prev_values[knob], data);
+        let deck_value = &mut self.values[self.deck_index][knob];
+
+        // Pickup algorithm: Don't do anything until the knob has passed the deck's stored value.
+        let prev_sign = (*deck_value).cmp(&prev_value);
+        if (*deck_value).cmp(&data) == prev_sign {
+            return Ok(true);
+        }
+        *deck_value = data;
+
+        let out_cc = self.config.out_ccs[self.deck_index][knob];
+        log_send(self.config.channel(), CONTROL_CHANGE, out_cc, data, out)?;
+        Ok(true)
+    }
+
+    // Re-sends every deck's knob values, and the focus LEDs.
+    pub fn sync(&self, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        for (out_ccs, values) in self.config.out_ccs.iter().zip(&self.values) {
+            for (&out_cc, &value) in out_ccs.iter().zip(values) {
+                log_send(self.config.channel(), CONTROL_CHANGE, out_cc, value, out)?;
+            }
+        }
+
+        self.light_selected(color_out)
+    }
+
+    pub fn clear_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for note in self.config.select_notes {
+            Led::new(note).set(LedColor::Off, color_out)?;
+        }
+
+        Ok(())
+    }
+}
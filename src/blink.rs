@@ -0,0 +1,43 @@
+use crate::led::{Led, LedColor};
+use crate::sink::MidiSink;
+use crate::Result;
+use std::time::{Duration, Instant};
+
+// How long the LED stays in each state while blinking.
+const BLINK_PERIOD: Duration = Duration::from_millis(120);
+
+// Flashes an LED between two colors a number of times, then leaves it in the color it started in.
+pub struct Blink {
+    led: Led,
+    colors: [LedColor; 2],
+    flips_left: u32,
+    next_flip: Instant,
+}
+
+impl Blink {
+    pub fn new(led: Led, current: LedColor, other: LedColor, count: u32, now: Instant) -> Self {
+        Self {
+            led,
+            colors: [current, other],
+            flips_left: count * 2,
+            next_flip: now,
+        }
+    }
+
+    // Returns whether the blink is still in progress.
+    pub fn tick(&mut self, now: Instant, color_out: &mut dyn MidiSink) -> Result<bool> {
+        if self.flips_left == 0 {
+            return Ok(false);
+        }
+        if now < self.next_flip {
+            return Ok(true);
+        }
+
+        self.colors.swap(0, 1);
+        self.led.set(self.colors[0], color_out)?;
+
+        self.flips_left -= 1;
+        self.next_flip = now + BLINK_PERIOD;
+        Ok(self.flips_left > 0)
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::{BrowseConfig, FakeButtonConfig};
+use crate::hold::{HoldTimer, Press};
+use crate::sink::MidiSink;
+use crate::{handle_fake_button, log_send, Result, FAKE_BUTTON_CHANNELS, NOTE_ON};
+use std::time::Instant;
+
+// A library browse encoder. Turning it ticks fake buttons like the loop encoders, and pushing it
+// either loads the selected track (if held for long enough, so a track isn't loaded over a playing
+// deck by accident) or opens/closes the selected folder.
+pub struct BrowseEncoder {
+    config: BrowseConfig,
+    push: HoldTimer,
+}
+
+impl BrowseEncoder {
+    pub fn new(config: BrowseConfig) -> Self {
+        Self {
+            push: HoldTimer::new(config.hold_ms),
+            config,
+        }
+    }
+
+    // None if `cc` isn't the encoder's, and otherwise whether `data` was understood as a tick.
+    pub fn handle_cc(
+        &self,
+        cc: u8,
+        data: u8,
+        fake_buttons: &FakeButtonConfig,
+        out: &mut dyn MidiSink,
+    ) -> Result<Option<bool>> {
+        if cc != self.config.cc {
+            return Ok(None);
+        }
+
+        handle_fake_button(cc, cc, data, fake_buttons, FAKE_BUTTON_CHANNELS, out).map(Some)
+    }
+
+    pub fn handle_button(
+        &mut self,
+        note: u8,
+        state: bool,
+        now: Instant,
+        out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        if note != self.config.push_note {
+            return Ok(false);
+        }
+
+        if state {
+            self.push.press(now);
+        } else if let Some(press) = self.push.release(now) {
+            let note = match press {
+                Press::Hold => self.config.load_note,
+                Press::Tap => self.config.tap_note,
+            };
+            log_send(self.config.channel, NOTE_ON, note, 127, out)?;
+        }
+        Ok(true)
+    }
+
+    // Loads the track as soon as the push has been held for long enough, rather than on release.
+    pub fn tick(&mut self, now: Instant, out: &mut dyn MidiSink) -> Result<()> {
+        if self.push.tick(now) {
+            log_send(
+                self.config.channel,
+                NOTE_ON,
+                self.config.load_note,
+                127,
+                out,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use std::time::Duration;
+
+    const PUSH: u8 = 0x0D;
+    const LOAD: [u8; 3] = [0x9A, 0x00, 0x7F];
+    const TAP: [u8; 3] = [0x9A, 0x01, 0x7F];
+
+    fn encoder() -> BrowseEncoder {
+        BrowseEncoder::new(BrowseConfig {
+            cc: 4,
+            push_note: PUSH,
+            load_note: 0,
+            tap_note: 1,
+            channel: 10,
+            hold_ms: 250,
+        })
+    }
+
+    #[test]
+    fn short_pushes_tap() {
+        let mut browse = encoder();
+        let mut out = RecordingSink::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        browse.handle_button(PUSH, true, at(0), &mut out).unwrap();
+        browse.tick(at(100), &mut out).unwrap();
+        browse.tick(at(249), &mut out).unwrap();
+        assert!(out.messages.is_empty());
+        browse
+            .handle_button(PUSH, false, at(249), &mut out)
+            .unwrap();
+        assert_eq!(out.messages, [TAP]);
+    }
+
+    #[test]
+    fn long_pushes_load_once() {
+        let mut browse = encoder();
+        let mut out = RecordingSink::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        browse.handle_button(PUSH, true, at(0), &mut out).unwrap();
+        browse.tick(at(250), &mut out).unwrap();
+        assert_eq!(out.messages, [LOAD]);
+        browse.tick(at(300), &mut out).unwrap();
+        browse.tick(at(1000), &mut out).unwrap();
+        // The load has already been sent, so the release sends nothing.
+        browse
+            .handle_button(PUSH, false, at(1000), &mut out)
+            .unwrap();
+        assert_eq!(out.messages, [LOAD]);
+    }
+
+    #[test]
+    fn late_ticks_still_load_on_release() {
+        let mut browse = encoder();
+        let mut out = RecordingSink::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        browse.handle_button(PUSH, true, at(0), &mut out).unwrap();
+        browse
+            .handle_button(PUSH, false, at(400), &mut out)
+            .unwrap();
+        browse.tick(at(500), &mut out).unwrap();
+        assert_eq!(out.messages, [LOAD]);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::config::{ButtonConfig, LedConfig, ReleasePolicy};
+use crate::led::{Led, LedColor};
+use crate::sink::MidiSink;
+use crate::{send_button, Result, NOTE_OFF, NOTE_ON};
+
+// A button which is sent to Rekordbox on another note, and whose LED shows the state Rekordbox
+// reports back for it (e.g. whether SYNC is on) rather than whether it's held.
+pub struct ButtonWithFeedback {
+    pub config: ButtonConfig,
+    leds: LedConfig,
+    release: ReleasePolicy,
+    // The last velocity Rekordbox sent back.
+    pub value: u8,
+}
+
+impl ButtonWithFeedback {
+    pub fn new(config: ButtonConfig, leds: LedConfig, release: ReleasePolicy) -> Self {
+        Self {
+            config,
+            leds,
+            release,
+            value: 0,
+        }
+    }
+
+    pub fn handle_button(&self, note: u8, state: bool, out: &mut dyn MidiSink) -> Result<bool> {
+        if note != self.config.note {
+            return Ok(false);
+        }
+
+        let data = if state { 127 } else { 0 };
+        send_button(
+            self.config.out_channel,
+            self.config.out_note,
+            data,
+            state,
+            self.release,
+            out,
+        )?;
+        Ok(true)
+    }
+
+    // Takes `message` if it's Rekordbox's feedback for this button, lighting the LED to match.
+    pub fn handle_feedback(
+        &mut self,
+        message: &[u8],
+        color_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        let [status, note, velocity] = *message else {
+            return Ok(false);
+        };
+        let kind = status & 0xF0;
+        if !matches!(kind, NOTE_ON | NOTE_OFF)
+            || status & 0x0F != self.config.feedback_channel()
+            || note != self.config.feedback_note()
+        {
+            return Ok(false);
+        }
+
+        self.value = if kind == NOTE_ON { velocity } else { 0 };
+        self.light_led(color_out)?;
+        Ok(true)
+    }
+
+    pub fn light_led(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        let color = if self.value > 0 {
+            self.leds.button_active
+        } else {
+            self.leds.button_inactive
+        };
+        Led::new(self.config.note).set(color, color_out)
+    }
+
+    pub fn clear_led(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        Led::new(self.config.note).set(LedColor::Off, color_out)
+    }
+}
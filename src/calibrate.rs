@@ -0,0 +1,177 @@
+use crate::config::{Config, ControlConfig};
+use crate::ports;
+use crate::{Result, CONTROL_CHANGE, TEMPO_CC};
+use midir::{Ignore, MidiInput};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{stdin, stdout, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+const TABLE: &str = "[input.scaling]";
+
+// Asks for each absolute control to be moved to both ends of its travel, and writes the lowest and
+// highest values seen to the config's [input.scaling] table. Controls default to those already in
+// it, the faders, controls declared absolute and the tempo fader.
+pub fn run(path: &Path, device_in: Option<&str>, ccs: &[u8]) -> Result<()> {
+    let text = if path.exists() {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?
+    } else {
+        String::new()
+    };
+    let config = Config::from_text(&text)
+        .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+    let ccs: BTreeSet<u8> = if ccs.is_empty() {
+        config
+            .input
+            .scaling
+            .keys()
+            .copied()
+            .chain(config.faders.iter().map(|fader| fader.cc))
+            .chain(
+                config
+                    .controls
+                    .iter()
+                    .filter(|(_, control)| matches!(control, ControlConfig::Absolute { .. }))
+                    .map(|(&cc, _)| cc),
+            )
+            .chain([TEMPO_CC])
+            .collect()
+    } else {
+        ccs.iter().copied().collect()
+    };
+
+    let mut midi_in = MidiInput::new("PartySaver calibrate")?;
+    midi_in.ignore(Ignore::All);
+    let port = match device_in {
+        Some(name) => ports::find_unique_port(&midi_in, "input", name)?,
+        None => ports::select_port(&midi_in, "input", None)?,
+    };
+
+    // The lowest and highest value seen on each CC since it was last cleared.
+    let seen = Arc::new(Mutex::new(HashMap::<u8, (u8, u8)>::new()));
+    let _conn_in = midi_in.connect(
+        &port,
+        "party-saver-calibrate",
+        |_, message, seen| {
+            if let [status, cc, value] = *message {
+                if status & 0xF0 == CONTROL_CHANGE {
+                    let mut seen = seen.lock().unwrap_or_else(PoisonError::into_inner);
+                    let (min, max) = seen.entry(cc).or_insert((value, value));
+                    *min = value.min(*min);
+                    *max = value.max(*max);
+                }
+            }
+        },
+        seen.clone(),
+    )?;
+
+    let mut ranges = BTreeMap::new();
+    for cc in ccs {
+        seen.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&cc);
+        print!(
+            "Move CC {} to both ends of its travel, then press enter (or enter s to skip): ",
+            cc
+        );
+        stdout().flush()?;
+        let mut input = String::new();
+        if stdin().read_line(&mut input)? == 0 {
+            break;
+        }
+        if input.trim() == "s" {
+            continue;
+        }
+
+        match seen.lock().unwrap_or_else(PoisonError::into_inner).get(&cc) {
+            None => println!("Nothing received on CC {}, skipping it", cc),
+            Some((min, max)) if min == max => {
+                println!("CC {} only sent {}, skipping it", cc, min)
+            }
+            Some(&(min, max)) => {
+                println!("CC {}: {} to {}", cc, min, max);
+                ranges.insert(cc, (min, max));
+            }
+        }
+    }
+    if ranges.is_empty() {
+        println!("Nothing calibrated, {} is unchanged", path.display());
+        return Ok(());
+    }
+
+    // Edited as text, so that the rest of the file's comments and layout are kept.
+    let updated = set_ranges(&text, &ranges);
+    if let Err(e) = Config::from_text(&updated) {
+        println!("Add these to {} by hand:", TABLE);
+        for (cc, (min, max)) in &ranges {
+            println!("{}", range_line(*cc, *min, *max));
+        }
+        return Err(format!("Failed to update {}: {}", path.display(), e).into());
+    }
+    let temp_path = path.with_extension("calibrating");
+    std::fs::write(&temp_path, updated)?;
+    std::fs::rename(&temp_path, path)?;
+    println!(
+        "Wrote {} calibrated CCs to {}",
+        ranges.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn range_line(cc: u8, min: u8, max: u8) -> String {
+    format!("{} = [{}, {}]", cc, min, max)
+}
+
+// Replaces the CCs' lines in the [input.scaling] table, adding any missing ones at its end, or adds
+// the table at the end of the file if it has none.
+fn set_ranges(text: &str, ranges: &BTreeMap<u8, (u8, u8)>) -> String {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let is_header = |line: &str| line.trim_start().starts_with('[');
+    let Some(start) = lines
+        .iter()
+        .position(|line| line.split('#').next().unwrap_or("").trim() == TABLE)
+    else {
+        if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push(TABLE.to_string());
+        lines.extend(
+            ranges
+                .iter()
+                .map(|(&cc, &(min, max))| range_line(cc, min, max)),
+        );
+        return lines.join("\n") + "\n";
+    };
+
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| is_header(line))
+        .map_or(lines.len(), |i| start + 1 + i);
+    let mut missing = ranges.clone();
+    for line in &mut lines[start + 1..end] {
+        let key = line
+            .split('=')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .trim_matches('"');
+        if let Some((cc, (min, max))) = key.parse().ok().and_then(|cc| missing.remove_entry(&cc)) {
+            *line = range_line(cc, min, max);
+        }
+    }
+
+    // After the table's last line, rather than any blank lines before the next.
+    let insert_at = lines[start + 1..end]
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(start + 1, |i| start + 2 + i);
+    lines.splice(
+        insert_at..insert_at,
+        missing
+            .iter()
+            .map(|(&cc, &(min, max))| range_line(cc, min, max)),
+    );
+    lines.join("\n") + "\n"
+}
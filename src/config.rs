@@ -0,0 +1,2794 @@
+use crate::led::LedColor;
+use crate::message::OutgoingMessage;
+use crate::nrpn;
+use crate::session_log;
+use crate::{
+    Result, CONTROL_CHANGE, DECK_COUNT, DEVICE_CHANNEL, FAKE_BUTTON_DOWN_CHANNEL,
+    FAKE_BUTTON_UP_CHANNEL, FILTER_CC, FILTER_ENCODER_CHANNEL, HEADPHONE_MIX_CC,
+    HEADPHONE_VOLUME_CC, MASTER_VOLUME_CC, NOTE_OFF, NOTE_ON, PB_DOWN_IN_NOTE,
+    PB_DOWN_OUT_NOTE_BASE, PB_UP_IN_NOTE, PB_UP_OUT_NOTE_BASE, TEMPO_CC, TEMPO_ENCODER_CHANNEL,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// Everything which can be tuned without recompiling. Every field has a default which matches the
+// original hardcoded behaviour, so an empty (or missing) config file changes nothing.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Which of the device's controls belong to each deck, and what they're sent as.
+    pub decks: Decks,
+    pub throttle: ThrottleConfig,
+    pub fake_buttons: FakeButtonConfig,
+    pub browse: Option<BrowseConfig>,
+    // Buttons which keep re-pressing their note while held.
+    pub repeat_buttons: Vec<RepeatConfig>,
+    pub key_control: Option<KeyControlConfig>,
+    pub talkover: Option<TalkoverConfig>,
+    pub mixxx: MixxxConfig,
+    pub tempo_range: Option<TempoRangeConfig>,
+    pub tempo_fine: Option<TempoFineConfig>,
+    // Input note which toggles the tempo fader driving every deck at once.
+    pub tempo_link_note: Option<u8>,
+    // Let the first movement of the tempo fader for each deck after startup take over straight
+    // away, rather than waiting for pickup.
+    pub tempo_arm_on_first_touch: bool,
+    // The deck (1-3) the tempo fader controls at startup.
+    pub initial_tempo_deck: usize,
+    pub fx: Option<FxConfig>,
+    // Knob banks which control whichever deck is focused.
+    pub banks: Vec<BankConfig>,
+    pub pad_row: Option<PadRowConfig>,
+    // Buttons whose LEDs follow the state Rekordbox sends back for them.
+    pub buttons: Vec<ButtonConfig>,
+    // Pads which store every value, and recall them later.
+    pub snapshot_pads: Vec<SnapshotPadConfig>,
+    // Input note which resets every control and re-sends everything.
+    pub panic_note: Option<u8>,
+    // Input note which re-sends every value, e.g. after Rekordbox has been restarted.
+    pub resync_note: Option<u8>,
+    pub resync_probe: Option<ResyncProbe>,
+    // Notes the filter and tempo deck toggles and plain buttons treat as another, keyed by the
+    // physical note. Unmapped notes are used as they are.
+    pub note_map: HashMap<u8, u8>,
+    // Output channels of plain buttons, keyed by the note sent. Others are sent on DEVICE_CHANNEL.
+    pub button_channels: HashMap<u8, u8>,
+    // How buttons' releases are sent to Rekordbox, keyed by input note, and for every other button.
+    pub release_policies: HashMap<u8, ReleasePolicy>,
+    pub release_policy: ReleasePolicy,
+    // A held button which switches controls to another layer of the mapping.
+    pub shift: Option<ShiftConfig>,
+    // Channel pressure and pitch bend handling, keyed by input channel. Messages on other channels
+    // are forwarded as-is.
+    pub aftertouch: HashMap<u8, AftertouchConfig>,
+    pub pitch_bend: HashMap<u8, PitchBendConfig>,
+    // Program changes sent as a note, keyed by program. Others are forwarded as-is.
+    pub program_changes: HashMap<u8, ProgramChangeConfig>,
+    // Don't send the device a message identical to one sent to it within this many milliseconds,
+    // to break feedback loops. 0 disables this.
+    pub echo_window_ms: u64,
+    // Send the device an active sensing message after this many seconds with no input from it, to
+    // notice when the connection has died. 0 disables this.
+    pub keepalive_secs: u64,
+    // Release a note left held in Rekordbox for this many seconds, e.g. because the device's
+    // release was lost. 0 disables this.
+    pub stuck_note_secs: u64,
+    // Notes sent to Rekordbox which are held on purpose, e.g. mapped as latching there, and so are
+    // never released for being held too long.
+    pub latching_notes: Vec<OutputNote>,
+    pub velocity_curve: VelocityCurve,
+    pub leds: LedConfig,
+    pub filter: FilterConfig,
+    pub input: InputConfig,
+    // Encoders which emulate pots. Listing any replaces the defaults entirely.
+    pub pots: Vec<PotConfig>,
+    // How individual CCs are handled, overriding the pots list: a CC declared a pot is added to it
+    // with its defaults if missing, and one declared anything else is taken out of it.
+    pub controls: HashMap<u8, ControlConfig>,
+    // Absolute faders whose values are reshaped along a curve.
+    pub faders: Vec<FaderConfig>,
+    // Rewrites CCs sent from Rekordbox to the device. The first matching rule applies, and CCs
+    // matching none (like every other message) are forwarded as-is.
+    pub feedback: Vec<FeedbackRule>,
+    // Level meters from Rekordbox, redrawn on the device's segment LEDs. These take precedence over
+    // feedback rules.
+    pub meters: Vec<MeterConfig>,
+    // Start and stop external gear, through --clock-out, when Rekordbox sends matching feedback.
+    pub clock: Vec<ClockRule>,
+    // Send messages for Rekordbox on some channels to other output ports instead, e.g. a lighting
+    // app. Ports are only opened at startup.
+    pub routes: Vec<RouteConfig>,
+    pub output: OutputConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            decks: Decks::default(),
+            throttle: ThrottleConfig::default(),
+            fake_buttons: FakeButtonConfig::default(),
+            browse: None,
+            repeat_buttons: Vec::new(),
+            key_control: None,
+            talkover: None,
+            mixxx: MixxxConfig::default(),
+            tempo_range: None,
+            tempo_fine: None,
+            tempo_link_note: None,
+            tempo_arm_on_first_touch: false,
+            initial_tempo_deck: 1,
+            fx: None,
+            banks: Vec::new(),
+            pad_row: None,
+            buttons: Vec::new(),
+            snapshot_pads: Vec::new(),
+            panic_note: None,
+            resync_note: None,
+            resync_probe: None,
+            note_map: HashMap::new(),
+            button_channels: HashMap::new(),
+            release_policies: HashMap::new(),
+            release_policy: ReleasePolicy::default(),
+            shift: None,
+            aftertouch: HashMap::new(),
+            program_changes: HashMap::new(),
+            pitch_bend: HashMap::new(),
+            echo_window_ms: 0,
+            keepalive_secs: 0,
+            stuck_note_secs: 0,
+            latching_notes: Vec::new(),
+            velocity_curve: VelocityCurve::default(),
+            leds: LedConfig::default(),
+            filter: FilterConfig::default(),
+            input: InputConfig::default(),
+            pots: vec![
+                PotConfig::new(HEADPHONE_MIX_CC),
+                PotConfig::new(HEADPHONE_VOLUME_CC),
+                PotConfig::new(MASTER_VOLUME_CC),
+            ],
+            controls: HashMap::new(),
+            faders: Vec::new(),
+            feedback: Vec::new(),
+            meters: Vec::new(),
+            clock: Vec::new(),
+            routes: Vec::new(),
+            output: OutputConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let config: Self = toml::from_str(&text)
+            .map_err(|e| format!("Failed to parse config {}: {}", path.display(), e))?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        for warning in config.warnings() {
+            session_log::event(format_args!(
+                "Warning: config {}: {}",
+                path.display(),
+                warning
+            ));
+        }
+        Ok(config)
+    }
+
+    // Parses and validates a config which hasn't been written to a file yet.
+    pub fn from_text(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Output channels set on individual controls, other than to the control's own default.
+    fn output_channels(&self) -> Vec<(String, u8)> {
+        let mut channels: Vec<_> = self
+            .button_channels
+            .iter()
+            .map(|(note, &channel)| (format!("button_channels.{}", note), channel))
+            .collect();
+        channels.extend(
+            self.buttons
+                .iter()
+                .map(|button| (format!("buttons note {}", button.note), button.out_channel)),
+        );
+        channels.extend(
+            self.pot_controls()
+                .iter()
+                .filter_map(|pot| Some((format!("pots CC {}", pot.cc), pot.channel?))),
+        );
+        channels.extend(
+            self.faders
+                .iter()
+                .map(|fader| (format!("faders CC {}", fader.cc), fader.channel())),
+        );
+        for (cc, control) in &self.controls {
+            let name = format!("controls.{}", cc);
+            match *control {
+                ControlConfig::Pot => (),
+                ControlConfig::Absolute { channel, .. } => {
+                    channels.extend(channel.map(|x| (name, x)))
+                }
+                ControlConfig::Button {
+                    up_channel,
+                    down_channel,
+                    ..
+                } => {
+                    channels.extend(
+                        up_channel
+                            .filter(|&x| x != FAKE_BUTTON_UP_CHANNEL)
+                            .map(|x| (name.clone(), x)),
+                    );
+                    channels.extend(
+                        down_channel
+                            .filter(|&x| x != FAKE_BUTTON_DOWN_CHANNEL)
+                            .map(|x| (name, x)),
+                    );
+                }
+            }
+        }
+        channels.sort();
+        channels
+    }
+
+    // The pots list with `controls` applied to it.
+    pub fn pot_controls(&self) -> Vec<PotConfig> {
+        let mut pots: Vec<_> = self
+            .pots
+            .iter()
+            .filter(|pot| {
+                self.controls
+                    .get(&pot.cc)
+                    .is_none_or(|control| *control == ControlConfig::Pot)
+            })
+            .cloned()
+            .collect();
+        let mut missing: Vec<_> = self
+            .controls
+            .iter()
+            .filter(|(cc, control)| {
+                **control == ControlConfig::Pot && !self.pots.iter().any(|pot| pot.cc == **cc)
+            })
+            .map(|(&cc, _)| PotConfig::new(cc))
+            .collect();
+        missing.sort_by_key(|pot| pot.cc);
+        pots.extend(missing);
+        pots
+    }
+
+    // When the config file was last modified, for noticing when it changes.
+    pub fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    // Problems which don't stop the config from working, but probably aren't intended: a note used
+    // by more than one control, whether as an input or an LED.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut notes = self.input_notes();
+        notes.extend(self.led_notes());
+
+        let mut warnings = Vec::new();
+        let mut note_map: Vec<_> = self.note_map.iter().collect();
+        note_map.sort();
+        for (from, to) in note_map {
+            if from != to && !self.note_map.contains_key(to) {
+                warnings.push(format!(
+                    "note_map treats note {:#04X} as {:#04X}, the same as the unmapped note {:#04X}",
+                    from, to, to
+                ));
+            }
+        }
+        for (control, channel) in self.output_channels() {
+            if let Some(reserved) = reserved_channel(channel) {
+                warnings.push(format!(
+                    "{} sends on channel {}, which is reserved for {}",
+                    control, channel, reserved
+                ));
+            }
+        }
+        for (i, (control, note)) in notes.iter().enumerate() {
+            if let Some((other, _)) = notes[..i]
+                .iter()
+                .find(|(other, other_note)| other_note == note && other != control)
+            {
+                warnings.push(format!(
+                    "note {:#04X} is used by both {} and {}",
+                    note, other, control
+                ));
+            }
+        }
+        warnings
+    }
+
+    // Every note a control takes as input, and what for. Most of the device's buttons are also
+    // their own LEDs.
+    pub fn input_notes(&self) -> Vec<(&'static str, u8)> {
+        let mut notes = vec![
+            ("filter deck 1", self.decks.0[0].filter_toggle_note),
+            ("filter deck 2", self.decks.0[1].filter_toggle_note),
+            ("filter deck 3", self.decks.0[2].filter_toggle_note),
+            ("tempo deck 1", self.decks.0[0].tempo_toggle_note),
+            ("tempo deck 2", self.decks.0[1].tempo_toggle_note),
+            ("tempo deck 3", self.decks.0[2].tempo_toggle_note),
+            ("pitch bend down", PB_DOWN_IN_NOTE),
+            ("pitch bend up", PB_UP_IN_NOTE),
+        ];
+        for (name, deck) in [
+            "tempo reset deck 1",
+            "tempo reset deck 2",
+            "tempo reset deck 3",
+        ]
+        .into_iter()
+        .zip(&self.decks.0)
+        {
+            notes.extend(deck.tempo_reset_note.map(|note| (name, note)));
+        }
+        if let Some(tempo_range) = &self.tempo_range {
+            notes.push(("tempo_range", tempo_range.cycle_note));
+        }
+        if let Some(tempo_fine) = &self.tempo_fine {
+            notes.push(("tempo_fine", tempo_fine.note));
+        }
+        if let Some(note) = self.tempo_link_note {
+            notes.push(("tempo_link_note", note));
+        }
+        if let Some(browse) = &self.browse {
+            notes.push(("browse", browse.push_note));
+        }
+        if let Some(key_control) = &self.key_control {
+            notes.push(("key_control", key_control.up_note));
+            notes.push(("key_control", key_control.down_note));
+            notes.push(("key_control", key_control.sync_note));
+        }
+        if let Some(talkover) = &self.talkover {
+            notes.push(("talkover", talkover.note));
+        }
+        notes.extend(
+            self.repeat_buttons
+                .iter()
+                .map(|button| ("repeat_buttons", button.note)),
+        );
+        if let Some(fx) = &self.fx {
+            notes.extend(fx.units.iter().map(|unit| ("fx unit", unit.select_note)));
+            notes.extend(fx.buttons.iter().map(|&note| ("fx button", note)));
+        }
+        for bank in &self.banks {
+            notes.extend(bank.select_notes.iter().map(|&note| ("banks", note)));
+        }
+        if let Some(pad_row) = &self.pad_row {
+            notes.extend(pad_row.notes.iter().map(|&note| ("pad_row", note)));
+        }
+        notes.extend(self.buttons.iter().map(|button| ("buttons", button.note)));
+        notes.extend(
+            self.pot_controls()
+                .iter()
+                .filter_map(|pot| Some(("pots reset_note", pot.reset_note?))),
+        );
+        for pads in &self.snapshot_pads {
+            notes.push(("snapshot_pads", pads.store_note));
+            notes.push(("snapshot_pads", pads.recall_note));
+        }
+        if let Some(note) = self.panic_note {
+            notes.push(("panic_note", note));
+        }
+        if let Some(note) = self.resync_note {
+            notes.push(("resync_note", note));
+        }
+        if let Some(shift) = &self.shift {
+            notes.push(("shift", shift.note));
+        }
+        notes
+    }
+
+    // The port each routed channel is sent to, keyed by channel.
+    pub fn routes(&self) -> HashMap<u8, String> {
+        let mut routes = HashMap::new();
+        for route in &self.routes {
+            for &channel in &route.channels {
+                routes.insert(channel, route.port.clone());
+            }
+        }
+        routes
+    }
+
+    pub fn release_policy(&self, note: u8) -> ReleasePolicy {
+        self.release_policies
+            .get(&note)
+            .copied()
+            .unwrap_or(self.release_policy)
+    }
+
+    // Notes sent to Rekordbox which are never held, as (channel, note): presses with no release to
+    // follow, e.g. fake button ticks and buttons whose releases are suppressed, and those in
+    // latching_notes.
+    pub fn unreleased_notes(&self) -> Vec<(u8, u8)> {
+        let mut notes: Vec<_> = self
+            .latching_notes
+            .iter()
+            .map(|x| (x.channel, x.note))
+            .collect();
+        let suppressed = |note| self.release_policy(note) == ReleasePolicy::Suppress;
+        for note in (0..=127).filter(|&note| suppressed(note)) {
+            let out_note = self.note_map.get(&note).copied().unwrap_or(note);
+            let channel = self.button_channels.get(&out_note).copied();
+            notes.push((channel.unwrap_or(DEVICE_CHANNEL), out_note));
+        }
+        if let Some(pad_row) = &self.pad_row {
+            for (note, out_note) in pad_row.notes.into_iter().zip(pad_row.out_notes) {
+                if suppressed(note) {
+                    notes.push((pad_row.channel, out_note));
+                }
+            }
+        }
+        for button in self.buttons.iter().filter(|x| suppressed(x.note)) {
+            notes.push((button.out_channel, button.out_note));
+        }
+        for button in self.repeat_buttons.iter().filter(|x| suppressed(x.note)) {
+            notes.push((button.channel(), button.out_note()));
+        }
+        if let Some(fx) = &self.fx {
+            for (i, _) in fx
+                .buttons
+                .iter()
+                .enumerate()
+                .filter(|(_, &x)| suppressed(x))
+            {
+                notes.extend(fx.units.iter().map(|unit| (unit.channel, i as u8)));
+            }
+        }
+        for (note, base) in [
+            (PB_DOWN_IN_NOTE, PB_DOWN_OUT_NOTE_BASE),
+            (PB_UP_IN_NOTE, PB_UP_OUT_NOTE_BASE),
+        ] {
+            if suppressed(note) {
+                notes.extend((0..DECK_COUNT as u8).map(|i| (TEMPO_ENCODER_CHANNEL, base + i)));
+            }
+        }
+
+        let mut ticks = |(up_channel, down_channel), note| {
+            notes.push((up_channel, note));
+            notes.push((down_channel, note));
+        };
+        let fake_buttons = (FAKE_BUTTON_UP_CHANNEL, FAKE_BUTTON_DOWN_CHANNEL);
+        for deck in &self.decks.0 {
+            ticks(fake_buttons, deck.loop_out_note);
+        }
+        for control in self.controls.values() {
+            if let ControlConfig::Button {
+                note,
+                up_channel,
+                down_channel,
+            } = *control
+            {
+                ticks(
+                    ControlConfig::button_channels(up_channel, down_channel),
+                    note,
+                );
+            }
+        }
+        if let Some(browse) = &self.browse {
+            ticks(fake_buttons, browse.cc);
+            notes.push((browse.channel, browse.load_note));
+            notes.push((browse.channel, browse.tap_note));
+        }
+        if let Some(key_control) = &self.key_control {
+            notes.extend(
+                [
+                    key_control.up_out_note,
+                    key_control.down_out_note,
+                    key_control.reset_out_note,
+                    key_control.sync_out_note,
+                ]
+                .map(|note| (key_control.channel, note)),
+            );
+        }
+        notes
+    }
+
+    // LEDs the device's own buttons don't share a note with.
+    pub fn led_notes(&self) -> Vec<(&'static str, u8)> {
+        let filter_leds = self.filter.led_notes(&self.decks);
+        vec![
+            ("filter deck 1", filter_leds[0]),
+            ("filter deck 2", filter_leds[1]),
+            ("filter deck 3", filter_leds[2]),
+        ]
+    }
+
+    // Every CC a control handles, and what for.
+    pub fn input_ccs(&self) -> Vec<(String, u8)> {
+        let mut ccs: Vec<_> = (0..DECK_COUNT)
+            .map(|i| (format!("loop deck {}", i + 1), self.decks.0[i].loop_cc))
+            .collect();
+        ccs.push(("filter".to_string(), FILTER_CC));
+        ccs.push(("tempo".to_string(), TEMPO_CC));
+        if let Some(makeup) = &self.filter.makeup {
+            ccs.extend(
+                (0..DECK_COUNT).map(|i| (format!("trim deck {}", i + 1), makeup.trim_ccs[i])),
+            );
+        }
+        if let Some(browse) = &self.browse {
+            ccs.push(("browse".to_string(), browse.cc));
+        }
+        ccs.extend(
+            self.pot_controls()
+                .iter()
+                .map(|pot| ("pots".to_string(), pot.cc)),
+        );
+        ccs.extend(
+            self.faders
+                .iter()
+                .map(|fader| ("faders".to_string(), fader.cc)),
+        );
+        let mut controls: Vec<_> = self
+            .controls
+            .iter()
+            .filter(|(_, control)| **control != ControlConfig::Pot)
+            .map(|(&cc, _)| ("controls".to_string(), cc))
+            .collect();
+        controls.sort();
+        ccs.extend(controls);
+        if let Some(fx) = &self.fx {
+            ccs.extend(fx.knobs.iter().map(|&cc| ("fx knob".to_string(), cc)));
+        }
+        for bank in &self.banks {
+            ccs.extend(bank.ccs.iter().map(|&cc| ("banks".to_string(), cc)));
+        }
+        ccs
+    }
+
+    // CCs from relative encoders, which send steps rather than a position.
+    pub fn relative_ccs(&self) -> Vec<u8> {
+        let mut ccs: Vec<_> = self.decks.0.iter().map(|deck| deck.loop_cc).collect();
+        ccs.extend(self.browse.map(|browse| browse.cc));
+        ccs.extend(
+            self.pot_controls()
+                .into_iter()
+                .filter(|pot| pot.mode == PotMode::Relative)
+                .map(|pot| pot.cc),
+        );
+        ccs.extend(
+            self.controls
+                .iter()
+                .filter_map(|(&cc, control)| match control {
+                    ControlConfig::Button { .. } => Some(cc),
+                    _ => None,
+                }),
+        );
+        ccs
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.fake_buttons.validate()?;
+        self.filter.validate()?;
+        for (note, channel) in &self.button_channels {
+            if *note > 127 || *channel > 15 {
+                return Err(format!("button_channels.{} is out of range", note).into());
+            }
+        }
+        if let Some(note) = self.release_policies.keys().find(|&&note| note > 127) {
+            return Err(format!("release_policies.{} is out of range", note).into());
+        }
+        let mut routed = HashMap::new();
+        for route in &self.routes {
+            for &channel in &route.channels {
+                if channel > 15 {
+                    return Err(
+                        format!("The route to {} has a channel out of range", route.port).into(),
+                    );
+                }
+                match routed.insert(channel, &route.port) {
+                    Some(other) if other != &route.port => {
+                        return Err(format!(
+                            "Channel {} is routed to both {} and {}",
+                            channel, other, route.port
+                        )
+                        .into())
+                    }
+                    _ => (),
+                }
+            }
+        }
+        if self
+            .latching_notes
+            .iter()
+            .any(|x| x.note > 127 || x.channel > 15)
+        {
+            return Err(
+                "latching_notes must have notes within 0-127 and channels within 0-15".into(),
+            );
+        }
+        if let Some(browse) = &self.browse {
+            browse.validate()?;
+        }
+        for button in &self.repeat_buttons {
+            button.validate()?;
+        }
+        if let Some(key_control) = &self.key_control {
+            key_control.validate()?;
+        }
+        if let Some(talkover) = &self.talkover {
+            talkover.validate()?;
+            if !self.pot_controls().iter().any(|pot| pot.cc == talkover.pot) {
+                return Err(
+                    format!("talkover.pot: CC {} isn't one of the pots", talkover.pot).into(),
+                );
+            }
+        }
+        if let Some(tempo_range) = &self.tempo_range {
+            tempo_range.validate()?;
+        }
+        if let Some(tempo_fine) = &self.tempo_fine {
+            tempo_fine.validate()?;
+        }
+        if !(1..=DECK_COUNT).contains(&self.initial_tempo_deck) {
+            return Err(format!("initial_tempo_deck must be within 1-{}", DECK_COUNT).into());
+        }
+        if let Some(fx) = &self.fx {
+            fx.validate()?;
+        }
+        for bank in &self.banks {
+            bank.validate()?;
+        }
+        if let Some(pad_row) = &self.pad_row {
+            pad_row.validate()?;
+        }
+        self.decks.validate()?;
+        if self.filter.led_notes(&self.decks).iter().any(|&x| x > 127) {
+            return Err("filter.led_notes must be within 0-127".into());
+        }
+        for button in &self.buttons {
+            button.validate()?;
+        }
+        for pads in &self.snapshot_pads {
+            pads.validate()?;
+        }
+        if self.panic_note.is_some_and(|note| note > 127) {
+            return Err("panic_note must be within 0-127".into());
+        }
+        if self.resync_note.is_some_and(|note| note > 127) {
+            return Err("resync_note must be within 0-127".into());
+        }
+        if self.tempo_link_note.is_some_and(|note| note > 127) {
+            return Err("tempo_link_note must be within 0-127".into());
+        }
+        let mut mapped_to = HashMap::new();
+        for (&from, &to) in &self.note_map {
+            if from > 127 || to > 127 {
+                return Err(format!("note_map.{} must map to a note within 0-127", from).into());
+            }
+            if let Some(other) = mapped_to.insert(to, from) {
+                return Err(format!(
+                    "note_map maps both notes {} and {} to {}, so they couldn't be told apart",
+                    other.min(from),
+                    other.max(from),
+                    to
+                )
+                .into());
+            }
+        }
+        if let Some(shift) = &self.shift {
+            shift.validate()?;
+        }
+        // Stretching a relative encoder's values would change which way it turns.
+        for cc in self.relative_ccs() {
+            if self.input.scaling.contains_key(&cc) {
+                return Err(format!("input.scaling.{} is a relative encoder", cc).into());
+            }
+        }
+        for (channel, aftertouch) in &self.aftertouch {
+            aftertouch.validate(*channel)?;
+        }
+        for (channel, pitch_bend) in &self.pitch_bend {
+            pitch_bend.validate(*channel)?;
+        }
+        for (program, program_change) in &self.program_changes {
+            program_change.validate(*program)?;
+        }
+        for rule in &self.feedback {
+            rule.validate()?;
+        }
+        for meter in &self.meters {
+            meter.validate()?;
+        }
+        if let Some(probe) = &self.resync_probe {
+            probe.validate()?;
+        }
+        for rule in &self.clock {
+            rule.validate()?;
+        }
+        for fader in &self.faders {
+            fader.validate()?;
+        }
+        self.output.validate()?;
+        self.velocity_curve.validate()?;
+        self.input.validate()?;
+
+        let mut ccs: Vec<_> = self.decks.0.iter().map(|deck| deck.loop_cc).collect();
+        ccs.extend([FILTER_CC, TEMPO_CC]);
+        if let Some(makeup) = &self.filter.makeup {
+            for (i, cc) in makeup.trim_ccs.into_iter().enumerate() {
+                if ccs.contains(&cc) {
+                    return Err(format!(
+                        "filter.makeup: CC {} is already mapped to another control",
+                        cc
+                    )
+                    .into());
+                }
+                if makeup.trim_ccs[..i].contains(&cc) {
+                    return Err(format!("filter.makeup uses CC {} twice", cc).into());
+                }
+            }
+            ccs.extend(makeup.trim_ccs);
+        }
+        if let Some(browse) = &self.browse {
+            if ccs.contains(&browse.cc) {
+                return Err(format!(
+                    "browse: CC {} is already mapped to another control",
+                    browse.cc
+                )
+                .into());
+            }
+            ccs.push(browse.cc);
+        }
+        for (cc, control) in &self.controls {
+            control.validate(*cc)?;
+            let listed = self.pots.iter().find(|pot| pot.cc == *cc);
+            if *control == ControlConfig::Pot
+                && listed.is_some_and(|pot| pot.mode != PotMode::Relative)
+            {
+                return Err(format!(
+                    "controls.{} is declared a pot, but pots lists it as absolute",
+                    cc
+                )
+                .into());
+            }
+        }
+        for pot in &self.pot_controls() {
+            if ccs.contains(&pot.cc) {
+                return Err(
+                    format!("pots: CC {} is already mapped to another control", pot.cc).into(),
+                );
+            }
+            if pot.cc > 127 || pot.out_cc() > 127 || pot.default > 127 {
+                return Err(format!("pots: CC {} has a value outside 0-127", pot.cc).into());
+            }
+            if pot.channel() > 15 {
+                return Err(format!("pots: CC {} channel is not within 0-15", pot.cc).into());
+            }
+            let reset_notes = [pot.reset_note, pot.reset_led()];
+            if pot.reset_value() > 127 || reset_notes.into_iter().flatten().any(|x| x > 127) {
+                return Err(format!(
+                    "pots: CC {} has a reset note, LED or value outside 0-127",
+                    pot.cc
+                )
+                .into());
+            }
+            if matches!(pot.at_limit, PotLimit::Note(note) if note > 127) {
+                return Err(
+                    format!("pots: CC {} has an at_limit note outside 0-127", pot.cc).into(),
+                );
+            }
+            if pot
+                .feedback()
+                .is_some_and(|(channel, cc)| channel > 15 || cc > 127)
+            {
+                return Err(format!(
+                    "pots: CC {} has a feedback channel or CC out of range",
+                    pot.cc
+                )
+                .into());
+            }
+            ccs.push(pot.cc);
+        }
+        for (&cc, control) in &self.controls {
+            if *control == ControlConfig::Pot {
+                continue;
+            }
+            if ccs.contains(&cc) {
+                return Err(
+                    format!("controls: CC {} is already mapped to another control", cc).into(),
+                );
+            }
+            ccs.push(cc);
+        }
+        for fader in &self.faders {
+            if ccs.contains(&fader.cc) {
+                return Err(format!(
+                    "faders: CC {} is already mapped to another control",
+                    fader.cc
+                )
+                .into());
+            }
+            ccs.push(fader.cc);
+        }
+        if let Some(cc) = self
+            .fx
+            .iter()
+            .flat_map(|fx| &fx.knobs)
+            .find(|cc| ccs.contains(cc))
+        {
+            return Err(format!("fx.knobs: CC {} is already mapped to another control", cc).into());
+        }
+        ccs.extend(self.fx.iter().flat_map(|fx| &fx.knobs));
+        for bank in &self.banks {
+            if let Some(cc) = bank.ccs.iter().find(|cc| ccs.contains(cc)) {
+                return Err(
+                    format!("banks: CC {} is already mapped to another control", cc).into(),
+                );
+            }
+            ccs.extend(&bank.ccs);
+        }
+        Ok(())
+    }
+}
+
+// How a button's release is sent to Rekordbox. Presses are always sent as a NOTE_ON.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReleasePolicy {
+    // The same NOTE_ON as a press, with the release's own velocity, which is what Rekordbox's
+    // buttons expect.
+    #[default]
+    Mirror,
+    // A NOTE_OFF with velocity 0.
+    NoteOff,
+    // A NOTE_ON with velocity 0, whatever the release's velocity was.
+    #[serde(rename = "velocity-0")]
+    Velocity0,
+    // Nothing, for functions which act on every message they're sent.
+    Suppress,
+}
+
+// A note sent to Rekordbox, and the channel it's sent on.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputNote {
+    pub channel: u8,
+    pub note: u8,
+}
+
+// Per-control output throttling. Controls without a policy send every change immediately.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThrottleConfig {
+    pub filter: Option<Deadband>,
+    pub pots: Option<Deadband>,
+}
+
+// Changes of at most `threshold` from the last sent value are treated as jitter and held back,
+// until the control has been idle for `idle_ms`, at which point the settled value is sent.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Deadband {
+    pub threshold: u8,
+    pub idle_ms: u64,
+}
+
+impl Deadband {
+    pub fn idle(&self) -> Duration {
+        Duration::from_millis(self.idle_ms)
+    }
+}
+
+// Which data values from a relative encoder count as a clockwise (up) or counter-clockwise (down)
+// tick when emulating buttons. Values in neither range are ignored.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FakeButtonConfig {
+    pub up: ValueRange,
+    pub down: ValueRange,
+}
+
+impl FakeButtonConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, range) in [("up", self.up), ("down", self.down)] {
+            if range.min > range.max || range.max > 127 {
+                return Err(format!(
+                    "fake_buttons.{} range {:?} is not within 0-127",
+                    name, range
+                )
+                .into());
+            }
+        }
+        if self.up.overlaps(self.down) {
+            return Err("fake_buttons.up and fake_buttons.down ranges overlap".into());
+        }
+        Ok(())
+    }
+}
+
+// A library browse encoder with a push button. Turning it ticks fake buttons on the encoder's CC,
+// and the push is sent as `load_note` once held for `hold_ms`, or `tap_note` if released sooner.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BrowseConfig {
+    pub cc: u8,
+    pub push_note: u8,
+    pub load_note: u8,
+    pub tap_note: u8,
+    pub channel: u8,
+    #[serde(default = "BrowseConfig::default_hold_ms")]
+    pub hold_ms: u64,
+}
+
+impl BrowseConfig {
+    fn default_hold_ms() -> u64 {
+        250
+    }
+
+    fn validate(&self) -> Result<()> {
+        if [self.cc, self.push_note, self.load_note, self.tap_note]
+            .iter()
+            .any(|&x| x > 127)
+        {
+            return Err("browse CC and notes must be within 0-127".into());
+        }
+        if self.channel > 15 {
+            return Err("browse.channel must be within 0-15".into());
+        }
+        Ok(())
+    }
+}
+
+// Pads which shift the key a semitone at a time, and sync it. Tapping a shift pad sends
+// `up_out_note` or `down_out_note`, while holding either for `hold_ms` sends `reset_out_note`
+// instead, to return to the track's original key.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyControlConfig {
+    pub up_note: u8,
+    pub down_note: u8,
+    pub sync_note: u8,
+    // Output channel for every output note, which Rekordbox's load feedback also arrives on.
+    pub channel: u8,
+    pub up_out_note: u8,
+    pub down_out_note: u8,
+    pub reset_out_note: u8,
+    pub sync_out_note: u8,
+    #[serde(default = "KeyControlConfig::default_hold_ms")]
+    pub hold_ms: u64,
+    // Note Rekordbox sends when a track is loaded, which is back in its original key.
+    pub load_feedback_note: Option<u8>,
+}
+
+impl KeyControlConfig {
+    fn default_hold_ms() -> u64 {
+        500
+    }
+
+    fn validate(&self) -> Result<()> {
+        let notes = [
+            self.up_note,
+            self.down_note,
+            self.sync_note,
+            self.up_out_note,
+            self.down_out_note,
+            self.reset_out_note,
+            self.sync_out_note,
+        ];
+        if notes
+            .iter()
+            .chain(&self.load_feedback_note)
+            .any(|&x| x > 127)
+        {
+            return Err("key_control notes must be within 0-127".into());
+        }
+        if self.channel > 15 {
+            return Err("key_control.channel must be within 0-15".into());
+        }
+        Ok(())
+    }
+}
+
+// A button which toggles the mic in Rekordbox, and ducks a pot while it's on.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TalkoverConfig {
+    // Input note of the button, which is also its LED.
+    pub note: u8,
+    // The note Rekordbox's mic button is mapped to.
+    pub out_note: u8,
+    // Defaults to DEVICE_CHANNEL.
+    #[serde(default)]
+    channel: Option<u8>,
+    // Input CC of the pot to duck, which must be one of the pots.
+    #[serde(default = "TalkoverConfig::default_pot")]
+    pub pot: u8,
+    // How far the pot is taken down while the mic is on.
+    #[serde(default = "TalkoverConfig::default_duck")]
+    pub duck: u8,
+    // How long the duck takes to ramp all the way in or out.
+    #[serde(default = "TalkoverConfig::default_ramp_ms")]
+    pub ramp_ms: u64,
+}
+
+impl TalkoverConfig {
+    fn default_pot() -> u8 {
+        MASTER_VOLUME_CC
+    }
+
+    fn default_duck() -> u8 {
+        32
+    }
+
+    fn default_ramp_ms() -> u64 {
+        200
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel.unwrap_or(DEVICE_CHANNEL)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if [self.note, self.out_note, self.pot, self.duck]
+            .iter()
+            .any(|&x| x > 127)
+        {
+            return Err("talkover notes, pot and duck must be within 0-127".into());
+        }
+        if self.channel() > 15 {
+            return Err("talkover.channel must be within 0-15".into());
+        }
+        Ok(())
+    }
+}
+
+// A button sent as `out_note`, and sent again every `interval_ms` once held for `delay_ms`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatConfig {
+    pub note: u8,
+    // Defaults to the input note.
+    pub out_note: Option<u8>,
+    // Defaults to DEVICE_CHANNEL.
+    channel: Option<u8>,
+    #[serde(default = "RepeatConfig::default_interval_ms")]
+    pub interval_ms: u64,
+    // Defaults to `interval_ms`.
+    pub delay_ms: Option<u64>,
+}
+
+impl RepeatConfig {
+    fn default_interval_ms() -> u64 {
+        250
+    }
+
+    pub fn out_note(&self) -> u8 {
+        self.out_note.unwrap_or(self.note)
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel.unwrap_or(DEVICE_CHANNEL)
+    }
+
+    pub fn delay_ms(&self) -> u64 {
+        self.delay_ms.unwrap_or(self.interval_ms)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.note > 127 || self.out_note() > 127 || self.channel() > 15 {
+            return Err(format!(
+                "repeat_buttons: note {} has a note or channel out of range",
+                self.note
+            )
+            .into());
+        }
+        if self.interval_ms == 0 {
+            return Err(format!(
+                "repeat_buttons: note {} must have an interval_ms above 0",
+                self.note
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for FakeButtonConfig {
+    fn default() -> Self {
+        Self {
+            up: ValueRange { min: 1, max: 1 },
+            down: ValueRange { min: 127, max: 127 },
+        }
+    }
+}
+
+// An inclusive range of data byte values, written as `[min, max]`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(from = "[u8; 2]")]
+pub struct ValueRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl ValueRange {
+    pub fn contains(&self, value: u8) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+
+    // Maps this range onto 0-127, clamping values outside it.
+    pub fn stretch(&self, value: u8) -> u8 {
+        let value = value.clamp(self.min, self.max) - self.min;
+        let span = (self.max - self.min).max(1) as u32;
+        ((value as u32 * 127 + span / 2) / span) as u8
+    }
+
+    fn overlaps(&self, other: Self) -> bool {
+        self.min <= other.max && other.min <= self.max
+    }
+}
+
+impl From<[u8; 2]> for ValueRange {
+    fn from([min, max]: [u8; 2]) -> Self {
+        Self { min, max }
+    }
+}
+
+// Settings which only apply with `--target mixxx`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MixxxConfig {
+    pub relative_mode: RelativeMode,
+}
+
+// How relative encoder movement is encoded in the CCs passed through to Mixxx.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RelativeMode {
+    // 1 = +1, 127 = -1. This is what the device sends, so messages pass through unchanged.
+    #[default]
+    TwosComplement,
+    // 65 = +1, 63 = -1.
+    Offset64,
+    // 1 = +1, 65 = -1.
+    SignBit,
+}
+
+impl RelativeMode {
+    // Re-encodes a two's complement relative value from the device.
+    pub fn encode(self, data: u8) -> u8 {
+        let delta = if data < 64 {
+            data as i8
+        } else {
+            (data as i16 - 128) as i8
+        };
+        match self {
+            RelativeMode::TwosComplement => data,
+            RelativeMode::Offset64 => (64 + delta).clamp(0, 127) as u8,
+            RelativeMode::SignBit if delta < 0 => 64 | delta.unsigned_abs().min(63),
+            RelativeMode::SignBit => delta as u8,
+        }
+    }
+}
+
+// Lets a button switch the tempo fader into a fine mode, in which its movement is scaled down.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TempoFineConfig {
+    // Input note of the button, which is also its LED.
+    pub note: u8,
+    // How much of the fader's movement is applied in fine mode.
+    #[serde(default = "TempoFineConfig::default_factor")]
+    pub factor: f32,
+    // Only in fine mode while the button is held, like a shift button, rather than toggling.
+    #[serde(default)]
+    pub momentary: bool,
+}
+
+impl TempoFineConfig {
+    fn default_factor() -> f32 {
+        0.25
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.note > 127 {
+            return Err("tempo_fine.note must be within 0-127".into());
+        }
+        if !(self.factor > 0.0 && self.factor <= 1.0) {
+            return Err("tempo_fine.factor must be greater than 0, and at most 1".into());
+        }
+        Ok(())
+    }
+}
+
+// Lets a button cycle the selected deck's tempo range in Rekordbox, while scaling the tempo fader
+// so the same fader travel always corresponds to the same tempo change.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TempoRangeConfig {
+    // Input note which cycles the selected deck's range.
+    pub cycle_note: u8,
+    // The ranges Rekordbox cycles through, in percent, in order.
+    #[serde(default = "TempoRangeConfig::default_ranges")]
+    pub ranges: Vec<f32>,
+    // Each deck's range at startup, which must match Rekordbox.
+    pub initial: [f32; 3],
+    // The tempo change from moving the fader from its center to either end, in percent.
+    pub fader_range: f32,
+}
+
+impl TempoRangeConfig {
+    fn default_ranges() -> Vec<f32> {
+        vec![6.0, 10.0, 16.0, 100.0]
+    }
+
+    // The index into `ranges` of each deck's initial range.
+    pub fn initial_indices(&self) -> [usize; 3] {
+        self.initial
+            .map(|range| self.ranges.iter().position(|&x| x == range).unwrap_or(0))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.cycle_note > 127 {
+            return Err("tempo_range.cycle_note must be within 0-127".into());
+        }
+        if self.ranges.is_empty() || self.ranges.iter().any(|&x| x <= 0.0) {
+            return Err("tempo_range.ranges must be a non-empty list of positive numbers".into());
+        }
+        if let Some(range) = self.initial.iter().find(|x| !self.ranges.contains(x)) {
+            return Err(format!(
+                "tempo_range.initial range {} is not in tempo_range.ranges",
+                range
+            )
+            .into());
+        }
+        if self.fader_range <= 0.0 {
+            return Err("tempo_range.fader_range must be positive".into());
+        }
+        Ok(())
+    }
+}
+
+// Applied to the velocity of buttons before they're forwarded, to soften or harden pad response.
+// A velocity of 0 (a release) is always left alone.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    // Exponents above 1 make soft hits softer, below 1 make them harder.
+    Exponential {
+        exponent: f32,
+    },
+    Fixed {
+        velocity: u8,
+    },
+    // Output velocity for each of the 128 input velocities.
+    Table {
+        values: Vec<u8>,
+    },
+}
+
+impl VelocityCurve {
+    pub fn apply(&self, velocity: u8) -> u8 {
+        if velocity == 0 {
+            return 0;
+        }
+
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Exponential { exponent } => {
+                let curved = 127.0 * (velocity as f32 / 127.0).powf(*exponent);
+                // Never turn a press into a release.
+                curved.round().clamp(1.0, 127.0) as u8
+            }
+            VelocityCurve::Fixed { velocity } => *velocity,
+            VelocityCurve::Table { values } => values[velocity.min(127) as usize],
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            VelocityCurve::Linear => Ok(()),
+            VelocityCurve::Exponential { exponent } if *exponent > 0.0 => Ok(()),
+            VelocityCurve::Exponential { .. } => {
+                Err("velocity_curve.exponent must be positive".into())
+            }
+            VelocityCurve::Fixed { velocity } if (1..=127).contains(velocity) => Ok(()),
+            VelocityCurve::Fixed { .. } => {
+                Err("velocity_curve.velocity must be within 1-127".into())
+            }
+            // The first entry is never used, as a release is left alone, but a press must never be
+            // turned into one.
+            VelocityCurve::Table { values }
+                if values.len() == 128
+                    && values[0] <= 127
+                    && values[1..].iter().all(|x| (1..=127).contains(x)) =>
+            {
+                Ok(())
+            }
+            VelocityCurve::Table { .. } => {
+                Err("velocity_curve.values must be 128 values, within 1-127 after the first".into())
+            }
+        }
+    }
+}
+
+// The colors of the LEDs party-saver drives itself, for each state they can be in.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LedConfig {
+    pub filter_enabled: LedColor,
+    pub filter_disabled: LedColor,
+    pub tempo_deck_selected: LedColor,
+    pub tempo_deck_unselected: LedColor,
+    pub pitch_bend_pressed: LedColor,
+    pub pitch_bend_released: LedColor,
+    pub fx_unit_selected: LedColor,
+    pub fx_unit_unselected: LedColor,
+    pub pad_held: LedColor,
+    pub pad_released: LedColor,
+    pub button_active: LedColor,
+    pub button_inactive: LedColor,
+    pub bank_deck_focused: LedColor,
+    pub bank_deck_unfocused: LedColor,
+    pub tempo_fine_on: LedColor,
+    pub tempo_fine_off: LedColor,
+    pub tempo_link_on: LedColor,
+    pub tempo_link_off: LedColor,
+    pub pot_reset: LedColor,
+    pub snapshot_stored: LedColor,
+    pub snapshot_empty: LedColor,
+    pub key_shifted: LedColor,
+    pub key_unshifted: LedColor,
+    // Each deck's own color, used in place of filter_enabled and tempo_deck_selected when set.
+    pub deck_colors: Option<[LedColor; DECK_COUNT]>,
+}
+
+impl Default for LedConfig {
+    fn default() -> Self {
+        Self {
+            filter_enabled: LedColor::Velocity(127),
+            filter_disabled: LedColor::Off,
+            tempo_deck_selected: LedColor::Velocity(127),
+            tempo_deck_unselected: LedColor::Off,
+            pitch_bend_pressed: LedColor::Velocity(127),
+            pitch_bend_released: LedColor::Off,
+            fx_unit_selected: LedColor::Velocity(127),
+            fx_unit_unselected: LedColor::Off,
+            pad_held: LedColor::Velocity(127),
+            pad_released: LedColor::Off,
+            button_active: LedColor::Velocity(127),
+            button_inactive: LedColor::Off,
+            bank_deck_focused: LedColor::Velocity(127),
+            bank_deck_unfocused: LedColor::Off,
+            tempo_fine_on: LedColor::Velocity(127),
+            tempo_fine_off: LedColor::Off,
+            tempo_link_on: LedColor::Velocity(127),
+            tempo_link_off: LedColor::Off,
+            pot_reset: LedColor::Velocity(127),
+            snapshot_stored: LedColor::Velocity(127),
+            snapshot_empty: LedColor::Off,
+            key_shifted: LedColor::Velocity(127),
+            key_unshifted: LedColor::Off,
+            deck_colors: None,
+        }
+    }
+}
+
+impl LedConfig {
+    // The color for deck `deck`'s LED when it's on, which is `color` unless decks have their own.
+    pub fn deck_color(&self, deck: usize, color: LedColor) -> LedColor {
+        self.deck_colors.map_or(color, |colors| colors[deck])
+    }
+}
+
+// An FX section whose knobs and buttons are shared between several FX units, one of which is
+// selected at a time. Knob N is sent as CC N and button N as note N, on the selected unit's channel.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FxConfig {
+    // Input CCs of the knobs.
+    pub knobs: Vec<u8>,
+    // Input notes of the on/off buttons.
+    pub buttons: Vec<u8>,
+    pub units: Vec<FxUnitConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FxUnitConfig {
+    // Input note which selects this unit, and whose LED shows it's selected.
+    pub select_note: u8,
+    // Output channel for this unit's knobs and buttons.
+    pub channel: u8,
+}
+
+impl FxConfig {
+    fn validate(&self) -> Result<()> {
+        if self.units.is_empty() {
+            return Err("fx.units must not be empty".into());
+        }
+        if self.knobs.len() > 128 || self.buttons.len() > 128 {
+            return Err("fx has more knobs or buttons than there are CCs or notes".into());
+        }
+        let codes = self.knobs.iter().chain(&self.buttons);
+        if codes
+            .chain(self.units.iter().map(|unit| &unit.select_note))
+            .any(|&x| x > 127)
+        {
+            return Err("fx knobs, buttons and select notes must be within 0-127".into());
+        }
+        if self.units.iter().any(|unit| unit.channel > 15) {
+            return Err("fx.units channels must be within 0-15".into());
+        }
+        Ok(())
+    }
+}
+
+// A bank of knobs shared between the decks, sent as a different set of CCs for each deck depending
+// on which is focused.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BankConfig {
+    // Input CCs of the knobs.
+    pub ccs: Vec<u8>,
+    // Input note which focuses each deck, and whose LED shows it's focused.
+    pub select_notes: [u8; DECK_COUNT],
+    // The CC each knob is sent as, by deck and then knob.
+    pub out_ccs: [Vec<u8>; DECK_COUNT],
+    // Defaults to DEVICE_CHANNEL.
+    #[serde(default)]
+    channel: Option<u8>,
+}
+
+impl BankConfig {
+    pub fn channel(&self) -> u8 {
+        self.channel.unwrap_or(DEVICE_CHANNEL)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.out_ccs.iter().any(|x| x.len() != self.ccs.len()) {
+            return Err("banks.out_ccs must list a CC for each knob, for each deck".into());
+        }
+        let codes = self.ccs.iter().chain(self.out_ccs.iter().flatten());
+        if codes.chain(&self.select_notes).any(|&x| x > 127) {
+            return Err("banks CCs and select notes must be within 0-127".into());
+        }
+        if self.channel() > 15 {
+            return Err("banks.channel must be within 0-15".into());
+        }
+        Ok(())
+    }
+}
+
+// Four momentary pads, each held in Rekordbox as a note for as long as it's held on the device.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PadRowConfig {
+    // Input notes of the pads, which are also their LEDs.
+    pub notes: [u8; 4],
+    // The note each pad is sent as, on `channel`.
+    pub out_notes: [u8; 4],
+    pub channel: u8,
+}
+
+impl PadRowConfig {
+    fn validate(&self) -> Result<()> {
+        if self.notes.iter().chain(&self.out_notes).any(|&x| x > 127) {
+            return Err("pad_row notes must be within 0-127".into());
+        }
+        if self.channel > 15 {
+            return Err("pad_row.channel must be within 0-15".into());
+        }
+        Ok(())
+    }
+}
+
+// A pair of pads for one snapshot slot. The recall pad is lit once something has been stored.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotPadConfig {
+    pub store_note: u8,
+    pub recall_note: u8,
+}
+
+impl SnapshotPadConfig {
+    fn validate(&self) -> Result<()> {
+        if self.store_note > 127 || self.recall_note > 127 {
+            return Err(format!(
+                "snapshot_pads: store note {} or recall note {} is outside 0-127",
+                self.store_note, self.recall_note
+            )
+            .into());
+        }
+        if self.store_note == self.recall_note {
+            return Err(format!(
+                "snapshot_pads: note {} can't both store and recall",
+                self.store_note
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// A button sent to Rekordbox as a note, whose LED is driven by the note Rekordbox sends back.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonConfig {
+    // Input note of the button, which is also its LED.
+    pub note: u8,
+    pub out_note: u8,
+    pub out_channel: u8,
+    // Where Rekordbox sends the button's state back. Defaults to the output note and channel.
+    pub feedback_note: Option<u8>,
+    pub feedback_channel: Option<u8>,
+}
+
+impl ButtonConfig {
+    pub fn feedback_note(&self) -> u8 {
+        self.feedback_note.unwrap_or(self.out_note)
+    }
+
+    pub fn feedback_channel(&self) -> u8 {
+        self.feedback_channel.unwrap_or(self.out_channel)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if [self.note, self.out_note, self.feedback_note()]
+            .iter()
+            .any(|&x| x > 127)
+        {
+            return Err("buttons notes must be within 0-127".into());
+        }
+        if self.out_channel > 15 || self.feedback_channel() > 15 {
+            return Err("buttons channels must be within 0-15".into());
+        }
+        Ok(())
+    }
+}
+
+// Sends a channel's pressure as a CC.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AftertouchConfig {
+    pub cc: u8,
+    // Output channel. Defaults to the input channel.
+    pub channel: Option<u8>,
+}
+
+impl AftertouchConfig {
+    fn validate(&self, channel: u8) -> Result<()> {
+        if channel > 15 || self.channel.is_some_and(|x| x > 15) || self.cc > 127 {
+            return Err(format!("aftertouch.{} has a channel or CC out of range", channel).into());
+        }
+        Ok(())
+    }
+}
+
+// While `note` is held, physical controls are treated as other controls, before anything else sees
+// them. This lets a Shift button give the same controls a second set of functions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShiftConfig {
+    pub note: u8,
+    // What each control is treated as while shifted, keyed by its physical CC or note. Controls
+    // which aren't listed act as they normally would.
+    #[serde(default)]
+    pub ccs: HashMap<u8, u8>,
+    #[serde(default)]
+    pub notes: HashMap<u8, u8>,
+}
+
+impl ShiftConfig {
+    fn validate(&self) -> Result<()> {
+        let codes = self.ccs.iter().chain(&self.notes);
+        if self.note > 127 || codes.into_iter().any(|(&from, &to)| from > 127 || to > 127) {
+            return Err("shift has a note or CC out of range".into());
+        }
+        if self.notes.contains_key(&self.note) {
+            return Err("shift.notes can't remap the shift note itself".into());
+        }
+        Ok(())
+    }
+}
+
+// Sends a program change, e.g. from a foot controller, as a press and release of `note`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProgramChangeConfig {
+    pub note: u8,
+    // Defaults to DEVICE_CHANNEL.
+    channel: Option<u8>,
+    // Only program changes on this channel are mapped. Defaults to any channel.
+    pub in_channel: Option<u8>,
+    // Adds the selected tempo deck's index to `note`, so it acts on whichever deck is selected.
+    #[serde(default)]
+    pub follow_deck: bool,
+}
+
+impl ProgramChangeConfig {
+    pub fn channel(&self) -> u8 {
+        self.channel.unwrap_or(DEVICE_CHANNEL)
+    }
+
+    fn validate(&self, program: u8) -> Result<()> {
+        let last_note = if self.follow_deck {
+            self.note as usize + DECK_COUNT - 1
+        } else {
+            self.note as usize
+        };
+        if program > 127
+            || last_note > 127
+            || self.channel() > 15
+            || self.in_channel.is_some_and(|x| x > 15)
+        {
+            return Err(format!(
+                "program_changes.{} has a note or channel out of range",
+                program
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum PitchBendConfig {
+    // Forwarded unchanged, apart from the channel.
+    Channel { channel: u8 },
+    // Sent as a 14-bit CC pair: the most significant 7 bits on `cc`, and the rest on `cc + 32`. The
+    // output channel defaults to the input channel.
+    Cc { cc: u8, channel: Option<u8> },
+}
+
+impl PitchBendConfig {
+    fn validate(&self, channel: u8) -> Result<()> {
+        let valid = channel <= 15
+            && match *self {
+                PitchBendConfig::Channel { channel } => channel <= 15,
+                PitchBendConfig::Cc { cc, channel } => cc < 32 && channel.is_none_or(|x| x <= 15),
+            };
+        if !valid {
+            return Err(format!(
+                "pitch_bend.{} has a channel out of range, or a CC outside 0-31",
+                channel
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    // Engage a deck's filter only while its toggle is held, rather than latching it on each press.
+    pub momentary: bool,
+    // Also forward the toggles' NOTE_OFF messages to Rekordbox as-is, for mapping them there too.
+    pub forward_release: bool,
+    // The LED each deck's toggle lights, by deck. Defaults to 0x48 above each toggle's note.
+    pub led_notes: Option<[u8; DECK_COUNT]>,
+    // The minimum time between updates to each deck's LED, for devices whose LED firmware can't
+    // keep up with fast toggling. Only the latest state is sent. 0 disables this.
+    pub led_interval_ms: u64,
+    // How long engaging a deck's filter takes to sweep from neutral to the filter's value, and
+    // disengaging it to sweep back, to avoid clicks. 0 jumps straight there.
+    pub ramp_ms: u64,
+    // The order the decks are sent in when the filter changes, by deck number. Defaults to 1, 2, 3.
+    pub order: Option<[usize; DECK_COUNT]>,
+    pub send: FilterSend,
+    pub makeup: Option<FilterMakeupConfig>,
+}
+
+// Which decks are sent when the filter changes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterSend {
+    // Every deck, whether or not its value changed.
+    #[default]
+    All,
+    // Only the decks whose value changed since they were last sent.
+    Changed,
+}
+
+impl FilterConfig {
+    fn validate(&self) -> Result<()> {
+        if let Some(order) = self.order {
+            let mut sorted = order;
+            sorted.sort();
+            if sorted != [1, 2, 3] {
+                return Err("filter.order must list each of decks 1-3 once".into());
+            }
+        }
+        if let Some(makeup) = &self.makeup {
+            makeup.validate()?;
+        }
+        Ok(())
+    }
+
+    // The decks' indices, in the order they're sent.
+    pub fn order(&self) -> [usize; DECK_COUNT] {
+        self.order
+            .map_or([0, 1, 2], |order| order.map(|deck| deck - 1))
+    }
+
+    pub fn led_notes(&self, decks: &Decks) -> [u8; DECK_COUNT] {
+        self.led_notes.unwrap_or(
+            decks
+                .0
+                .map(|deck| deck.filter_toggle_note.saturating_add(0x48)),
+        )
+    }
+}
+
+// Boosts each deck's trim while its filter is engaged away from the middle, to make up for the level
+// the filter takes away. The trims are taken over from being forwarded as-is, so that the boost is
+// added on top of wherever each trim is, and taken off again without losing where it was moved to.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterMakeupConfig {
+    // Input CC of each deck's trim, by deck. Each is sent to Rekordbox on the same CC.
+    pub trim_ccs: [u8; DECK_COUNT],
+    // Defaults to DEVICE_CHANNEL.
+    #[serde(default)]
+    channel: Option<u8>,
+    // The boost with the filter turned all the way either way, in trim steps.
+    pub max_boost: u8,
+    // How the boost grows as the filter is turned away from the middle.
+    #[serde(default)]
+    pub curve: OutputCurve,
+    // Where each trim is taken to be until it's first moved.
+    #[serde(default = "FilterMakeupConfig::default_trim")]
+    pub default_trim: u8,
+}
+
+impl FilterMakeupConfig {
+    fn default_trim() -> u8 {
+        63
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel.unwrap_or(DEVICE_CHANNEL)
+    }
+
+    // The boost for the filter at `value`, which is none in the middle.
+    pub fn boost(&self, value: u8) -> u8 {
+        let distance = value.abs_diff(63) as u32;
+        let scaling = OutputScaling {
+            out_min: 0,
+            out_max: self.max_boost,
+            curve: self.curve,
+        };
+        scaling.apply((distance * 127 / 64).min(127) as u8)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.trim_ccs.iter().any(|&cc| cc > 127) || self.max_boost > 127 {
+            return Err("filter.makeup CCs and max_boost must be within 0-127".into());
+        }
+        if self.default_trim > 127 || self.channel() > 15 {
+            return Err("filter.makeup default_trim or channel is out of range".into());
+        }
+        Ok(())
+    }
+}
+
+// The controls belonging to one deck: the inputs on the device, and the outputs they're sent to
+// Rekordbox as, which rekordbox-mappings.csv assigns to that deck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeckConfig {
+    // Input CC of the loop encoder, and the note its ticks are sent as on the fake button channels.
+    pub loop_cc: u8,
+    pub loop_out_note: u8,
+    // Input notes of the filter and tempo deck toggles.
+    pub filter_toggle_note: u8,
+    pub tempo_toggle_note: u8,
+    // Output CCs of the filter and tempo, on their encoder channels.
+    pub filter_cc: u8,
+    pub tempo_cc: u8,
+    // Input note which snaps the deck's tempo back to 0%.
+    #[serde(default)]
+    pub tempo_reset_note: Option<u8>,
+}
+
+// The Xone:K2 layout rekordbox-mappings.csv was made for, where the third deck is on the left.
+pub const DEFAULT_DECKS: [DeckConfig; DECK_COUNT] = [
+    DeckConfig {
+        loop_cc: 1,
+        loop_out_note: 1,
+        filter_toggle_note: 0x29,
+        tempo_toggle_note: 0x23,
+        filter_cc: 1,
+        tempo_cc: 1,
+        tempo_reset_note: None,
+    },
+    DeckConfig {
+        loop_cc: 2,
+        loop_out_note: 2,
+        filter_toggle_note: 0x2A,
+        tempo_toggle_note: 0x1F,
+        filter_cc: 2,
+        tempo_cc: 2,
+        tempo_reset_note: None,
+    },
+    DeckConfig {
+        loop_cc: 0,
+        loop_out_note: 0,
+        filter_toggle_note: 0x28,
+        tempo_toggle_note: 0x27,
+        filter_cc: 0,
+        tempo_cc: 0,
+        tempo_reset_note: None,
+    },
+];
+
+// Every deck's controls, by deck. Listing them replaces the default layout entirely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Decks(pub [DeckConfig; DECK_COUNT]);
+
+impl Default for Decks {
+    fn default() -> Self {
+        Self(DEFAULT_DECKS)
+    }
+}
+
+impl Decks {
+    // The deck whose filter toggle is on `note`.
+    pub fn filter_deck(&self, note: u8) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|deck| deck.filter_toggle_note == note)
+    }
+
+    // The deck whose tempo toggle is on `note`.
+    pub fn tempo_deck(&self, note: u8) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|deck| deck.tempo_toggle_note == note)
+    }
+
+    // The deck whose tempo reset is on `note`.
+    pub fn tempo_reset_deck(&self, note: u8) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|deck| deck.tempo_reset_note == Some(note))
+    }
+
+    // The deck whose loop encoder is on `cc`.
+    pub fn loop_deck(&self, cc: u8) -> Option<&DeckConfig> {
+        self.0.iter().find(|deck| deck.loop_cc == cc)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (i, deck) in self.0.iter().enumerate() {
+            let values = [
+                deck.loop_cc,
+                deck.loop_out_note,
+                deck.filter_toggle_note,
+                deck.tempo_toggle_note,
+                deck.filter_cc,
+                deck.tempo_cc,
+            ];
+            if values
+                .iter()
+                .chain(&deck.tempo_reset_note)
+                .any(|&x| x > 127)
+            {
+                return Err(format!("decks: deck {} has a CC or note out of range", i + 1).into());
+            }
+        }
+
+        // Two decks sharing an input couldn't be told apart, and sharing an output would drive
+        // one of Rekordbox's decks from both. A toggle note can't be both decks' filter and tempo.
+        let fields = [
+            ("loop_cc", self.0.map(|deck| vec![deck.loop_cc])),
+            ("loop_out_note", self.0.map(|deck| vec![deck.loop_out_note])),
+            (
+                "toggle note",
+                self.0.map(|deck| {
+                    let notes = [deck.filter_toggle_note, deck.tempo_toggle_note];
+                    notes.into_iter().chain(deck.tempo_reset_note).collect()
+                }),
+            ),
+            ("filter_cc", self.0.map(|deck| vec![deck.filter_cc])),
+            ("tempo_cc", self.0.map(|deck| vec![deck.tempo_cc])),
+        ];
+        for (name, values) in fields {
+            let mut seen = Vec::new();
+            for (i, values) in values.into_iter().enumerate() {
+                for value in values {
+                    match seen.iter().find(|&&(_, x)| x == value) {
+                        Some((other, _)) if *other == i => {
+                            return Err(format!(
+                                "decks: deck {} uses {} {} twice",
+                                i + 1,
+                                name,
+                                value
+                            )
+                            .into());
+                        }
+                        Some((other, _)) => {
+                            return Err(format!(
+                                "decks: decks {} and {} share {} {}",
+                                other + 1,
+                                i + 1,
+                                name,
+                                value
+                            )
+                            .into());
+                        }
+                        None => seen.push((i, value)),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Which channels controls listen on, for devices which report the same control on several channels.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    // Channels messages are accepted on at all, before any control sees them. Any if unset.
+    pub allow_channels: Option<Vec<u8>>,
+    // Channels messages are never accepted on, even if allowed above.
+    pub deny_channels: Vec<u8>,
+    // What happens to messages on a channel which isn't accepted.
+    pub excluded_channels: ChannelPolicy,
+    // The channel every control listens on, unless overridden below. Any channel if unset.
+    pub channel: Option<u8>,
+    // Per-control overrides of `channel`, keyed by input CC or note number.
+    pub cc_channels: HashMap<u8, u8>,
+    pub note_channels: HashMap<u8, u8>,
+    // What happens to CCs and notes on a channel their control isn't listening on.
+    pub other_channels: ChannelPolicy,
+    // What happens to CCs which aren't mapped to any control.
+    pub unknown_ccs: UnknownCcPolicy,
+    // Drop a message identical to the previous one if it arrives within this many milliseconds.
+    pub dedupe_ms: u64,
+    // The part of an absolute control's travel which is actually used, keyed by input CC. It's
+    // stretched to cover the full 0-127 range before any control sees it, or it's forwarded.
+    pub scaling: HashMap<u8, ValueRange>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            allow_channels: None,
+            deny_channels: Vec::new(),
+            excluded_channels: ChannelPolicy::Drop,
+            channel: None,
+            cc_channels: HashMap::new(),
+            note_channels: HashMap::new(),
+            other_channels: ChannelPolicy::default(),
+            unknown_ccs: UnknownCcPolicy::default(),
+            dedupe_ms: 0,
+            scaling: HashMap::new(),
+        }
+    }
+}
+
+impl InputConfig {
+    // Whether messages on `channel` are accepted at all.
+    pub fn accepts(&self, channel: u8) -> bool {
+        self.allow_channels
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&channel))
+            && !self.deny_channels.contains(&channel)
+    }
+
+    // Whether a CC or note message on `channel` should reach the control mapped to `code`.
+    pub fn listens(&self, kind: u8, code: u8, channel: u8) -> bool {
+        let overrides = if kind == CONTROL_CHANGE {
+            &self.cc_channels
+        } else {
+            &self.note_channels
+        };
+        overrides
+            .get(&code)
+            .copied()
+            .or(self.channel)
+            .is_none_or(|listening| listening == channel)
+    }
+
+    pub fn scale(&self, cc: u8, data: u8) -> u8 {
+        self.scaling
+            .get(&cc)
+            .map_or(data, |range| range.stretch(data))
+    }
+
+    pub fn dedupe_window(&self) -> Duration {
+        Duration::from_millis(self.dedupe_ms)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let channels = self
+            .channel
+            .iter()
+            .chain(self.allow_channels.iter().flatten())
+            .chain(&self.deny_channels)
+            .chain(self.cc_channels.values())
+            .chain(self.note_channels.values());
+        if let Some(channel) = channels.into_iter().find(|&&x| x > 15) {
+            return Err(format!("input channel {} is not within 0-15", channel).into());
+        }
+        for (cc, range) in &self.scaling {
+            if range.min >= range.max || range.max > 127 {
+                return Err(
+                    format!("input.scaling.{} range {:?} is not within 0-127", cc, range).into(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelPolicy {
+    #[default]
+    Forward,
+    Drop,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownCcPolicy {
+    #[default]
+    Forward,
+    Drop,
+    // Forward, but call the CC out in the log so it stands out as a candidate for mapping.
+    Log,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PotConfig {
+    // The encoder's input CC.
+    pub cc: u8,
+    #[serde(default)]
+    pub mode: PotMode,
+    // The CC the emulated pot's value is sent on. Defaults to the input CC, and DEVICE_CHANNEL.
+    #[serde(default)]
+    out_cc: Option<u8>,
+    #[serde(default)]
+    channel: Option<u8>,
+    // The pot's value at startup.
+    #[serde(default = "PotConfig::default_value")]
+    pub default: u8,
+    // Where Rekordbox reports the pot's value, so that it can be followed when changed elsewhere.
+    #[serde(default)]
+    pub feedback: Option<PotFeedbackConfig>,
+    // Input note (e.g. the encoder's push) which snaps the pot back to `reset_value`.
+    #[serde(default)]
+    pub reset_note: Option<u8>,
+    // Defaults to `default`.
+    #[serde(default)]
+    reset_value: Option<u8>,
+    // If set, the LED at `reset_note` plus this offset flashes on reset.
+    #[serde(default)]
+    pub reset_led_offset: Option<u8>,
+    // What's sent when the encoder is turned further past either end.
+    #[serde(default)]
+    pub at_limit: PotLimit,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PotFeedbackConfig {
+    pub channel: u8,
+    // Defaults to the pot's output CC.
+    pub cc: Option<u8>,
+}
+
+impl PotConfig {
+    fn new(cc: u8) -> Self {
+        Self {
+            cc,
+            mode: PotMode::default(),
+            out_cc: None,
+            channel: None,
+            default: Self::default_value(),
+            feedback: None,
+            reset_note: None,
+            reset_value: None,
+            reset_led_offset: None,
+            at_limit: PotLimit::default(),
+        }
+    }
+
+    fn default_value() -> u8 {
+        63
+    }
+
+    pub fn out_cc(&self) -> u8 {
+        self.out_cc.unwrap_or(self.cc)
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel.unwrap_or(DEVICE_CHANNEL)
+    }
+
+    pub fn reset_value(&self) -> u8 {
+        self.reset_value.unwrap_or(self.default)
+    }
+
+    // The note of the LED which flashes on reset, if any.
+    pub fn reset_led(&self) -> Option<u8> {
+        Some(self.reset_note?.saturating_add(self.reset_led_offset?))
+    }
+
+    // The channel and CC Rekordbox reports the pot's value on, if followed.
+    pub fn feedback(&self) -> Option<(u8, u8)> {
+        self.feedback
+            .map(|x| (x.channel, x.cc.unwrap_or(self.out_cc())))
+    }
+}
+
+// How a single CC is handled, declared in `controls`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ControlConfig {
+    // A relative encoder emulating a pot, as if listed in `pots` with its defaults.
+    Pot,
+    // A control sending its position, which is forwarded as-is. Defaults to the input CC, and
+    // DEVICE_CHANNEL.
+    Absolute {
+        #[serde(default)]
+        out_cc: Option<u8>,
+        #[serde(default)]
+        channel: Option<u8>,
+    },
+    // A relative encoder pressing `note` on the fake button channels, per `fake_buttons`.
+    Button {
+        note: u8,
+        #[serde(default)]
+        up_channel: Option<u8>,
+        #[serde(default)]
+        down_channel: Option<u8>,
+    },
+}
+
+impl ControlConfig {
+    // The channels a button's up and down ticks are sent on.
+    pub fn button_channels(up_channel: Option<u8>, down_channel: Option<u8>) -> (u8, u8) {
+        (
+            up_channel.unwrap_or(FAKE_BUTTON_UP_CHANNEL),
+            down_channel.unwrap_or(FAKE_BUTTON_DOWN_CHANNEL),
+        )
+    }
+
+    fn validate(&self, cc: u8) -> Result<()> {
+        let (value, channels) = match *self {
+            Self::Pot => (0, [None; 2]),
+            Self::Absolute { out_cc, channel } => (out_cc.unwrap_or(0), [channel, None]),
+            Self::Button {
+                note,
+                up_channel,
+                down_channel,
+            } => (note, [up_channel, down_channel]),
+        };
+        if cc > 127 || value > 127 {
+            return Err(format!("controls: CC {} has a value outside 0-127", cc).into());
+        }
+        if channels.into_iter().flatten().any(|x| x > 15) {
+            return Err(format!("controls: CC {} channel is not within 0-15", cc).into());
+        }
+        Ok(())
+    }
+}
+
+// What an output channel set aside for the mapping's own use is for, if `channel` is one.
+fn reserved_channel(channel: u8) -> Option<&'static str> {
+    match channel {
+        FAKE_BUTTON_UP_CHANNEL => Some("fake button up ticks"),
+        FAKE_BUTTON_DOWN_CHANNEL => Some("fake button down ticks"),
+        FILTER_ENCODER_CHANNEL => Some("the filter"),
+        TEMPO_ENCODER_CHANNEL => Some("the tempo fader"),
+        _ => None,
+    }
+}
+
+// Signals that an encoder is pinned at either end of its pot, which otherwise sends nothing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PotLimit {
+    #[default]
+    Off,
+    // Re-send the end's value.
+    Resend,
+    // Press and release a note on the pot's channel.
+    Note(u8),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PotMode {
+    // The encoder sends steps, which are accumulated into the pot's value.
+    #[default]
+    Relative,
+    // The control sends its position, which is used as the pot's value as-is.
+    Absolute,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedbackRule {
+    // The channel the CC arrives on from Rekordbox.
+    pub channel: u8,
+    // The CC to match. Any CC on `channel` if unset.
+    pub cc: Option<u8>,
+    // The channel and CC to send on instead. Both default to the incoming ones.
+    pub out_channel: Option<u8>,
+    pub out_cc: Option<u8>,
+    // The part of the incoming range to stretch across 0-127.
+    pub scaling: Option<ValueRange>,
+}
+
+impl FeedbackRule {
+    pub fn matches(&self, channel: u8, cc: u8) -> bool {
+        channel == self.channel && self.cc.is_none_or(|x| x == cc)
+    }
+
+    // The CC message to send the device in place of the one from Rekordbox.
+    pub fn apply(&self, channel: u8, cc: u8, value: u8) -> OutgoingMessage {
+        OutgoingMessage::new(CONTROL_CHANGE, self.out_channel.unwrap_or(channel)).data(
+            self.out_cc.unwrap_or(cc),
+            self.scaling.map_or(value, |range| range.stretch(value)),
+        )
+    }
+
+    fn validate(&self) -> Result<()> {
+        let channels_valid = self.channel <= 15 && self.out_channel.is_none_or(|x| x <= 15);
+        let ccs_valid = self.cc.is_none_or(|x| x <= 127) && self.out_cc.is_none_or(|x| x <= 127);
+        let scaling_valid = self
+            .scaling
+            .is_none_or(|range| range.min < range.max && range.max <= 127);
+        if !(channels_valid && ccs_valid && scaling_valid) {
+            return Err(format!(
+                "feedback rule for channel {} has a channel, CC or scaling out of range",
+                self.channel
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// A level meter Rekordbox sends as a CC, shown on a meter on the device which takes the number of
+// segments to light rather than 0-127.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeterConfig {
+    // The channel and CC the level arrives on from Rekordbox.
+    pub channel: u8,
+    pub cc: u8,
+    // The channel and CC of the device's meter. Both default to the incoming ones.
+    pub out_channel: Option<u8>,
+    pub out_cc: Option<u8>,
+    #[serde(default = "MeterConfig::default_segments")]
+    pub segments: u8,
+    // Keep the highest level lit for `peak_hold_ms` before letting it fall back.
+    #[serde(default)]
+    pub peak_hold: bool,
+    #[serde(default = "MeterConfig::default_peak_hold_ms")]
+    pub peak_hold_ms: u64,
+}
+
+impl MeterConfig {
+    fn default_segments() -> u8 {
+        8
+    }
+
+    fn default_peak_hold_ms() -> u64 {
+        500
+    }
+
+    pub fn out_channel(&self) -> u8 {
+        self.out_channel.unwrap_or(self.channel)
+    }
+
+    pub fn out_cc(&self) -> u8 {
+        self.out_cc.unwrap_or(self.cc)
+    }
+
+    // The number of segments to light for `value`, rounded to the nearest.
+    pub fn quantize(&self, value: u8) -> u8 {
+        let value = u16::from(value.min(127));
+        ((value * u16::from(self.segments) + 63) / 127) as u8
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.channel > 15 || self.out_channel() > 15 || self.cc > 127 || self.out_cc() > 127 {
+            return Err(format!(
+                "meters: CC {} on channel {} has a channel or CC out of range",
+                self.cc, self.channel
+            )
+            .into());
+        }
+        if !(1..=127).contains(&self.segments) {
+            return Err(format!(
+                "meters: CC {} on channel {} must have 1-127 segments",
+                self.cc, self.channel
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// The kinds of message from Rekordbox a clock rule or resync probe can match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FeedbackKind {
+    NoteOn,
+    // Also matches a NOTE ON with velocity 0, which Rekordbox sends to turn LEDs off.
+    NoteOff,
+    Cc,
+}
+
+impl FeedbackKind {
+    // The channel, kind, note (or CC) and value of a message, if it's of a kind rules can match.
+    fn parse(message: &[u8]) -> Option<(u8, Self, u8, u8)> {
+        let &[status, note, value] = message else {
+            return None;
+        };
+        let kind = match status & 0xF0 {
+            NOTE_ON if value > 0 => FeedbackKind::NoteOn,
+            NOTE_ON | NOTE_OFF => FeedbackKind::NoteOff,
+            CONTROL_CHANGE => FeedbackKind::Cc,
+            _ => return None,
+        };
+        Some((status & 0x0F, kind, note, value))
+    }
+
+    // Whether `message` is of this kind, on `channel` and for `note`, and has `value` if one is
+    // given.
+    pub fn matches(self, message: &[u8], channel: u8, note: u8, value: Option<u8>) -> bool {
+        Self::parse(message).is_some_and(|(c, kind, n, v)| {
+            (c, kind, n) == (channel, self, note) && value.is_none_or(|x| x == v)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClockAction {
+    Start,
+    Stop,
+    Continue,
+}
+
+impl ClockAction {
+    // The MIDI realtime message for the action.
+    pub fn message(self) -> u8 {
+        match self {
+            ClockAction::Start => 0xFA,
+            ClockAction::Continue => 0xFB,
+            ClockAction::Stop => 0xFC,
+        }
+    }
+}
+
+// Messages which would be sent to Rekordbox on any of `channels` go to another output port instead.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    // Part of the port's name, or an alias for it.
+    pub port: String,
+    pub channels: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClockRule {
+    // The channel the message arrives on from Rekordbox.
+    pub channel: u8,
+    pub kind: FeedbackKind,
+    // The note, or CC for `kind = "cc"`.
+    pub note: u8,
+    // The velocity or value to match. Any if unset.
+    pub value: Option<u8>,
+    pub action: ClockAction,
+}
+
+impl ClockRule {
+    pub fn matches(&self, message: &[u8]) -> bool {
+        self.kind
+            .matches(message, self.channel, self.note, self.value)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.channel > 15 || self.note > 127 || self.value.is_some_and(|x| x > 127) {
+            return Err(format!(
+                "clock rule for channel {} has a channel, note or value out of range",
+                self.channel
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// A message Rekordbox sends when it connects, e.g. while initializing the device's LEDs, which
+// means it has forgotten every value and needs them all re-sending.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResyncProbe {
+    pub channel: u8,
+    pub kind: FeedbackKind,
+    // The note, or CC for `kind = "cc"`.
+    pub note: u8,
+    // The velocity or value to match. Any if unset.
+    pub value: Option<u8>,
+    // Matches within this long of the last resync are ignored, as the message may be part of a
+    // burst.
+    #[serde(default = "ResyncProbe::default_cooldown_ms")]
+    pub cooldown_ms: u64,
+}
+
+impl ResyncProbe {
+    fn default_cooldown_ms() -> u64 {
+        2000
+    }
+
+    pub fn matches(&self, message: &[u8]) -> bool {
+        self.kind
+            .matches(message, self.channel, self.note, self.value)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.channel > 15 || self.note > 127 || self.value.is_some_and(|x| x > 127) {
+            return Err("resync_probe has a channel, note or value out of range".into());
+        }
+        Ok(())
+    }
+}
+
+// How the values of continuous controls are mapped onto what's actually sent. Internally controls
+// keep their full 0-127 resolution.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub filter: OutputScaling,
+    pub tempo: OutputScaling,
+    // Keyed by the fake pot's input CC.
+    pub pots: HashMap<u8, OutputScaling>,
+    // Sends the filter or tempo as 14-bit NRPNs instead of CCs, after scaling.
+    pub filter_nrpn: Option<NrpnConfig>,
+    pub tempo_nrpn: Option<NrpnConfig>,
+}
+
+impl OutputConfig {
+    fn validate(&self) -> Result<()> {
+        let all = [&self.filter, &self.tempo]
+            .into_iter()
+            .chain(self.pots.values());
+        for scaling in all {
+            if scaling.out_min > 127 || scaling.out_max > 127 {
+                return Err("output out_min and out_max must be within 0-127".into());
+            }
+        }
+
+        let nrpns = [
+            ("filter_nrpn", self.filter_nrpn, FILTER_ENCODER_CHANNEL),
+            ("tempo_nrpn", self.tempo_nrpn, TEMPO_ENCODER_CHANNEL),
+        ];
+        let mut parameters = Vec::new();
+        for (name, nrpn, default_channel) in nrpns {
+            let Some(nrpn) = nrpn else {
+                continue;
+            };
+            let channel = nrpn.channel(default_channel);
+            if channel > 15 || nrpn.parameters.iter().any(|&x| x > nrpn::MAX) {
+                return Err(
+                    format!("output.{} has a channel or parameter out of range", name).into(),
+                );
+            }
+            for parameter in nrpn.parameters {
+                if parameters.contains(&(channel, parameter)) {
+                    return Err(format!(
+                        "output.{}: parameter {} on channel {} is used more than once",
+                        name, parameter, channel
+                    )
+                    .into());
+                }
+                parameters.push((channel, parameter));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A 14-bit NRPN output, with a parameter per deck.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NrpnConfig {
+    // Defaults to the channel the control's CCs are sent on.
+    channel: Option<u8>,
+    pub parameters: [u16; DECK_COUNT],
+}
+
+impl NrpnConfig {
+    pub fn channel(&self, default: u8) -> u8 {
+        self.channel.unwrap_or(default)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaderConfig {
+    // The fader's input CC.
+    pub cc: u8,
+    // Default to the input CC, and DEVICE_CHANNEL.
+    #[serde(default)]
+    out_cc: Option<u8>,
+    #[serde(default)]
+    channel: Option<u8>,
+    pub curve: FaderShape,
+    // How close to either end counts as being at that end, to make up for noise.
+    #[serde(default = "FaderConfig::default_edge")]
+    pub edge: u8,
+}
+
+impl FaderConfig {
+    fn default_edge() -> u8 {
+        2
+    }
+
+    pub fn out_cc(&self) -> u8 {
+        self.out_cc.unwrap_or(self.cc)
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel.unwrap_or(DEVICE_CHANNEL)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.cc > 127 || self.out_cc() > 127 {
+            return Err(format!("faders: CC {} is not within 0-127", self.cc).into());
+        }
+        if self.channel() > 15 {
+            return Err(format!("faders: CC {} channel is not within 0-15", self.cc).into());
+        }
+        if self.edge > 31 {
+            return Err(format!("faders: CC {} edge must be at most 31", self.cc).into());
+        }
+        if let FaderShape::Points(points) = &self.curve {
+            if points.len() < 2
+                || points.windows(2).any(|pair| pair[0][0] >= pair[1][0])
+                || points.iter().flatten().any(|&x| x > 127)
+            {
+                return Err(format!(
+                    "faders: CC {} curve needs at least 2 points within 0-127, in increasing \
+                     order of input",
+                    self.cc
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+// A fader's curve, as either a preset or [input, output] breakpoints which are interpolated between.
+// Inputs outside the first and last breakpoints are clamped to them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum FaderShape {
+    Preset(FaderPreset),
+    Points(Vec<[u8; 2]>),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FaderPreset {
+    Linear,
+    // Eases in and out of each end.
+    Smooth,
+    // A scratch curve: reaches the middle within the first and last ~10% of travel, and stays there
+    // in between.
+    Sharp,
+}
+
+impl FaderShape {
+    pub fn apply(&self, value: u8) -> u8 {
+        let points: &[[u8; 2]] = match self {
+            FaderShape::Preset(FaderPreset::Linear) => &[[0, 0], [127, 127]],
+            FaderShape::Preset(FaderPreset::Smooth) => &[[0, 0], [32, 16], [95, 111], [127, 127]],
+            FaderShape::Preset(FaderPreset::Sharp) => &[[0, 0], [13, 64], [114, 64], [127, 127]],
+            FaderShape::Points(points) => points,
+        };
+
+        let Some(i) = points.iter().position(|&[input, _]| input >= value) else {
+            return points[points.len() - 1][1];
+        };
+        if i == 0 {
+            return points[0][1];
+        }
+        let ([x0, y0], [x1, y1]) = (points[i - 1], points[i]);
+        let t = (value - x0) as f32 / (x1 - x0) as f32;
+        (y0 as f32 + (y1 as f32 - y0 as f32) * t).round() as u8
+    }
+}
+
+// Maps 0-127 onto `out_min`-`out_max` along `curve`. `out_min` may be greater than `out_max`, to
+// flip a control's direction.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputScaling {
+    pub out_min: u8,
+    pub out_max: u8,
+    pub curve: OutputCurve,
+}
+
+impl OutputScaling {
+    pub fn apply(&self, value: u8) -> u8 {
+        self.apply_fine(value as f32).round() as u8
+    }
+
+    // Like `apply`, but without rounding to a whole step, for outputs with finer resolution.
+    pub fn apply_fine(&self, value: f32) -> f32 {
+        let t = value.clamp(0.0, 127.0) / 127.0;
+        let t = match self.curve {
+            OutputCurve::Linear => t,
+            // Both pass through 0 and 1 exactly, so the endpoints stay reachable.
+            OutputCurve::Log => (1.0 + 9.0 * t).log10(),
+            OutputCurve::Exp => (10f32.powf(t) - 1.0) / 9.0,
+        };
+        let (min, max) = (self.out_min as f32, self.out_max as f32);
+        let scaled = min + (max - min) * t;
+        scaled.clamp(min.min(max), min.max(max)).min(127.0)
+    }
+
+    // The internal value which is sent as closest to `sent`.
+    pub fn invert(&self, sent: u8) -> u8 {
+        (0..=127)
+            .min_by_key(|&x| self.apply(x).abs_diff(sent))
+            .unwrap_or(sent)
+    }
+}
+
+impl Default for OutputScaling {
+    fn default() -> Self {
+        Self {
+            out_min: 0,
+            out_max: 127,
+            curve: OutputCurve::Linear,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputCurve {
+    #[default]
+    Linear,
+    // Rises quickly at first, then flattens out.
+    Log,
+    // Rises slowly at first, then steepens.
+    Exp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whether each of 0, 1, 63, 64, 65 and 127 is an up tick, a down tick, or neither.
+    fn directions(ranges: FakeButtonConfig) -> Vec<Option<&'static str>> {
+        [0, 1, 63, 64, 65, 127]
+            .into_iter()
+            .map(|value| match value {
+                _ if ranges.up.contains(value) => Some("up"),
+                _ if ranges.down.contains(value) => Some("down"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn table(first: u8, rest: u8) -> VelocityCurve {
+        let mut values = vec![rest; 128];
+        values[0] = first;
+        VelocityCurve::Table { values }
+    }
+
+    #[test]
+    fn velocity_tables_never_turn_presses_into_releases() {
+        assert!(table(0, 1).validate().is_ok());
+        assert!(table(127, 127).validate().is_ok());
+        assert!(table(0, 0).validate().is_err());
+        assert!(table(128, 64).validate().is_err());
+        assert!(table(0, 128).validate().is_err());
+        let short = VelocityCurve::Table {
+            values: vec![64; 127],
+        };
+        assert!(short.validate().is_err());
+    }
+
+    #[test]
+    fn exponential_velocity_curve() {
+        let curve = VelocityCurve::Exponential { exponent: 2.0 };
+        let curved: Vec<_> = [0, 1, 32, 64, 100, 127]
+            .into_iter()
+            .map(|x| curve.apply(x))
+            .collect();
+        // Soft hits get softer, but never become releases, and the ends stay put.
+        assert_eq!(curved, [0, 1, 8, 32, 79, 127]);
+
+        let curve = VelocityCurve::Exponential { exponent: 0.5 };
+        assert_eq!(curve.apply(32), 64);
+        assert!(VelocityCurve::Exponential { exponent: 0.0 }
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn inverted_output_scaling_reaches_both_ends() {
+        for curve in [OutputCurve::Linear, OutputCurve::Log, OutputCurve::Exp] {
+            for (out_min, out_max) in [(127, 0), (96, 10)] {
+                let scaling = OutputScaling {
+                    out_min,
+                    out_max,
+                    curve,
+                };
+                assert_eq!(scaling.apply(0), out_min, "{:?}", curve);
+                assert_eq!(scaling.apply(127), out_max, "{:?}", curve);
+                assert_eq!(scaling.apply_fine(0.0), out_min as f32, "{:?}", curve);
+                assert_eq!(scaling.apply_fine(127.0), out_max as f32, "{:?}", curve);
+
+                // Only ever moving down, and never outside the range, even given values outside
+                // 0-127.
+                let sent: Vec<_> = (0..=127).map(|x| scaling.apply(x)).collect();
+                assert!(sent.windows(2).all(|x| x[0] >= x[1]), "{:?}", curve);
+                for value in [-10.0, 0.5, 126.5, 200.0] {
+                    let scaled = scaling.apply_fine(value);
+                    assert!((out_max as f32..=out_min as f32).contains(&scaled));
+                    assert!(scaled <= 127.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn input_scaling_clamps_at_each_end() {
+        let range = ValueRange { min: 10, max: 117 };
+        let scaled: Vec<_> = [0, 9, 10, 11, 116, 117, 118, 127]
+            .into_iter()
+            .map(|x| range.stretch(x))
+            .collect();
+        assert_eq!(scaled, [0, 0, 0, 1, 126, 127, 127, 127]);
+
+        let config = Config::from_text("[input.scaling]\n19 = [10, 117]\n").unwrap();
+        assert_eq!(config.input.scale(0x13, 5), 0);
+        assert_eq!(config.input.scale(0x13, 120), 127);
+        // Other CCs are left alone.
+        assert_eq!(config.input.scale(0x0F, 5), 5);
+        assert!(Config::from_text("[input.scaling]\n19 = [64, 64]\n").is_err());
+    }
+
+    #[test]
+    fn fake_buttons_default_to_single_steps() {
+        assert_eq!(
+            directions(FakeButtonConfig::default()),
+            [None, Some("up"), None, None, None, Some("down")]
+        );
+    }
+
+    #[test]
+    fn fake_button_ranges_are_inclusive() {
+        let config = Config::from_text("[fake_buttons]\nup = [1, 63]\ndown = [65, 127]\n").unwrap();
+        assert_eq!(
+            directions(config.fake_buttons),
+            [
+                None,
+                Some("up"),
+                Some("up"),
+                None,
+                Some("down"),
+                Some("down")
+            ]
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::sink::RecordingSink;
+use crate::status;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// How many recently sent messages are remembered.
+const CAPACITY: usize = 32;
+
+// Remembers messages recently sent to the device, so that an identical message sent again shortly
+// after (e.g. the device echoing an LED state back, which Rekordbox then echoes again) can be
+// suppressed before it turns into a feedback loop.
+pub struct EchoGuard {
+    window: Duration,
+    recent: VecDeque<(Vec<u8>, Instant)>,
+}
+
+impl EchoGuard {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            recent: VecDeque::with_capacity(CAPACITY),
+        }
+    }
+
+    // Returns whether `message` should be sent, remembering it if so. Always true if disabled.
+    pub fn allow(&mut self, message: &[u8], now: Instant) -> bool {
+        if self.window.is_zero() {
+            return true;
+        }
+
+        while let Some((_, at)) = self.recent.front() {
+            if now.duration_since(*at) < self.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+
+        if self.recent.iter().any(|(recent, _)| recent == message) {
+            status::message(format_args!("Suppressed {:?} (ECHO)", message));
+            return false;
+        }
+
+        if self.recent.len() == CAPACITY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((message.to_vec(), now));
+        true
+    }
+
+    // Drops any recorded messages which would be echoes.
+    pub fn filter(&mut self, recorded: &mut RecordingSink, now: Instant) {
+        recorded.messages.retain(|message| self.allow(message, now));
+    }
+}
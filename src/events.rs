@@ -0,0 +1,43 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// A change made by one of the controls, for external tools (e.g. a stream overlay) to react to
+// without decoding MIDI themselves. With --json-events each is printed to stdout as a line of JSON,
+// tagged with its kebab-case `type`. Decks count from 1, and values are 0-127 as the control sees
+// them, before any output scaling or inversion.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    // A deck's filter was enabled or disabled: {"type":"filter","deck":1,"enabled":true}
+    Filter { deck: usize, enabled: bool },
+    // The filter was turned, which applies to every enabled deck. 63 is the middle:
+    // {"type":"filter-value","value":90}
+    FilterValue { value: u8 },
+    // A fake pot, by input CC, was sent a new value: {"type":"pot","cc":20,"value":70}
+    Pot { cc: u8, value: u8 },
+    // The tempo fader was selected onto a deck: {"type":"tempo-deck","deck":2}
+    TempoDeck { deck: usize },
+    // A deck's tempo was sent, as the fader's position (0 at the top): {"type":"tempo","deck":2,"value":64}
+    Tempo { deck: usize, value: u8 },
+}
+
+// Prints events from now on, in place of the line per message.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn emit(event: Event) {
+    if !enabled() {
+        return;
+    }
+    match serde_json::to_string(&event) {
+        Ok(line) => println!("{}", line),
+        Err(e) => eprintln!("Failed to encode {:?}: {}", event, e),
+    }
+}
@@ -0,0 +1,53 @@
+use crate::config::FaderConfig;
+use crate::sink::MidiSink;
+use crate::{log_send, Result, CONTROL_CHANGE};
+
+// An absolute fader (e.g. a crossfader) whose value is reshaped along a curve before being
+// forwarded.
+pub struct FaderCurve {
+    config: FaderConfig,
+    // The end (0 or 127) the fader is snapped to, if any.
+    snapped: Option<u8>,
+}
+
+impl FaderCurve {
+    pub fn new(config: FaderConfig) -> Self {
+        Self {
+            config,
+            snapped: None,
+        }
+    }
+
+    pub fn handle(&mut self, data: u8, out: &mut dyn MidiSink) -> Result<()> {
+        let data = self.snap(data.min(127));
+        let value = self.config.curve.apply(data);
+        log_send(
+            self.config.channel(),
+            CONTROL_CHANGE,
+            self.config.out_cc(),
+            value,
+            out,
+        )
+    }
+
+    // Within `edge` of either end, the fader snaps to that end, so it's reliably reached despite
+    // noise. Once snapped, it has to move twice as far to leave, so noise can't flicker it back out.
+    fn snap(&mut self, data: u8) -> u8 {
+        let margin = |end| {
+            let edge = self.config.edge;
+            if self.snapped == Some(end) {
+                edge * 2
+            } else {
+                edge
+            }
+        };
+        self.snapped = if data <= margin(0) {
+            Some(0)
+        } else if data >= 127 - margin(127) {
+            Some(127)
+        } else {
+            None
+        };
+        self.snapped.unwrap_or(data)
+    }
+}
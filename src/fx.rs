@@ -0,0 +1,118 @@
+use crate::config::{FxConfig, LedConfig, ReleasePolicy};
+use crate::led::{Led, LedColor};
+use crate::sink::MidiSink;
+use crate::{log_send, send_button, Result, CONTROL_CHANGE};
+
+// Routes a shared set of FX knobs and buttons to whichever FX unit is selected.
+pub struct FxSection {
+    config: FxConfig,
+    leds: LedConfig,
+    // How each button is released, indexed by button.
+    releases: Vec<ReleasePolicy>,
+    pub unit_index: usize,
+    // Each unit's knob values, indexed by unit and then knob.
+    pub values: Vec<Vec<u8>>,
+    // Where each physical knob was last seen.
+    prev_values: Vec<u8>,
+}
+
+impl FxSection {
+    pub fn new(config: FxConfig, leds: LedConfig, releases: Vec<ReleasePolicy>) -> Self {
+        Self {
+            releases,
+            unit_index: 0,
+            values: vec![vec![63; config.knobs.len()]; config.units.len()],
+            prev_values: vec![63; config.knobs.len()],
+            config,
+            leds,
+        }
+    }
+
+    pub fn select_unit(&mut self, note: u8, color_out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(i) = self
+            .config
+            .units
+            .iter()
+            .position(|unit| unit.select_note == note)
+        else {
+            return Ok(false);
+        };
+
+        self.unit_index = i;
+        self.light_selected(color_out)?;
+        Ok(true)
+    }
+
+    // Lights the selected unit's LED, and turns off the others.
+    pub fn light_selected(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for (i, unit) in self.config.units.iter().enumerate() {
+            let color = if i == self.unit_index {
+                self.leds.fx_unit_selected
+            } else {
+                self.leds.fx_unit_unselected
+            };
+
+            Led::new(unit.select_note).set(color, color_out)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn adjust(&mut self, cc: u8, data: u8, out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(knob) = self.config.knobs.iter().position(|&x| x == cc) else {
+            return Ok(false);
+        };
+
+        let data = data.min(127);
+        let prev_value = std::mem::replace(&mut self.prev_values[knob], data);
+        let unit_value = &mut self.values[self.unit_index][knob];
+
+        // Pickup algorithm: Don't do anything until the knob has passed the unit's stored value.
+        let prev_sign = (*unit_value).cmp(&prev_value);
+        if (*unit_value).cmp(&data) == prev_sign {
+            return Ok(true);
+        }
+        *unit_value = data;
+
+        let channel = self.config.units[self.unit_index].channel;
+        log_send(channel, CONTROL_CHANGE, knob as u8, data, out)?;
+        Ok(true)
+    }
+
+    pub fn handle_button(&self, note: u8, state: bool, out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(button) = self.config.buttons.iter().position(|&x| x == note) else {
+            return Ok(false);
+        };
+
+        let channel = self.config.units[self.unit_index].channel;
+        let data = if state { 127 } else { 0 };
+        send_button(
+            channel,
+            button as u8,
+            data,
+            state,
+            self.releases[button],
+            out,
+        )?;
+        Ok(true)
+    }
+
+    // Re-sends every unit's knob values, and the unit selection LEDs.
+    pub fn sync(&self, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        for (unit, values) in self.config.units.iter().zip(&self.values) {
+            for (knob, &value) in values.iter().enumerate() {
+                log_send(unit.channel, CONTROL_CHANGE, knob as u8, value, out)?;
+            }
+        }
+
+        self.light_selected(color_out)
+    }
+
+    pub fn clear_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for unit in &self.config.units {
+            Led::new(unit.select_note).set(LedColor::Off, color_out)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{NOTE_OFF, NOTE_ON};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+// Notes sent to Rekordbox as pressed and not yet released, keyed by channel and note, with when
+// they were pressed. This is what's released on a panic or shutdown, and by the watchdog if a note
+// is held for too long, e.g. because the device's release was lost.
+pub struct HeldNotes {
+    held: HashMap<(u8, u8), Instant>,
+    timeout: Option<Duration>,
+    // Notes which are never released by the watchdog: presses which are never followed by a
+    // release, and notes which latch in Rekordbox.
+    exempt: HashSet<(u8, u8)>,
+}
+
+impl HeldNotes {
+    pub fn new(timeout: Option<Duration>, exempt: impl IntoIterator<Item = (u8, u8)>) -> Self {
+        Self {
+            held: HashMap::new(),
+            timeout,
+            exempt: exempt.into_iter().collect(),
+        }
+    }
+
+    // Follows a message sent to Rekordbox.
+    pub fn sent(&mut self, message: &[u8], now: Instant) {
+        let [status, note, velocity] = *message else {
+            return;
+        };
+        let key = (status & 0x0F, note);
+        match status & 0xF0 {
+            // Held since the first press, as a repeated press doesn't mean the release arrived.
+            NOTE_ON if velocity > 0 => {
+                self.held.entry(key).or_insert(now);
+            }
+            NOTE_ON | NOTE_OFF => {
+                self.held.remove(&key);
+            }
+            _ => (),
+        }
+    }
+
+    // Forgets and returns the notes which have been held for longer than the timeout.
+    pub fn expired(&mut self, now: Instant) -> Vec<(u8, u8)> {
+        let Some(timeout) = self.timeout else {
+            return Vec::new();
+        };
+        let mut expired: Vec<_> = self
+            .held
+            .iter()
+            .filter(|(key, &pressed)| {
+                !self.exempt.contains(key) && now.saturating_duration_since(pressed) >= timeout
+            })
+            .map(|(&key, _)| key)
+            .collect();
+        expired.sort();
+        for key in &expired {
+            self.held.remove(key);
+        }
+        expired
+    }
+
+    // Forgets and returns every held note.
+    pub fn take(&mut self) -> Vec<(u8, u8)> {
+        let mut held: Vec<_> = self.held.drain().map(|(key, _)| key).collect();
+        held.sort();
+        held
+    }
+}
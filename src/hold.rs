@@ -0,0 +1,83 @@
+use std::time::{Duration, Instant};
+
+// Tells a tap from a hold: a press released within `hold` is a tap, while one held for longer is a
+// hold, which fires as soon as the time has passed rather than waiting for the release.
+pub struct HoldTimer {
+    hold: Duration,
+    // When the button was pressed, until it's released or the hold fires.
+    pressed_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Tap,
+    Hold,
+}
+
+impl HoldTimer {
+    pub fn new(hold_ms: u64) -> Self {
+        Self {
+            hold: Duration::from_millis(hold_ms),
+            pressed_at: None,
+        }
+    }
+
+    pub fn press(&mut self, now: Instant) {
+        self.pressed_at.get_or_insert(now);
+    }
+
+    // What the press was, unless the hold has already fired from `tick`.
+    pub fn release(&mut self, now: Instant) -> Option<Press> {
+        let pressed_at = self.pressed_at.take()?;
+        // Normally the hold has fired from `tick` by now, but ticks can lag behind input.
+        if now.duration_since(pressed_at) >= self.hold {
+            Some(Press::Hold)
+        } else {
+            Some(Press::Tap)
+        }
+    }
+
+    // Whether the press has just become a hold. Only true once per press.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        if self
+            .pressed_at
+            .is_some_and(|pressed_at| now.duration_since(pressed_at) >= self.hold)
+        {
+            self.pressed_at = None;
+            return true;
+        }
+        false
+    }
+
+    // Forgets the press, e.g. when the release may never arrive.
+    pub fn cancel(&mut self) {
+        self.pressed_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_fire_once() {
+        let mut timer = HoldTimer::new(250);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        timer.press(at(0));
+        assert_eq!(timer.release(at(249)), Some(Press::Tap));
+
+        timer.press(at(1000));
+        assert!(!timer.tick(at(1249)));
+        assert!(timer.tick(at(1250)));
+        assert!(!timer.tick(at(1300)));
+        assert_eq!(timer.release(at(1300)), None);
+
+        // A release with no press, e.g. after `cancel`, is nothing at all.
+        timer.press(at(2000));
+        timer.cancel();
+        assert!(!timer.tick(at(3000)));
+        assert_eq!(timer.release(at(3000)), None);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::config::{KeyControlConfig, LedConfig};
+use crate::hold::{HoldTimer, Press};
+use crate::led::{Led, LedColor};
+use crate::sink::MidiSink;
+use crate::{log_send, Result, NOTE_ON};
+use std::time::Instant;
+
+// Key shift and key sync pads. Rekordbox doesn't report the key, so whether it's been shifted is
+// tracked here from what was sent, and shown on the shift pads' LEDs.
+pub struct KeyControl {
+    config: KeyControlConfig,
+    leds: LedConfig,
+    up: HoldTimer,
+    down: HoldTimer,
+    // Semitones shifted since the key was last reset.
+    pub shift: i32,
+}
+
+impl KeyControl {
+    pub fn new(config: KeyControlConfig, leds: LedConfig) -> Self {
+        Self {
+            up: HoldTimer::new(config.hold_ms),
+            down: HoldTimer::new(config.hold_ms),
+            config,
+            leds,
+            shift: 0,
+        }
+    }
+
+    pub fn handle_button(
+        &mut self,
+        note: u8,
+        state: bool,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        if note == self.config.sync_note {
+            if state {
+                log_send(
+                    self.config.channel,
+                    NOTE_ON,
+                    self.config.sync_out_note,
+                    127,
+                    out,
+                )?;
+            }
+            return Ok(true);
+        }
+
+        let (timer, step, out_note) = if note == self.config.up_note {
+            (&mut self.up, 1, self.config.up_out_note)
+        } else if note == self.config.down_note {
+            (&mut self.down, -1, self.config.down_out_note)
+        } else {
+            return Ok(false);
+        };
+
+        if state {
+            timer.press(now);
+            return Ok(true);
+        }
+        match timer.release(now) {
+            Some(Press::Tap) => {
+                self.shift += step;
+                log_send(self.config.channel, NOTE_ON, out_note, 127, out)?;
+                self.light_leds(color_out)?;
+            }
+            Some(Press::Hold) => self.reset(out, color_out)?,
+            None => (),
+        }
+        Ok(true)
+    }
+
+    // Resets the key as soon as a shift pad has been held for long enough, rather than on release.
+    pub fn tick(
+        &mut self,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        let up_held = self.up.tick(now);
+        let down_held = self.down.tick(now);
+        if up_held || down_held {
+            self.reset(out, color_out)?;
+        }
+        Ok(())
+    }
+
+    // A newly loaded track plays in its original key.
+    pub fn follow(&mut self, message: &[u8], color_out: &mut dyn MidiSink) -> Result<()> {
+        let Some(load_note) = self.config.load_feedback_note else {
+            return Ok(());
+        };
+        let [status, note, velocity] = *message else {
+            return Ok(());
+        };
+        if status != NOTE_ON | self.config.channel || note != load_note || velocity == 0 {
+            return Ok(());
+        }
+
+        if self.shift != 0 {
+            self.shift = 0;
+            self.light_leds(color_out)?;
+        }
+        Ok(())
+    }
+
+    // Forgets held pads, e.g. when their release may never arrive.
+    pub fn release(&mut self) {
+        self.up.cancel();
+        self.down.cancel();
+    }
+
+    pub fn light_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        let color = if self.shift != 0 {
+            self.leds.key_shifted
+        } else {
+            self.leds.key_unshifted
+        };
+        Led::new(self.config.up_note).set(color, color_out)?;
+        Led::new(self.config.down_note).set(color, color_out)
+    }
+
+    pub fn clear_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        Led::new(self.config.up_note).set(LedColor::Off, color_out)?;
+        Led::new(self.config.down_note).set(LedColor::Off, color_out)
+    }
+
+    fn reset(&mut self, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        self.shift = 0;
+        log_send(
+            self.config.channel,
+            NOTE_ON,
+            self.config.reset_out_note,
+            127,
+            out,
+        )?;
+        self.light_leds(color_out)
+    }
+}
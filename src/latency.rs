@@ -0,0 +1,49 @@
+use crate::status;
+use std::time::{Duration, Instant};
+
+// How often latency is reported, while messages are arriving.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+// The time from a device message arriving in the input callback to everything it produced having
+// been queued for sending, summarized over each REPORT_INTERVAL.
+pub struct LatencyStats {
+    min: Duration,
+    max: Duration,
+    total: Duration,
+    count: u32,
+    since: Instant,
+}
+
+impl LatencyStats {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            min: Duration::MAX,
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+            count: 0,
+            since: now,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration, now: Instant) {
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+        self.total += latency;
+        self.count += 1;
+
+        if now.duration_since(self.since) >= REPORT_INTERVAL {
+            self.report();
+            *self = Self::new(now);
+        }
+    }
+
+    fn report(&self) {
+        status::line(format_args!(
+            "Latency over the last {} messages: min {}us, avg {}us, max {}us",
+            self.count,
+            self.min.as_micros(),
+            (self.total / self.count).as_micros(),
+            self.max.as_micros()
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::config::{Config, DeckConfig, DEFAULT_DECKS};
+use crate::ports;
+use crate::{Result, CONTROL_CHANGE, DECK_COUNT, NOTE_ON};
+use midir::{Ignore, MidiInput};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+// How long a control has to stay still after being captured before the next prompt, so that the
+// rest of an encoder's turn or a button's release isn't taken as the next control.
+const SETTLE: Duration = Duration::from_millis(400);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Note,
+    Cc,
+}
+
+// A deck input being learned, and where it's kept in the deck's config.
+#[derive(Clone, Copy)]
+struct Control {
+    name: &'static str,
+    kind: Kind,
+    field: fn(&mut DeckConfig) -> &mut u8,
+}
+
+const CONTROLS: [Control; 3] = [
+    Control {
+        name: "FILTER TOGGLE",
+        kind: Kind::Note,
+        field: |deck| &mut deck.filter_toggle_note,
+    },
+    Control {
+        name: "TEMPO TOGGLE",
+        kind: Kind::Note,
+        field: |deck| &mut deck.tempo_toggle_note,
+    },
+    Control {
+        name: "LOOP ENCODER",
+        kind: Kind::Cc,
+        field: |deck| &mut deck.loop_cc,
+    },
+];
+
+// A captured control: its deck and control, and the channel and note or CC it was heard on.
+#[derive(Clone, Copy)]
+struct Captured {
+    deck: usize,
+    control: Control,
+    channel: u8,
+    code: u8,
+}
+
+enum Input {
+    Midi(u8, Kind, u8),
+    Line(String),
+    Eof,
+}
+
+// Asks for each deck control to be moved in turn, capturing the note or CC and channel it sends,
+// and writes the resulting mapping to `path`. Controls can be skipped, keeping the default layout's,
+// and the last capture redone.
+pub fn run(path: &Path, device_in: Option<&str>) -> Result<()> {
+    if path.exists() {
+        return Err(format!(
+            "{} already exists, move it out of the way or choose another file",
+            path.display()
+        )
+        .into());
+    }
+
+    let mut midi_in = MidiInput::new("PartySaver learn")?;
+    midi_in.ignore(Ignore::All);
+    let port = match device_in {
+        Some(name) => ports::find_unique_port(&midi_in, "input", name)?,
+        None => ports::select_port(&midi_in, "input", None)?,
+    };
+
+    // MIDI and typed commands arrive on the same channel, so either can answer a prompt.
+    let (sender, inputs) = mpsc::channel();
+    let _conn_in = midi_in.connect(
+        &port,
+        "party-saver-learn",
+        |_, message, sender| {
+            let input = match *message {
+                [status, note, velocity] if status & 0xF0 == NOTE_ON && velocity > 0 => {
+                    Input::Midi(status & 0x0F, Kind::Note, note)
+                }
+                [status, cc, _] if status & 0xF0 == CONTROL_CHANGE => {
+                    Input::Midi(status & 0x0F, Kind::Cc, cc)
+                }
+                // Releases belong to the press before them.
+                _ => return,
+            };
+            let _ = sender.send(input);
+        },
+        sender.clone(),
+    )?;
+    std::thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(Input::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(Input::Eof);
+    });
+
+    let steps: Vec<_> = (0..DECK_COUNT)
+        .flat_map(|deck| CONTROLS.map(|control| (deck, control)))
+        .collect();
+    let mut captured: Vec<Option<Captured>> = Vec::new();
+    // Lines typed while a control was settling, answered at the next prompt.
+    let mut pending = VecDeque::new();
+    println!("Move each control when asked. Enter s to skip one, or r to redo the last one.");
+    'steps: while captured.len() < steps.len() {
+        let (deck, control) = steps[captured.len()];
+        print!("Move the control for DECK{} {}: ", deck + 1, control.name);
+        stdout().flush()?;
+        loop {
+            let input = match pending.pop_front() {
+                Some(input) => input,
+                None => inputs.recv()?,
+            };
+            match input {
+                Input::Eof => break 'steps,
+                Input::Line(line) => match line.trim() {
+                    "s" => {
+                        println!("Skipped, keeping the default");
+                        captured.push(None);
+                        continue 'steps;
+                    }
+                    "r" => {
+                        if captured.pop().is_none() {
+                            println!("Nothing to redo");
+                        }
+                        continue 'steps;
+                    }
+                    _ => {
+                        print!("Enter s to skip, or r to redo the last one: ");
+                        stdout().flush()?;
+                    }
+                },
+                Input::Midi(channel, kind, code) => {
+                    if kind != control.kind {
+                        print!(
+                            "\nThat sent a {}, expected a {}. Try again: ",
+                            kind, control.kind
+                        );
+                        stdout().flush()?;
+                        continue;
+                    }
+                    if let Some(other) = captured
+                        .iter()
+                        .flatten()
+                        .find(|other| other.control.kind == kind && other.code == code)
+                    {
+                        print!(
+                            "\nThat's already DECK{} {}. Try again: ",
+                            other.deck + 1,
+                            other.control.name
+                        );
+                        stdout().flush()?;
+                        continue;
+                    }
+                    println!("{} {} on channel {}", kind, code, channel);
+                    captured.push(Some(Captured {
+                        deck,
+                        control,
+                        channel,
+                        code,
+                    }));
+                    settle(&inputs, &mut pending);
+                    continue 'steps;
+                }
+            }
+        }
+    }
+    println!();
+
+    let captured: Vec<_> = captured.into_iter().flatten().collect();
+    if captured.is_empty() {
+        println!("Nothing learned, {} wasn't written", path.display());
+        return Ok(());
+    }
+    let text = mapping(&captured);
+    if let Err(e) = Config::from_text(&text) {
+        println!(
+            "Fix this by hand and save it as {}:\n{}",
+            path.display(),
+            text
+        );
+        return Err(format!("The learned mapping is invalid: {}", e).into());
+    }
+    std::fs::write(path, text)?;
+    println!(
+        "Wrote {} learned controls to {}",
+        captured.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Kind::Note => "note",
+            Kind::Cc => "CC",
+        })
+    }
+}
+
+// Drops MIDI until none has arrived for SETTLE. Anything typed meanwhile is kept for the next prompt.
+fn settle(inputs: &mpsc::Receiver<Input>, pending: &mut VecDeque<Input>) {
+    while let Ok(input) = inputs.recv_timeout(SETTLE) {
+        if !matches!(input, Input::Midi(..)) {
+            pending.push_back(input);
+        }
+    }
+}
+
+// The config for the captured controls. Decks are listed in full, as listing them replaces the
+// default layout, with skipped controls keeping the default's. Channels go in [input], as one
+// channel if every control was heard on the same one.
+fn mapping(captured: &[Captured]) -> String {
+    let mut decks = DEFAULT_DECKS;
+    for capture in captured {
+        *(capture.control.field)(&mut decks[capture.deck]) = capture.code;
+    }
+
+    let mut text = String::from("# Written by party-saver learn\n");
+    let mut channels: Vec<_> = captured.iter().map(|capture| capture.channel).collect();
+    channels.sort();
+    channels.dedup();
+    if let [channel] = channels[..] {
+        let _ = writeln!(text, "\n[input]\nchannel = {}", channel);
+    } else {
+        for (kind, table) in [(Kind::Note, "note_channels"), (Kind::Cc, "cc_channels")] {
+            let _ = writeln!(text, "\n[input.{}]", table);
+            for capture in captured
+                .iter()
+                .filter(|capture| capture.control.kind == kind)
+            {
+                let _ = writeln!(
+                    text,
+                    "{} = {}    # DECK{} {}",
+                    capture.code,
+                    capture.channel,
+                    capture.deck + 1,
+                    capture.control.name
+                );
+            }
+        }
+    }
+
+    for (i, deck) in decks.iter().enumerate() {
+        let _ = writeln!(
+            text,
+            "\n[[decks]]  # Deck {}\nloop_cc = {}\nloop_out_note = {}\n\
+             filter_toggle_note = 0x{:02X}\ntempo_toggle_note = 0x{:02X}\nfilter_cc = {}\ntempo_cc = {}",
+            i + 1,
+            deck.loop_cc,
+            deck.loop_out_note,
+            deck.filter_toggle_note,
+            deck.tempo_toggle_note,
+            deck.filter_cc,
+            deck.tempo_cc
+        );
+    }
+    text
+}
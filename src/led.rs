@@ -0,0 +1,64 @@
+use crate::message::OutgoingMessage;
+use crate::sink::MidiSink;
+use crate::status;
+use crate::{Result, DEVICE_CHANNEL, NOTE_OFF, NOTE_ON};
+use serde::Deserialize;
+
+// What an LED should show. Devices with RGB pads take the color as the velocity of a NOTE_ON, so
+// this is either that velocity, or "off" for a NOTE_OFF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "LedColorRepr")]
+pub enum LedColor {
+    Off,
+    Velocity(u8),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LedColorRepr {
+    Velocity(u8),
+    Name(String),
+}
+
+impl TryFrom<LedColorRepr> for LedColor {
+    type Error = String;
+
+    fn try_from(repr: LedColorRepr) -> std::result::Result<Self, String> {
+        match repr {
+            LedColorRepr::Velocity(velocity @ 0..=127) => Ok(LedColor::Velocity(velocity)),
+            LedColorRepr::Velocity(velocity) => {
+                Err(format!("LED velocity {} is not within 0-127", velocity))
+            }
+            LedColorRepr::Name(name) if name == "off" => Ok(LedColor::Off),
+            LedColorRepr::Name(name) => Err(format!(
+                "Unknown LED color \"{}\", expected a velocity or \"off\"",
+                name
+            )),
+        }
+    }
+}
+
+// An LED on the device, addressed by note.
+#[derive(Debug, Clone, Copy)]
+pub struct Led {
+    pub note: u8,
+}
+
+impl Led {
+    pub fn new(note: u8) -> Self {
+        Self { note }
+    }
+
+    pub fn set(self, color: LedColor, color_out: &mut dyn MidiSink) -> Result<()> {
+        let message = match color {
+            LedColor::Off => OutgoingMessage::new(NOTE_OFF, DEVICE_CHANNEL).data(self.note, 127),
+            LedColor::Velocity(velocity) => {
+                OutgoingMessage::new(NOTE_ON, DEVICE_CHANNEL).data(self.note, velocity)
+            }
+        };
+        let message = message.build().inspect_err(|e| {
+            status::line(format_args!("Refused to set LED {}: {}", self.note, e))
+        })?;
+        color_out.send(&message)
+    }
+}
@@ -0,0 +1,209 @@
+use crate::config::DEFAULT_DECKS;
+use crate::session_log;
+use crate::{
+    CHANNEL_PRESSURE, CONTROL_CHANGE, DEVICE_CHANNEL, FAKE_BUTTON_DOWN_CHANNEL,
+    FAKE_BUTTON_UP_CHANNEL, FILTER_ENCODER_CHANNEL, HEADPHONE_MIX_CC, HEADPHONE_VOLUME_CC,
+    MASTER_VOLUME_CC, NOTE_OFF, NOTE_ON, PB_DOWN_OUT_NOTE_BASE, PB_UP_OUT_NOTE_BASE, PITCH_BEND,
+    PROGRAM_CHANGE, TEMPO_ENCODER_CHANNEL, TEMPO_RANGE_OUT_NOTE_BASE,
+};
+use clap::ValueEnum;
+use std::fmt;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+// Warnings about the same control are logged at most this often.
+const WARN_INTERVAL: Duration = Duration::from_secs(1);
+
+// How messages sent to Rekordbox are written to the log.
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    // The bytes as-is, e.g. [159, 41, 127].
+    #[default]
+    Raw,
+    // The message type, channel, what it controls and its value.
+    Decoded,
+}
+
+// How messages forwarded to Rekordbox as-is are written to the log.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VerbatimLog {
+    Off,
+    // Each distinct status and note or CC the first time it's seen, then again after 10, 100, ...
+    // of them, with the count so far.
+    Sample,
+    #[default]
+    All,
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+static VERBATIM: OnceLock<VerbatimLog> = OnceLock::new();
+
+// When a warning was last logged for each input CC, and how many have been suppressed since.
+static WARNED: Mutex<Vec<(u8, Instant, u64)>> = Mutex::new(Vec::new());
+
+pub fn set_format(format: LogFormat) {
+    let _ = FORMAT.set(format);
+}
+
+// Logs a warning about the control on input CC `cc`, unless one was logged within WARN_INTERVAL, so
+// that a misconfigured or stuck control can't bury everything else.
+pub fn warn(cc: u8, message: fmt::Arguments) {
+    let now = Instant::now();
+    let mut warned = WARNED.lock().unwrap_or_else(PoisonError::into_inner);
+    let suppressed = match warned.iter_mut().find(|(x, _, _)| *x == cc) {
+        Some((_, last, suppressed)) if now.duration_since(*last) < WARN_INTERVAL => {
+            *suppressed += 1;
+            return;
+        }
+        Some((_, last, suppressed)) => {
+            *last = now;
+            std::mem::take(suppressed)
+        }
+        None => {
+            warned.push((cc, now, 0));
+            0
+        }
+    };
+
+    if suppressed > 0 {
+        session_log::event(format_args!(
+            "Warning: CC {}: {} ({} more suppressed)",
+            cc, message, suppressed
+        ));
+    } else {
+        session_log::event(format_args!("Warning: CC {}: {}", cc, message));
+    }
+}
+
+pub fn set_verbatim(mode: VerbatimLog) {
+    let _ = VERBATIM.set(mode);
+}
+
+pub fn verbatim() -> VerbatimLog {
+    VERBATIM.get().copied().unwrap_or_default()
+}
+
+pub fn format(message: &[u8]) -> String {
+    match FORMAT.get().copied().unwrap_or_default() {
+        LogFormat::Raw => format!("{:?}", message),
+        LogFormat::Decoded => decode(message).unwrap_or_else(|| format!("{:?}", message)),
+    }
+}
+
+fn decode(message: &[u8]) -> Option<String> {
+    let (&status, data) = message.split_first()?;
+    let channel = status & 0x0F;
+    let decoded = match (status & 0xF0, data) {
+        (kind @ (NOTE_ON | NOTE_OFF), &[note, velocity]) => format!(
+            "{} ch{} {} velocity {}",
+            if kind == NOTE_ON { "NoteOn" } else { "NoteOff" },
+            channel,
+            name(note_name(channel, note), "note", note),
+            velocity
+        ),
+        (CONTROL_CHANGE, &[cc, value]) => format!(
+            "CC ch{} {} value {}",
+            channel,
+            name(cc_name(channel, cc), "cc", cc),
+            value
+        ),
+        (PROGRAM_CHANGE, &[program]) => format!("ProgramChange ch{} program {}", channel, program),
+        (CHANNEL_PRESSURE, &[pressure]) => format!("Pressure ch{} value {}", channel, pressure),
+        (PITCH_BEND, &[lsb, msb]) => format!(
+            "PitchBend ch{} value {}",
+            channel,
+            (msb as u16) << 7 | lsb as u16
+        ),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+// What a message controls, for errors about it, e.g. "DECK1_FILTER (1) on channel 12".
+pub fn describe(channel: u8, kind: u8, code: u8) -> String {
+    let described = match kind {
+        CONTROL_CHANGE => name(cc_name(channel, code), "cc", code),
+        NOTE_ON | NOTE_OFF => name(note_name(channel, code), "note", code),
+        _ => format!("message {:#04X} {}", kind, code),
+    };
+    format!("{} on channel {}", described, channel)
+}
+
+fn name(known: Option<&str>, kind: &str, code: u8) -> String {
+    match known {
+        Some(known) => format!("{} ({})", known, code),
+        None => format!("{} {}", kind, code),
+    }
+}
+
+// Names of the CCs party-saver sends by default, by output channel.
+fn cc_name(channel: u8, cc: u8) -> Option<&'static str> {
+    let name = match (channel, cc) {
+        (DEVICE_CHANNEL, HEADPHONE_MIX_CC) => "HEADPHONE_MIX",
+        (DEVICE_CHANNEL, HEADPHONE_VOLUME_CC) => "HEADPHONE_VOLUME",
+        (DEVICE_CHANNEL, MASTER_VOLUME_CC) => "MASTER_VOLUME",
+        (FILTER_ENCODER_CHANNEL, cc) => {
+            let i = DEFAULT_DECKS.iter().position(|deck| deck.filter_cc == cc)?;
+            ["DECK1_FILTER", "DECK2_FILTER", "DECK3_FILTER"][i]
+        }
+        (TEMPO_ENCODER_CHANNEL, cc) => {
+            let i = DEFAULT_DECKS.iter().position(|deck| deck.tempo_cc == cc)?;
+            ["DECK1_TEMPO", "DECK2_TEMPO", "DECK3_TEMPO"][i]
+        }
+        _ => return None,
+    };
+    Some(name)
+}
+
+// Notes sent on TEMPO_ENCODER_CHANNEL, each a base note offset by the deck index.
+const TEMPO_NOTES: [(u8, [&str; 3]); 3] = [
+    (
+        PB_DOWN_OUT_NOTE_BASE,
+        [
+            "DECK1_PITCH_BEND_DOWN",
+            "DECK2_PITCH_BEND_DOWN",
+            "DECK3_PITCH_BEND_DOWN",
+        ],
+    ),
+    (
+        PB_UP_OUT_NOTE_BASE,
+        [
+            "DECK1_PITCH_BEND_UP",
+            "DECK2_PITCH_BEND_UP",
+            "DECK3_PITCH_BEND_UP",
+        ],
+    ),
+    (
+        TEMPO_RANGE_OUT_NOTE_BASE,
+        [
+            "DECK1_TEMPO_RANGE",
+            "DECK2_TEMPO_RANGE",
+            "DECK3_TEMPO_RANGE",
+        ],
+    ),
+];
+
+// Names of the notes party-saver sends itself, by output channel.
+fn note_name(channel: u8, note: u8) -> Option<&'static str> {
+    let name = match (channel, note) {
+        (FAKE_BUTTON_UP_CHANNEL, note) => {
+            let i = DEFAULT_DECKS
+                .iter()
+                .position(|deck| deck.loop_out_note == note)?;
+            ["DECK1_LOOP_UP", "DECK2_LOOP_UP", "DECK3_LOOP_UP"][i]
+        }
+        (FAKE_BUTTON_DOWN_CHANNEL, note) => {
+            let i = DEFAULT_DECKS
+                .iter()
+                .position(|deck| deck.loop_out_note == note)?;
+            ["DECK1_LOOP_DOWN", "DECK2_LOOP_DOWN", "DECK3_LOOP_DOWN"][i]
+        }
+        (TEMPO_ENCODER_CHANNEL, note) => {
+            return TEMPO_NOTES
+                .iter()
+                .find_map(|(base, names)| names.get(note.checked_sub(*base)? as usize).copied())
+        }
+        _ => return None,
+    };
+    Some(name)
+}
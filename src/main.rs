@@ -1,7 +1,81 @@
+mod bank;
+mod blink;
+mod browse;
+mod buttons;
+mod calibrate;
+mod config;
+mod echo;
+mod events;
+mod fader;
+mod fx;
+mod held;
+mod hold;
+mod key;
+mod latency;
+mod learn;
+mod led;
+mod logging;
+mod message;
+mod meter;
+mod nrpn;
+mod output;
+mod pads;
+mod ports;
+mod repeat;
+mod replay;
+mod self_test;
+mod session_log;
+mod shutdown;
+mod sink;
+mod stats;
+mod status;
+mod talkover;
+mod throttle;
+
+use bank::FocusedBank;
+use blink::Blink;
+use browse::BrowseEncoder;
+use buttons::ButtonWithFeedback;
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{
+    AftertouchConfig, ChannelPolicy, ClockAction, ClockRule, Config, ControlConfig, Deadband,
+    Decks, FakeButtonConfig, FeedbackRule, FilterConfig, FilterSend, InputConfig, LedConfig,
+    NrpnConfig, OutputScaling, PitchBendConfig, PotConfig, PotLimit, PotMode, ProgramChangeConfig,
+    RelativeMode, ReleasePolicy, ResyncProbe, ShiftConfig, SnapshotPadConfig, TempoFineConfig,
+    TempoRangeConfig, UnknownCcPolicy, VelocityCurve,
+};
+use echo::EchoGuard;
+use events::Event;
+use fader::FaderCurve;
+use fx::FxSection;
+use held::HeldNotes;
+use key::KeyControl;
+use latency::LatencyStats;
+use led::{Led, LedColor};
+use logging::{LogFormat, VerbatimLog};
+use message::OutgoingMessage;
+use meter::Meter;
 use midir::os::unix::{VirtualInput, VirtualOutput};
-use midir::{MidiIO, MidiInput, MidiOutput, MidiOutputConnection};
+use midir::{
+    MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputConnection, MidiOutputPort,
+};
+use output::{Batch, Dest, OutputQueue, Outputs, SharedBatch};
+use pads::PadRow;
+use ports::Aliases;
+use repeat::RepeatButton;
+use shutdown::{exit_with, Shutdown, EXIT_CONNECTION, EXIT_PORT_SELECTION};
+use sink::{DryRunSink, MidiSink, RecordingSink};
+use stats::SessionStats;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::io::{stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use talkover::Talkover;
+use throttle::Throttle;
 
 // This channel is reserved for actual messages from the device, or messages which haven't been
 // substantially altered.
@@ -9,107 +83,629 @@ const DEVICE_CHANNEL: u8 = 0xF;
 
 const FAKE_BUTTON_UP_CHANNEL: u8 = 0xE;
 const FAKE_BUTTON_DOWN_CHANNEL: u8 = 0xD;
+// The up and down channels together, as fake buttons are sent on unless overridden.
+const FAKE_BUTTON_CHANNELS: (u8, u8) = (FAKE_BUTTON_UP_CHANNEL, FAKE_BUTTON_DOWN_CHANNEL);
 const FILTER_ENCODER_CHANNEL: u8 = 0xC;
 const TEMPO_ENCODER_CHANNEL: u8 = 0xB;
 
 const NOTE_OFF: u8 = 0x80;
 const NOTE_ON: u8 = 0x90;
 const CONTROL_CHANGE: u8 = 0xB0;
+const PROGRAM_CHANGE: u8 = 0xC0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
+const PITCH_BEND: u8 = 0xE0;
+
+// Channel mode messages, sent with a value of 0.
+const ALL_SOUND_OFF_CC: u8 = 120;
+const ALL_NOTES_OFF_CC: u8 = 123;
 
 const HEADPHONE_MIX_CC: u8 = 20;
 const HEADPHONE_VOLUME_CC: u8 = 21;
-const DECK1_LOOP_CC: u8 = 1;
-const DECK2_LOOP_CC: u8 = 2;
-const DECK3_LOOP_CC: u8 = 0;
 const MASTER_VOLUME_CC: u8 = 3;
 
-// Inputs for filter controls.
+// Inputs for filter and tempo controls. Each deck's toggles and outputs are in config::Decks.
 const FILTER_CC: u8 = 15;
-const DECK1_FILTER_TOGGLE_NOTE: u8 = 0x29;
-const DECK2_FILTER_TOGGLE_NOTE: u8 = 0x2A;
-const DECK3_FILTER_TOGGLE_NOTE: u8 = 0x28;
-
-// Fake outputs for filter controls.
-const DECK1_FILTER_CC: u8 = 1;
-const DECK2_FILTER_CC: u8 = 2;
-const DECK3_FILTER_CC: u8 = 0;
-
-// Inputs for tempo controls.
 const TEMPO_CC: u8 = 19;
-const DECK1_TEMPO_TOGGLE_NOTE: u8 = 0x23;
-const DECK2_TEMPO_TOGGLE_NOTE: u8 = 0x1F;
-const DECK3_TEMPO_TOGGLE_NOTE: u8 = 0x27;
 const PB_DOWN_IN_NOTE: u8 = 0x0C;
 const PB_UP_IN_NOTE: u8 = 0x0F;
 
 // Fake outputs for tempo controls.
-const DECK1_TEMPO_CC: u8 = 1;
-const DECK2_TEMPO_CC: u8 = 2;
-const DECK3_TEMPO_CC: u8 = 0;
 const PB_DOWN_OUT_NOTE_BASE: u8 = 1;
 const PB_UP_OUT_NOTE_BASE: u8 = 4;
+const TEMPO_RANGE_OUT_NOTE_BASE: u8 = 7;
+
+// The number of decks the filter and tempo controls switch between.
+const DECK_COUNT: usize = 3;
+
+// How often time-based work (settling throttled values, blinking LEDs) is done.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+// How often the config file is checked for changes.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often the keepalive thread checks whether the device has gone quiet.
+const KEEPALIVE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// A MIDI active sensing message, which the device ignores but still has to accept.
+const ACTIVE_SENSING: u8 = 0xFE;
+
+// How many distinct kinds of message forwarded verbatim are counted for the stats.
+const MAX_UNRECOGNIZED: usize = 512;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
-fn log_send(
-    channel: u8,
-    kind: u8,
-    code: u8,
-    data: u8,
-    out: &mut MidiOutputConnection,
-) -> Result<()> {
-    let message = [channel | kind, code, data];
+// Where to find what party-saver sends, for setting up the mapping in Rekordbox. Channels are
+// numbered from 0, as in the second digit of a status byte in `rekordbox-mappings.csv`.
+const CHANNELS_HELP: &str = "\
+Channels sent to Rekordbox:
+  F (15)  Controls forwarded as-is, and the fake pots
+  E (14)  Loop encoders turned up, as fake buttons
+  D (13)  Loop encoders turned down, as fake buttons
+  C (12)  Filter, one CC per deck
+  B (11)  Tempo, one CC per deck, plus pitch bend and tempo range notes";
+
+// The crate version, plus the commit and target it was built from, for bug reports.
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (commit ",
+    env!("PARTY_SAVER_COMMIT"),
+    ", ",
+    env!("PARTY_SAVER_TARGET"),
+    ")"
+);
+
+#[derive(Parser)]
+#[command(
+    version = VERSION,
+    about = "MIDI Translator for Xone:K2 -> Rekordbox",
+    after_help = CHANNELS_HELP,
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Translate between the device and Rekordbox. This is the default.
+    Run(Box<Args>),
+
+    /// List the available MIDI input and output ports, then exit.
+    ListPorts {
+        /// Print the ports as JSON, for scripts.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check that a config file is valid, then exit. Exits non-zero if it isn't.
+    CheckConfig { file: PathBuf },
+
+    /// Measure how far each absolute control actually travels, by moving it to both ends when
+    /// asked, and write that to the config file's [input.scaling] table.
+    Calibrate {
+        file: PathBuf,
+
+        /// Instead of prompting, use the only input port whose name contains this.
+        #[arg(long, value_name = "NAME")]
+        device_in: Option<String>,
+
+        /// A CC to calibrate. May be repeated. Defaults to those already in [input.scaling], the
+        /// faders and the tempo fader.
+        #[arg(long = "cc", value_name = "CC")]
+        ccs: Vec<u8>,
+    },
+
+    /// Light every LED the config uses in turn, then listen while the controls are moved and
+    /// report PASS, FAIL or UNTESTED for each. Exits non-zero if any failed.
+    SelfTest {
+        /// Path to a TOML config file. Defaults are used for anything not specified.
+        #[arg(long, env = "PARTY_SAVER_CONFIG")]
+        config: Option<PathBuf>,
+
+        /// Instead of prompting, use the only input port whose name contains this.
+        #[arg(long, value_name = "NAME")]
+        device_in: Option<String>,
+
+        /// Instead of prompting, use the only output port whose name contains this. Defaults to
+        /// --device-in.
+        #[arg(long, value_name = "NAME")]
+        device_out: Option<String>,
+
+        /// How long to listen for controls being moved, in seconds.
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        listen_secs: u64,
+    },
+
+    /// Build a mapping by moving each deck control when asked, and write it to a new config file.
+    Learn {
+        #[arg(default_value = "mapping.toml")]
+        file: PathBuf,
+
+        /// Instead of prompting, use the only input port whose name contains this.
+        #[arg(long, value_name = "NAME")]
+        device_in: Option<String>,
+    },
+
+    /// Replay fixtures of timed messages through the translator, and compare everything sent with
+    /// each fixture's golden file. Set PARTY_SAVER_BLESS=1 to write the golden files instead.
+    /// Exits non-zero if any differed.
+    Replay {
+        /// Fixture files. Defaults to every .fixture file in tests/fixtures.
+        fixtures: Vec<PathBuf>,
+
+        #[arg(long, value_enum, default_value_t = Target::Rekordbox)]
+        target: Target,
+    },
+}
+
+#[derive(clap::Args)]
+struct Args {
+    /// Path to a TOML config file. Defaults are used for anything not specified.
+    #[arg(long, env = "PARTY_SAVER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Run a message given as hex bytes (e.g. "B0 0F 40") through the translator and print what
+    /// it emits, without opening any MIDI ports. May be repeated; messages are applied in order.
+    #[arg(long, value_name = "HEX")]
+    inject: Vec<String>,
+
+    /// Treat the injected messages as coming from Rekordbox, rather than the device.
+    #[arg(long, requires = "inject")]
+    inject_feedback: bool,
+
+    /// Don't stop when enter is pressed; only SIGINT/SIGTERM (or losing the device) stops it.
+    /// Stdin is ignored anyway when it isn't a terminal.
+    #[arg(long)]
+    run_forever: bool,
+
+    /// Instead of prompting for ports, wait until an input and output port whose names contain
+    /// this (or what this alias stands for) appear, then connect to them.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["device_in", "device_out"])]
+    wait_for_device: Option<String>,
+
+    /// Instead of prompting, use the only input port whose name contains this, or what this alias
+    /// stands for. Also used for the output port unless --device-out is given.
+    #[arg(long, value_name = "NAME")]
+    device_in: Option<String>,
+
+    /// Instead of prompting, use the only output port whose name contains this, or what this alias
+    /// stands for.
+    #[arg(long, value_name = "NAME")]
+    device_out: Option<String>,
+
+    /// A TOML file of port aliases, each mapping a friendly name to part of a port's name, e.g.
+    /// main-controller = "XONE:K2". Aliases can be used wherever a port name is.
+    #[arg(long, value_name = "PATH", env = "PARTY_SAVER_ALIASES")]
+    aliases: Option<PathBuf>,
+
+    /// Give up waiting for the device after this many seconds.
+    #[arg(long, value_name = "SECONDS", requires = "wait_for_device")]
+    wait_timeout: Option<u64>,
+
+    /// Don't forward messages from Rekordbox to the device. This saves some latency and log noise
+    /// for devices without useful feedback, but any LEDs Rekordbox would drive stay dark. LEDs
+    /// party-saver drives itself (filter, tempo) still work.
+    #[arg(long)]
+    no_passthrough: bool,
+
+    /// Send MIDI Start/Stop from the config's clock rules to the only output port whose name
+    /// contains this, or what this alias stands for. Without it, clock rules are only logged.
+    #[arg(long, value_name = "NAME")]
+    clock_out: Option<String>,
+
+    /// The name of the virtual port Rekordbox sends feedback to.
+    #[arg(long, value_name = "NAME", default_value = "PartySaver In")]
+    virtual_in_name: String,
+
+    /// The name of the virtual port Rekordbox receives the device's messages from.
+    #[arg(long, value_name = "NAME", default_value = "PartySaver Out")]
+    virtual_out_name: String,
+
+    /// Send the device's messages to the only existing output port whose name contains this (e.g.
+    /// a loopback port from loopMIDI), or what this alias stands for, instead of creating a virtual
+    /// port.
+    #[arg(long, value_name = "NAME")]
+    rekordbox_out: Option<String>,
+
+    /// Also write the session statistics printed on exit to this file, as JSON.
+    #[arg(long, value_name = "PATH")]
+    stats_out: Option<PathBuf>,
+
+    /// Whether LEDs and feedback from Rekordbox share one connection to the device, which keeps
+    /// them in order, or each have their own. Defaults to shared on Linux, and separate elsewhere.
+    #[arg(long, value_enum, value_name = "MODE")]
+    device_output: Option<DeviceOutput>,
+
+    /// Connect to the device and run everything as normal, but only log what would be sent
+    /// instead of sending it, to either Rekordbox or the device.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print a line per message instead of a status line redrawn in place. Messages are always
+    /// printed line by line when stdout isn't a terminal, or in a dry run.
+    #[arg(long)]
+    no_status_line: bool,
+
+    /// Print a line of JSON to stdout for each change a control makes (e.g. a filter being
+    /// enabled, or a pot's new value), in place of the line per message and the status line.
+    #[arg(long)]
+    json_events: bool,
+
+    /// How messages sent to Rekordbox are logged: as raw bytes, or decoded into the message type,
+    /// channel, control and value.
+    #[arg(long, value_enum, default_value_t = LogFormat::Raw)]
+    log_format: LogFormat,
+
+    /// How messages forwarded to Rekordbox as-is are logged: not at all, only a sample of each
+    /// distinct status and note or CC with a running count, or every one.
+    #[arg(long, value_enum, default_value_t = VerbatimLog::All)]
+    log_verbatim: VerbatimLog,
+
+    /// Where connection events, errors and warnings are recorded. Defaults to
+    /// $XDG_STATE_HOME/party-saver/party-saver.log, or ~/.local/state/party-saver/party-saver.log.
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Don't record anything to a log file.
+    #[arg(long, conflicts_with_all = ["log_file", "log_traffic"])]
+    no_log_file: bool,
+
+    /// Rotate the log file once it would grow beyond this many kilobytes.
+    #[arg(long, value_name = "KB", default_value_t = 1024)]
+    log_file_max_kb: u64,
+
+    /// How many rotated log files to keep.
+    #[arg(long, value_name = "COUNT", default_value_t = 3)]
+    log_file_keep: u32,
+
+    /// Also record every message sent and received to the log file.
+    #[arg(long)]
+    log_traffic: bool,
+
+    /// On exit, list every distinct message forwarded as-is by how often it was seen, with a guess
+    /// at what it is. This shows what still needs mapping.
+    #[arg(long)]
+    coverage: bool,
+
+    /// Periodically print the min/avg/max time, in microseconds, from a message arriving from the
+    /// device to everything it produced having been queued for sending.
+    #[arg(long)]
+    measure_latency: bool,
+
+    /// The DJ software the output is shaped for.
+    #[arg(long, value_enum, default_value_t = Target::Rekordbox)]
+    target: Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Target {
+    Rekordbox,
+    // Mixxx handles conventional note on/off pairs and relative CCs natively, so it doesn't need
+    // the workarounds Rekordbox does.
+    Mixxx,
+}
+
+// How the LEDs party-saver drives itself and the feedback passed through from Rekordbox reach the
+// device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DeviceOutput {
+    // One connection, owned by the output worker, so that LEDs and feedback arrive in the order
+    // they were produced.
+    Shared,
+    // A connection each, so that feedback is sent straight from the MIDI callback rather than
+    // waiting behind queued LEDs.
+    Separate,
+}
+
+impl DeviceOutput {
+    // ALSA gives each connection its own sequencer client, and doesn't keep messages to the same
+    // port from different clients in order.
+    fn platform_default() -> Self {
+        if cfg!(target_os = "linux") {
+            DeviceOutput::Shared
+        } else {
+            DeviceOutput::Separate
+        }
+    }
+}
+
+fn log_send(channel: u8, kind: u8, code: u8, data: u8, out: &mut dyn MidiSink) -> Result<()> {
+    let message = OutgoingMessage::new(kind, channel)
+        .data(code, data)
+        .build()
+        .inspect_err(|e| {
+            let control = logging::describe(channel, kind, code);
+            session_log::event(format_args!("Refused to send {}: {}", control, e));
+        })?;
     out.send(&message)?;
-    println!("PartySaver->Rekordbox: {:?}", message);
+    let formatted = logging::format(&message);
+    status::message(format_args!("PartySaver->Rekordbox: {}", formatted));
+    session_log::traffic(format_args!("PartySaver->Rekordbox: {}", formatted));
     Ok(())
 }
 
-// Allows treating rotary encoders as pot encoders.
+// Allows treating rotary encoders as pot encoders. Absolute controls can share the same dispatch.
 struct FakePotEncoder {
-    value: u8,
+    cc: u8,
+    // Within 0-127, and not rounded until it's sent, so that movements too small to change what's
+    // sent still add up.
+    value: f32,
+    mode: PotMode,
+    out_cc: u8,
+    channel: u8,
+    throttle: Throttle,
+    scaling: OutputScaling,
+    ticks: u64,
+    feedback: Option<(u8, u8)>,
+    reset_note: Option<u8>,
+    reset_value: u8,
+    reset_led: Option<(Led, LedColor)>,
+    blink: Option<Blink>,
+    at_limit: PotLimit,
+    // How far a talkover has taken the pot down from `value`.
+    duck: u8,
+    // What was last sent, so that a movement which doesn't change it isn't sent again.
+    sent: Option<u8>,
 }
 
 impl FakePotEncoder {
-    fn add(&mut self, data: u8) {
+    fn new(
+        config: &PotConfig,
+        throttle: Option<Deadband>,
+        scaling: OutputScaling,
+        leds: &LedConfig,
+    ) -> Self {
+        Self {
+            cc: config.cc,
+            value: config.default as f32,
+            mode: config.mode,
+            out_cc: config.out_cc(),
+            channel: config.channel(),
+            throttle: Throttle::new(throttle),
+            scaling,
+            ticks: 0,
+            feedback: config.feedback(),
+            reset_note: config.reset_note,
+            reset_value: config.reset_value(),
+            reset_led: config
+                .reset_led()
+                .map(|note| (Led::new(note), leds.pot_reset)),
+            blink: None,
+            at_limit: config.at_limit,
+            duck: 0,
+            sent: None,
+        }
+    }
+
+    // Snaps the pot back to its reset value on a press of its reset note, and sends it.
+    fn reset(
+        &mut self,
+        note: u8,
+        state: bool,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        if self.reset_note != Some(note) {
+            return Ok(false);
+        }
+        if !state {
+            return Ok(true);
+        }
+
+        self.value = self.reset_value as f32;
+        self.throttle.sync(self.position());
+        self.send(out)?;
+        if let Some((led, color)) = self.reset_led {
+            self.blink = Some(Blink::new(led, LedColor::Off, color, 1, now));
+            self.tick(now, color_out)?;
+        }
+        Ok(true)
+    }
+
+    fn tick(&mut self, now: Instant, color_out: &mut dyn MidiSink) -> Result<()> {
+        if let Some(blink) = &mut self.blink {
+            if !blink.tick(now, color_out)? {
+                self.blink = None;
+            }
+        }
+        Ok(())
+    }
+
+    // Picks up the value Rekordbox reports, so the next turn continues from there.
+    fn follow(&mut self, channel: u8, cc: u8, value: u8) -> bool {
+        // Rekordbox echoing back what was sent isn't a change, even if scaling makes it ambiguous.
+        if self.feedback != Some((channel, cc)) || self.data() == value {
+            return false;
+        }
+
+        self.value = (self.scaling.invert(value) as f32 + self.duck as f32).min(127.0);
+        self.throttle.sync(self.position());
+        self.sent = Some(value);
+        true
+    }
+
+    // The value rounded to a whole step.
+    fn position(&self) -> u8 {
+        self.value.round() as u8
+    }
+
+    // What's sent, with any duck taken off, before scaling.
+    fn level(&self) -> f32 {
+        (self.value - self.duck as f32).max(0.0)
+    }
+
+    fn data(&self) -> u8 {
+        self.scaling.apply_fine(self.level()).round() as u8
+    }
+
+    fn set_duck(&mut self, duck: u8, out: &mut dyn MidiSink) -> Result<()> {
+        if duck != self.duck {
+            self.duck = duck;
+            self.send_changed(out)?;
+        }
+        Ok(())
+    }
+
+    // Returns whether `data` was understood.
+    fn add(&mut self, data: u8) -> bool {
+        self.ticks += 1;
+        if self.mode == PotMode::Absolute {
+            self.value = data.min(127) as f32;
+            return true;
+        }
+
         let delta = match data {
-            127 => -3,
-            1 => 3,
+            127 => -3.0,
+            1 => 3.0,
             _ => {
-                println!("Unknown data value: {}", data);
-                0
+                logging::warn(self.cc, format_args!("Unknown data value: {}", data));
+                return false;
             }
         };
-        self.value = self.value.saturating_add_signed(delta).min(127);
+        self.value = (self.value + delta).clamp(0.0, 127.0);
+        true
     }
 
-    fn send(&self, cc: u8, out: &mut MidiOutputConnection) -> Result<()> {
-        log_send(DEVICE_CHANNEL, CONTROL_CHANGE, cc, self.value, out)
+    fn send(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        events::emit(Event::Pot {
+            cc: self.cc,
+            value: self.level().round() as u8,
+        });
+        let data = self.data();
+        self.sent = Some(data);
+        log_send(self.channel, CONTROL_CHANGE, self.out_cc, data, out)
     }
-}
 
-impl Default for FakePotEncoder {
-    fn default() -> Self {
-        Self { value: 63 }
+    // Signals, if configured to, that the encoder was turned further past either end.
+    fn signal_limit(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        match self.at_limit {
+            PotLimit::Off => Ok(()),
+            PotLimit::Resend => self.send(out),
+            PotLimit::Note(note) => {
+                log_send(self.channel, NOTE_ON, note, 127, out)?;
+                log_send(self.channel, NOTE_ON, note, 0, out)
+            }
+        }
+    }
+
+    // Sends the value, unless it would send the same as last time.
+    fn send_changed(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        if self.sent != Some(self.data()) {
+            self.send(out)?;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, now: Instant, out: &mut dyn MidiSink) -> Result<()> {
+        if self.throttle.update(self.position(), now) {
+            self.send_changed(out)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, now: Instant, out: &mut dyn MidiSink) -> Result<()> {
+        if self.throttle.flush(self.position(), now) {
+            self.send_changed(out)?;
+        }
+        Ok(())
     }
 }
 
 // Rekordbox expects the same signal for on AND off for buttons for some stupid reason.
-fn handle_button(note: u8, data: u8, out: &mut MidiOutputConnection) -> Result<()> {
-    log_send(DEVICE_CHANNEL, NOTE_ON, note, data, out)
+#[allow(clippy::too_many_arguments)]
+fn handle_button(
+    channel: u8,
+    note: u8,
+    data: u8,
+    pressed: bool,
+    release: ReleasePolicy,
+    target: Target,
+    curve: &VelocityCurve,
+    out: &mut dyn MidiSink,
+) -> Result<()> {
+    let data = curve.apply(data);
+    match target {
+        Target::Mixxx if release == ReleasePolicy::Mirror => {
+            if pressed && data > 0 {
+                log_send(channel, NOTE_ON, note, data, out)
+            } else {
+                log_send(channel, NOTE_OFF, note, data, out)
+            }
+        }
+        _ => send_button(channel, note, data, pressed && data > 0, release, out),
+    }
 }
 
-// Allows treating rotary encoders as buttons.
-fn handle_fake_button(cc: u8, data: u8, out: &mut MidiOutputConnection) -> Result<()> {
-    let channel = match data {
-        1 => FAKE_BUTTON_UP_CHANNEL,
-        127 => FAKE_BUTTON_DOWN_CHANNEL,
-        _ => {
-            println!("Unknown data value: {}", data);
-            return Ok(());
+// Sends a button's press, or its release as `release` says.
+fn send_button(
+    channel: u8,
+    note: u8,
+    data: u8,
+    pressed: bool,
+    release: ReleasePolicy,
+    out: &mut dyn MidiSink,
+) -> Result<()> {
+    match release {
+        _ if pressed => log_send(channel, NOTE_ON, note, data, out),
+        ReleasePolicy::Mirror => log_send(channel, NOTE_ON, note, data, out),
+        ReleasePolicy::NoteOff => log_send(channel, NOTE_OFF, note, 0, out),
+        ReleasePolicy::Velocity0 => log_send(channel, NOTE_ON, note, 0, out),
+        ReleasePolicy::Suppress => Ok(()),
+    }
+}
+
+// Whether the count is one a sampled verbatim message is logged at: 1, 10, 100, ...
+fn is_sampled(count: u64) -> bool {
+    let mut x = count;
+    while x.is_multiple_of(10) {
+        x /= 10;
+    }
+    x == 1
+}
+
+// Whether a channel message has a data byte above 127. System messages are left alone, as SysEx
+// carries its own framing.
+fn is_malformed(message: &[u8]) -> bool {
+    match message.split_first() {
+        Some((&status, data)) if (NOTE_OFF..0xF0).contains(&status) => {
+            data.iter().any(|&x| x > 127)
         }
+        _ => false,
+    }
+}
+
+// For targets which understand relative encoders natively, instead of faking pots or buttons.
+fn handle_relative(cc: u8, data: u8, mode: RelativeMode, out: &mut dyn MidiSink) -> Result<()> {
+    log_send(DEVICE_CHANNEL, CONTROL_CHANGE, cc, mode.encode(data), out)
+}
+
+// Allows treating rotary encoders as buttons, pressing `note` on the up or down channel of
+// `channels`. Returns whether `data` was in either range.
+fn handle_fake_button(
+    cc: u8,
+    note: u8,
+    data: u8,
+    ranges: &FakeButtonConfig,
+    channels: (u8, u8),
+    out: &mut dyn MidiSink,
+) -> Result<bool> {
+    let (up_channel, down_channel) = channels;
+    let channel = if ranges.up.contains(data) {
+        up_channel
+    } else if ranges.down.contains(data) {
+        down_channel
+    } else {
+        logging::warn(cc, format_args!("Unknown data value: {}", data));
+        return Ok(false);
     };
 
-    log_send(channel, NOTE_ON, cc, 127, out)
+    log_send(channel, NOTE_ON, note, 127, out)?;
+    Ok(true)
 }
 
 // Specialized control for the filter encoder.
@@ -118,6 +714,27 @@ struct FilterEncoder {
     deck2: bool,
     deck3: bool,
     state: u8,
+    throttle: Throttle,
+    leds: LedConfig,
+    config: FilterConfig,
+    decks: Decks,
+    // Only applies to the filter's value. Disabled decks are always sent the neutral 63.
+    scaling: OutputScaling,
+    nrpn: Option<NrpnConfig>,
+    // Where each deck's trim was last moved to, and the makeup boost currently added to it.
+    trims: [u8; 3],
+    boosts: [u8; 3],
+    toggles: [u64; 3],
+    // When each deck's LED was last sent, and whether a newer state is waiting for the LED interval
+    // to pass.
+    led_sent: [Option<Instant>; 3],
+    led_pending: [bool; 3],
+    // How far each deck's filter is engaged, from 0 (neutral) to 1 (the filter's value).
+    mixes: [f32; 3],
+    // Where each deck's ramp started from, and when. None once it's reached where it's going.
+    ramps: [Option<(f32, Instant)>; 3],
+    // What was last sent for each deck. 14 bits with NRPN output.
+    sent: [Option<u16>; 3],
 }
 
 impl FilterEncoder {
@@ -125,35 +742,38 @@ impl FilterEncoder {
         &mut self,
         note: u8,
         state: bool,
-        out: &mut MidiOutputConnection,
-        color_out: &mut MidiOutputConnection,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
     ) -> Result<bool> {
-        if let Some(i) = [
-            DECK1_FILTER_TOGGLE_NOTE,
-            DECK2_FILTER_TOGGLE_NOTE,
-            DECK3_FILTER_TOGGLE_NOTE,
-        ]
-        .iter()
-        .position(|&x| x == note)
-        {
-            // Off messages are captured, but ignored.
-            if !state {
-                return Ok(true);
-            }
-
+        if let Some(i) = self.decks.filter_deck(note) {
             let enabled = &mut [&mut self.deck1, &mut self.deck2, &mut self.deck3][i];
-            **enabled = !**enabled;
-            let enabled = **enabled;
+            if self.config.momentary {
+                // Engaged while held, and back to neutral on release.
+                if **enabled == state {
+                    return Ok(true);
+                }
+                **enabled = state;
+            } else {
+                // Off messages are captured, but ignored.
+                if !state {
+                    return Ok(true);
+                }
+                **enabled = !**enabled;
+            }
+            self.toggles[i] += 1;
+            events::emit(Event::Filter {
+                deck: i + 1,
+                enabled: **enabled,
+            });
+            self.start_ramp(i, now);
 
             // Send filter encoder output to rekordbox.
             self.send(out)?;
+            self.update_makeup(out)?;
 
             // Send color output back to device.
-            if enabled {
-                color_out.send(&[DEVICE_CHANNEL | NOTE_ON, note + 0x48, 127])?;
-            } else {
-                color_out.send(&[DEVICE_CHANNEL | NOTE_OFF, note + 0x48, 127])?;
-            }
+            self.update_led(i, now, color_out)?;
 
             Ok(true)
         } else {
@@ -161,26 +781,226 @@ impl FilterEncoder {
         }
     }
 
-    fn adjust(&mut self, data: u8, out: &mut MidiOutputConnection) -> Result<()> {
-        self.state = data;
-        self.send(out)
+    // Heads deck `i` towards whether it's enabled, from wherever a ramp has got to.
+    fn start_ramp(&mut self, i: usize, now: Instant) {
+        let target = self.target_mix(i);
+        if self.config.ramp_ms == 0 {
+            self.mixes[i] = target;
+            self.ramps[i] = None;
+        } else {
+            self.ramps[i] = Some((self.mixes[i], now));
+        }
+    }
+
+    fn target_mix(&self, i: usize) -> f32 {
+        if [self.deck1, self.deck2, self.deck3][i] {
+            1.0
+        } else {
+            0.0
+        }
     }
 
-    fn send(&self, out: &mut MidiOutputConnection) -> Result<()> {
-        for (enabled, cc) in [
-            (self.deck1, DECK1_FILTER_CC),
-            (self.deck2, DECK2_FILTER_CC),
-            (self.deck3, DECK3_FILTER_CC),
-        ] {
-            if enabled {
-                log_send(FILTER_ENCODER_CHANNEL, CONTROL_CHANGE, cc, self.state, out)?;
+    // Moves each ramping deck along, sending it whenever its output changes.
+    fn ramp(&mut self, now: Instant, out: &mut dyn MidiSink) -> Result<()> {
+        for i in 0..DECK_COUNT {
+            let Some((from, started)) = self.ramps[i] else {
+                continue;
+            };
+            let target = self.target_mix(i);
+
+            // A reversed ramp takes only as long as the distance it has to go back.
+            let duration =
+                Duration::from_millis(self.config.ramp_ms).as_secs_f32() * (target - from).abs();
+            let elapsed = now.saturating_duration_since(started).as_secs_f32();
+            let before = self.deck_data(i);
+            if elapsed >= duration {
+                self.mixes[i] = target;
+                self.ramps[i] = None;
             } else {
-                log_send(FILTER_ENCODER_CHANNEL, CONTROL_CHANGE, cc, 63, out)?;
+                self.mixes[i] = from + (target - from) * elapsed / duration;
+            }
+            if self.deck_data(i) != before {
+                self.send_deck(i, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn adjust(&mut self, data: u8, now: Instant, out: &mut dyn MidiSink) -> Result<()> {
+        // Some devices repeat the same value while a control is held, which would otherwise be
+        // re-sent to every deck. Toggling a deck still sends it, so this is only about movement.
+        if data == self.state {
+            return Ok(());
+        }
+        self.state = data;
+        if self.throttle.update(self.state, now) {
+            events::emit(Event::FilterValue { value: self.state });
+            self.send(out)?;
+            self.update_makeup(out)?;
+        }
+        Ok(())
+    }
+
+    // Takes a move of a deck's trim, if `cc` is one, and sends it with any makeup boost added.
+    fn trim(&mut self, cc: u8, data: u8, out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(makeup) = &self.config.makeup else {
+            return Ok(false);
+        };
+        let Some(i) = makeup.trim_ccs.iter().position(|&x| x == cc) else {
+            return Ok(false);
+        };
+        self.trims[i] = data;
+        self.send_trim(i, out)?;
+        Ok(true)
+    }
+
+    // Brings each deck's makeup boost in line with its filter, sending the trims which changed.
+    fn update_makeup(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        let Some(makeup) = self.config.makeup else {
+            return Ok(());
+        };
+        let enabled = [self.deck1, self.deck2, self.deck3];
+        for (i, enabled) in enabled.into_iter().enumerate() {
+            let boost = if enabled { makeup.boost(self.state) } else { 0 };
+            if boost != self.boosts[i] {
+                self.boosts[i] = boost;
+                self.send_trim(i, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Takes the boost off every trim, e.g. before the trims stop being handled.
+    fn clear_makeup(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        for i in 0..DECK_COUNT {
+            if self.boosts[i] > 0 {
+                self.boosts[i] = 0;
+                self.send_trim(i, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_trim(&self, i: usize, out: &mut dyn MidiSink) -> Result<()> {
+        let Some(makeup) = &self.config.makeup else {
+            return Ok(());
+        };
+        let data = self.trims[i].saturating_add(self.boosts[i]).min(127);
+        log_send(
+            makeup.channel(),
+            CONTROL_CHANGE,
+            makeup.trim_ccs[i],
+            data,
+            out,
+        )
+    }
+
+    fn flush(
+        &mut self,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        self.ramp(now, out)?;
+        if self.throttle.flush(self.state, now) {
+            events::emit(Event::FilterValue { value: self.state });
+            self.send(out)?;
+            self.update_makeup(out)?;
+        }
+        for i in 0..self.led_pending.len() {
+            if self.led_pending[i] {
+                self.update_led(i, now, color_out)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Lights deck `i`'s LED, unless it was sent less than `led_interval_ms` ago. In that case only
+    // the latest state is sent, by `flush` once the interval has passed.
+    fn update_led(&mut self, i: usize, now: Instant, color_out: &mut dyn MidiSink) -> Result<()> {
+        let interval = Duration::from_millis(self.config.led_interval_ms);
+        if self.led_sent[i].is_some_and(|sent| now.duration_since(sent) < interval) {
+            self.led_pending[i] = true;
+            return Ok(());
+        }
+
+        self.led_pending[i] = false;
+        self.led_sent[i] = Some(now);
+        self.light_led(i, color_out)
+    }
+
+    // Re-sends the filter output, the boosted trims and every deck's LED.
+    fn sync(&mut self, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        for i in self.config.order() {
+            self.send_deck(i, out)?;
+        }
+        for i in 0..DECK_COUNT {
+            if self.boosts[i] > 0 {
+                self.send_trim(i, out)?;
+            }
+        }
+        self.light_leds(color_out)
+    }
+
+    fn light_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for i in 0..DECK_COUNT {
+            self.light_led(i, color_out)?;
+        }
+
+        Ok(())
+    }
+
+    fn light_led(&self, i: usize, color_out: &mut dyn MidiSink) -> Result<()> {
+        let color = if [self.deck1, self.deck2, self.deck3][i] {
+            self.leds.deck_color(i, self.leds.filter_enabled)
+        } else {
+            self.leds.filter_disabled
+        };
+        Led::new(self.config.led_notes(&self.decks)[i]).set(color, color_out)
+    }
+
+    fn clear_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for note in self.config.led_notes(&self.decks) {
+            Led::new(note).set(LedColor::Off, color_out)?;
+        }
+
+        Ok(())
+    }
+
+    // Sends the decks in the configured order, either all of them or only those which changed.
+    fn send(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        for i in self.config.order() {
+            if self.config.send == FilterSend::All || self.sent[i] != Some(self.deck_data(i)) {
+                self.send_deck(i, out)?;
             }
         }
 
         Ok(())
     }
+
+    fn send_deck(&mut self, i: usize, out: &mut dyn MidiSink) -> Result<()> {
+        let data = self.deck_data(i);
+        self.sent[i] = Some(data);
+        if let Some(nrpn) = &self.nrpn {
+            let channel = nrpn.channel(FILTER_ENCODER_CHANNEL);
+            return nrpn::send(channel, nrpn.parameters[i], data, out);
+        }
+        let cc = self.decks.0[i].filter_cc;
+        log_send(FILTER_ENCODER_CHANNEL, CONTROL_CHANGE, cc, data as u8, out)
+    }
+
+    // What deck `i` is sent: the filter's value while engaged, neutral (63) while not, and in
+    // between while ramping. 14 bits with NRPN output.
+    fn deck_data(&self, i: usize) -> u16 {
+        let mix = self.mixes[i];
+        if self.nrpn.is_some() {
+            let value = self.scaling.apply_fine(self.state as f32);
+            nrpn::from_7bit(63.0 + (value - 63.0) * mix)
+        } else {
+            let value = self.scaling.apply(self.state) as f32;
+            (63.0 + (value - 63.0) * mix).round() as u16
+        }
+    }
 }
 
 impl Default for FilterEncoder {
@@ -190,61 +1010,364 @@ impl Default for FilterEncoder {
             deck2: false,
             deck3: false,
             state: 63,
+            throttle: Throttle::default(),
+            leds: LedConfig::default(),
+            config: FilterConfig::default(),
+            decks: Decks::default(),
+            scaling: OutputScaling::default(),
+            nrpn: None,
+            trims: [63; 3],
+            boosts: [0; 3],
+            toggles: [0; 3],
+            led_sent: [None; 3],
+            led_pending: [false; 3],
+            mixes: [0.0; 3],
+            ramps: [None; 3],
+            sent: [None; 3],
         }
     }
 }
 
 struct TempoEncoder {
+    decks: Decks,
     deck_index: usize,
-    deck1_value: u8,
-    deck2_value: u8,
-    deck3_value: u8,
+    // Each deck's tempo as a fader position, not rounded until it's sent, so that fine mode's
+    // movements within a step still add up.
+    deck1_value: f32,
+    deck2_value: f32,
+    deck3_value: f32,
     prev_value: u8,
+    range_config: Option<TempoRangeConfig>,
+    range_indices: [usize; 3],
+    blink: Option<Blink>,
+    leds: LedConfig,
+    scaling: OutputScaling,
+    nrpn: Option<NrpnConfig>,
+    selects: [u64; 3],
+    fine_config: Option<TempoFineConfig>,
+    fine: bool,
+    link_note: Option<u8>,
+    // Whether the fader drives every deck at once.
+    linked: bool,
+    // Decks whose next movement takes over straight away instead of waiting for pickup, which is
+    // only the first after startup.
+    armed: [bool; DECK_COUNT],
+    // What was last sent for each deck, so that a movement which doesn't change it isn't sent
+    // again. 14 bits with NRPN output.
+    sent: [Option<u16>; 3],
 }
 
 impl TempoEncoder {
-    fn select_deck(&mut self, note: u8, color_out: &mut MidiOutputConnection) -> Result<bool> {
-        let toggle_notes = [
-            DECK1_TEMPO_TOGGLE_NOTE,
-            DECK2_TEMPO_TOGGLE_NOTE,
-            DECK3_TEMPO_TOGGLE_NOTE,
-        ];
+    fn new(range_config: Option<TempoRangeConfig>, leds: LedConfig, deck_index: usize) -> Self {
+        Self {
+            deck_index,
+            leds,
+            range_indices: range_config
+                .as_ref()
+                .map_or([0; 3], TempoRangeConfig::initial_indices),
+            range_config,
+            ..Self::default()
+        }
+    }
 
-        if let Some(i) = toggle_notes.iter().position(|&x| x == note) {
-            self.deck_index = i;
+    // The selected deck's output CC and stored value.
+    fn deck_mut(&mut self) -> Result<(u8, &mut f32)> {
+        let cc = self.decks.0.get(self.deck_index).map(|deck| deck.tempo_cc);
+        match (self.deck_index, cc) {
+            (0, Some(cc)) => Ok((cc, &mut self.deck1_value)),
+            (1, Some(cc)) => Ok((cc, &mut self.deck2_value)),
+            (2, Some(cc)) => Ok((cc, &mut self.deck3_value)),
+            _ => Err("INTERNAL ERROR: Tempo deck index out of range".into()),
+        }
+    }
 
-            // Toggle lights for other decks.
-            for x in toggle_notes {
-                let message = if x == note { NOTE_ON } else { NOTE_OFF };
+    // Whether the fader drives the selected deck's tempo, rather than waiting to pass it for pickup.
+    fn caught(&self) -> bool {
+        let value = [self.deck1_value, self.deck2_value, self.deck3_value][self.deck_index];
+        self.linked || self.fine || self.armed[self.deck_index] || value == self.prev_value as f32
+    }
 
-                color_out.send(&[DEVICE_CHANNEL | message, x, 127])?;
-            }
+    fn select_deck(&mut self, note: u8, color_out: &mut dyn MidiSink) -> Result<bool> {
+        if let Some(i) = self.decks.tempo_deck(note) {
+            self.deck_index = i;
+            self.blink = None;
+            self.selects[i] += 1;
+            events::emit(Event::TempoDeck { deck: i + 1 });
+            self.light_selected(color_out)?;
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
-    fn adjust(&mut self, data: u8, out: &mut MidiOutputConnection) -> Result<()> {
-        let (cc, deck_value) = match self.deck_index {
-            0 => (DECK1_TEMPO_CC, &mut self.deck1_value),
-            1 => (DECK2_TEMPO_CC, &mut self.deck2_value),
-            2 => (DECK3_TEMPO_CC, &mut self.deck3_value),
-            _ => return Err("INTERNAL ERROR: Tempo deck index out of range".into()),
-        };
+    // Lights the selected deck's LED, and turns off the others.
+    fn light_selected(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for (i, deck) in self.decks.0.iter().enumerate() {
+            let note = deck.tempo_toggle_note;
+            let color = if i == self.deck_index {
+                self.leds.deck_color(i, self.leds.tempo_deck_selected)
+            } else {
+                self.leds.tempo_deck_unselected
+            };
 
-        // Pickup algorithm: Don't do anything until the new value has passed the stored value.
-        let prev_sign = (*deck_value).cmp(&self.prev_value);
-        self.prev_value = data;
-        if (*deck_value).cmp(&data) == prev_sign {
-            return Ok(());
+            Led::new(note).set(color, color_out)?;
+        }
+
+        if let Some(fine_config) = &self.fine_config {
+            let color = if self.fine {
+                self.leds.tempo_fine_on
+            } else {
+                self.leds.tempo_fine_off
+            };
+            Led::new(fine_config.note).set(color, color_out)?;
+        }
+
+        if let Some(link_note) = self.link_note {
+            let color = if self.linked {
+                self.leds.tempo_link_on
+            } else {
+                self.leds.tempo_link_off
+            };
+            Led::new(link_note).set(color, color_out)?;
+        }
+
+        Ok(())
+    }
+
+    fn toggle_link(&mut self, note: u8, state: bool, color_out: &mut dyn MidiSink) -> Result<bool> {
+        if self.link_note != Some(note) {
+            return Ok(false);
+        }
+
+        if state {
+            self.linked = !self.linked;
+            self.light_selected(color_out)?;
+        }
+        Ok(true)
+    }
+
+    fn toggle_fine(&mut self, note: u8, state: bool, color_out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(fine_config) = self.fine_config else {
+            return Ok(false);
+        };
+        if note != fine_config.note {
+            return Ok(false);
+        }
+
+        if fine_config.momentary {
+            self.fine = state;
+        } else if state {
+            self.fine = !self.fine;
+        }
+        self.light_selected(color_out)?;
+        Ok(true)
+    }
+
+    // Re-sends every deck's tempo, and the deck selection LEDs.
+    fn sync(&mut self, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        for i in 0..DECK_COUNT {
+            self.send(i, out)?;
+        }
+
+        self.light_selected(color_out)
+    }
+
+    fn send(&mut self, deck_index: usize, out: &mut dyn MidiSink) -> Result<()> {
+        let value = [self.deck1_value, self.deck2_value, self.deck3_value][deck_index];
+        events::emit(Event::Tempo {
+            deck: deck_index + 1,
+            value: value.round() as u8,
+        });
+
+        let data = self.data(deck_index);
+        self.sent[deck_index] = Some(data);
+        if let Some(nrpn) = &self.nrpn {
+            let channel = nrpn.channel(TEMPO_ENCODER_CHANNEL);
+            return nrpn::send(channel, nrpn.parameters[deck_index], data, out);
+        }
+        let cc = self.decks.0[deck_index].tempo_cc;
+        log_send(TEMPO_ENCODER_CHANNEL, CONTROL_CHANGE, cc, data as u8, out)
+    }
+
+    // Sends a deck's tempo, unless it would send the same as last time.
+    fn send_changed(&mut self, deck_index: usize, out: &mut dyn MidiSink) -> Result<()> {
+        if self.sent[deck_index] != Some(self.data(deck_index)) {
+            self.send(deck_index, out)?;
+        }
+        Ok(())
+    }
+
+    // What's sent for a deck's tempo. 14 bits with NRPN output, which is fine enough to show
+    // movement within a step.
+    fn data(&self, deck_index: usize) -> u16 {
+        let value = [self.deck1_value, self.deck2_value, self.deck3_value][deck_index];
+        if self.nrpn.is_some() {
+            let data = self.scaled_fine(deck_index, value).clamp(0.0, 127.0);
+            return nrpn::from_7bit(self.scaling.apply_fine(127.0 - data));
         }
-        *deck_value = data;
 
         // Inverting the value of this, since I'm used to the Rekordbox controls where up =
         // slower, down = faster.
-        log_send(TEMPO_ENCODER_CHANNEL, CONTROL_CHANGE, cc, 127 - data, out)?;
+        let data = self.scaled(deck_index, value);
+        self.scaling.apply(127 - data) as u16
+    }
+
+    fn adjust(&mut self, data: u8, out: &mut dyn MidiSink) -> Result<()> {
+        // A malformed data byte would otherwise underflow when inverted below.
+        let data = data.min(127);
+        let prev_value = self.prev_value;
+        self.prev_value = data;
+        let fine_factor = self.fine_config.filter(|_| self.fine).map(|x| x.factor);
+        let linked = self.linked;
+        // Fine mode never jumps, so it leaves the arm for the first coarse movement.
+        let first_touch = fine_factor.is_none() && std::mem::take(&mut self.armed[self.deck_index]);
+        let (_, deck_value) = self.deck_mut()?;
+
+        if let Some(factor) = fine_factor {
+            // The fader moves the tempo relative to where it is, so nothing jumps. Once back in
+            // coarse mode, the fader has to be brought back past the deck's tempo as usual.
+            let movement = (data as f32 - prev_value as f32) * factor;
+            *deck_value = (*deck_value + movement).clamp(0.0, 127.0);
+        } else if linked || first_touch {
+            // The decks' tempos may well differ when linking, and pickup can only follow one of
+            // them, so the fader takes over straight away. Likewise for the first movement after
+            // startup if armed, as the fader is unlikely to be where the deck's tempo is anyway.
+            *deck_value = data as f32;
+        } else {
+            // Pickup algorithm: Don't do anything until the new value has passed the stored value.
+            let prev_sign = deck_value.total_cmp(&(prev_value as f32));
+            if deck_value.total_cmp(&(data as f32)) == prev_sign {
+                return Ok(());
+            }
+            *deck_value = data as f32;
+        }
+        let value = *deck_value;
+
+        if !linked {
+            return self.send_changed(self.deck_index, out);
+        }
+
+        // Every deck keeps the value, so unlinking leaves them consistent.
+        self.deck1_value = value;
+        self.deck2_value = value;
+        self.deck3_value = value;
+        self.armed = [false; DECK_COUNT];
+        for i in 0..DECK_COUNT {
+            self.send_changed(i, out)?;
+        }
+        Ok(())
+    }
+
+    // Maps a fader position onto the given deck's tempo range.
+    fn scaled(&self, deck_index: usize, data: f32) -> u8 {
+        self.scaled_fine(deck_index, data).round().clamp(0.0, 127.0) as u8
+    }
+
+    // Like `scaled`, but neither rounded nor clamped.
+    fn scaled_fine(&self, deck_index: usize, data: f32) -> f32 {
+        let Some(config) = &self.range_config else {
+            return data;
+        };
+        let range = config.ranges[self.range_indices[deck_index]];
+        63.5 + (data - 63.5) * config.fader_range / range
+    }
+
+    // Snaps a deck's tempo back to 0% on a press of its reset note, and blinks the deck's LED to
+    // confirm. The fader then has to be brought back past the middle before it takes over again.
+    fn reset_tempo(
+        &mut self,
+        note: u8,
+        state: bool,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        let Some(i) = self.decks.tempo_reset_deck(note) else {
+            return Ok(false);
+        };
+        if !state {
+            return Ok(true);
+        }
+
+        // Halfway between the fader's two middle steps, so pickup can't catch it without crossing.
+        *[
+            &mut self.deck1_value,
+            &mut self.deck2_value,
+            &mut self.deck3_value,
+        ][i] = 63.5;
+        self.armed[i] = false;
+        self.send(i, out)?;
 
+        let (selected, unselected) = (
+            self.leds.tempo_deck_selected,
+            self.leds.tempo_deck_unselected,
+        );
+        let (current, other) = if i == self.deck_index {
+            (selected, unselected)
+        } else {
+            (unselected, selected)
+        };
+        let led = Led::new(self.decks.0[i].tempo_toggle_note);
+        self.blink = Some(Blink::new(led, current, other, 1, now));
+        self.tick(now, color_out)?;
+        Ok(true)
+    }
+
+    fn cycle_range(
+        &mut self,
+        note: u8,
+        state: bool,
+        release: ReleasePolicy,
+        now: Instant,
+        out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        let Some(config) = self.range_config.clone() else {
+            return Ok(false);
+        };
+        if note != config.cycle_note {
+            return Ok(false);
+        }
+
+        let out_note = TEMPO_RANGE_OUT_NOTE_BASE + self.deck_index as u8;
+        if !state {
+            send_button(TEMPO_ENCODER_CHANNEL, out_note, 0, false, release, out)?;
+            return Ok(true);
+        }
+
+        let index = &mut self.range_indices[self.deck_index];
+        *index = (*index + 1) % config.ranges.len();
+        let range = config.ranges[*index];
+        let blink_count = *index as u32 + 1;
+
+        // Rekordbox keeps the current tempo if it's within the new range, and clamps it otherwise.
+        // Rather than sending anything, re-anchor the pickup to where the fader would be for that.
+        let (_, deck_value) = self.deck_mut()?;
+        let percent = ((*deck_value - 63.5) / 63.5 * config.fader_range).clamp(-range, range);
+        *deck_value = (63.5 + percent / config.fader_range * 63.5)
+            .round()
+            .clamp(0.0, 127.0);
+
+        log_send(TEMPO_ENCODER_CHANNEL, NOTE_ON, out_note, 127, out)?;
+
+        // Blink the deck's LED once per step through the ranges, to show which is selected.
+        let toggle_note = self.decks.0[self.deck_index].tempo_toggle_note;
+        self.blink = Some(Blink::new(
+            Led::new(toggle_note),
+            self.leds.tempo_deck_selected,
+            self.leds.tempo_deck_unselected,
+            blink_count,
+            now,
+        ));
+
+        Ok(true)
+    }
+
+    fn tick(&mut self, now: Instant, color_out: &mut dyn MidiSink) -> Result<()> {
+        if let Some(blink) = &mut self.blink {
+            if !blink.tick(now, color_out)? {
+                self.blink = None;
+            }
+        }
         Ok(())
     }
 
@@ -252,8 +1375,9 @@ impl TempoEncoder {
         &self,
         note: u8,
         state: bool,
-        out: &mut MidiOutputConnection,
-        color_out: &mut MidiOutputConnection,
+        release: ReleasePolicy,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
     ) -> Result<bool> {
         let out_note = match note {
             PB_UP_IN_NOTE => PB_UP_OUT_NOTE_BASE,
@@ -263,190 +1387,2553 @@ impl TempoEncoder {
 
         // Send the fake note to rekordbox, and the color to the device.
         if state {
-            color_out.send(&[DEVICE_CHANNEL | NOTE_ON, note, 127])?;
+            Led::new(note).set(self.leds.pitch_bend_pressed, color_out)?;
             log_send(TEMPO_ENCODER_CHANNEL, NOTE_ON, out_note, 127, out)?;
         } else {
-            color_out.send(&[DEVICE_CHANNEL | NOTE_OFF, note, 127])?;
-            log_send(TEMPO_ENCODER_CHANNEL, NOTE_ON, out_note, 0, out)?;
+            Led::new(note).set(self.leds.pitch_bend_released, color_out)?;
+            send_button(TEMPO_ENCODER_CHANNEL, out_note, 0, false, release, out)?;
         }
 
         Ok(true)
     }
+
+    fn clear_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        let toggle_notes = self.decks.0.iter().map(|deck| deck.tempo_toggle_note);
+        for note in toggle_notes.chain([PB_DOWN_IN_NOTE, PB_UP_IN_NOTE]) {
+            Led::new(note).set(LedColor::Off, color_out)?;
+        }
+        if let Some(fine_config) = &self.fine_config {
+            Led::new(fine_config.note).set(LedColor::Off, color_out)?;
+        }
+        if let Some(link_note) = self.link_note {
+            Led::new(link_note).set(LedColor::Off, color_out)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for TempoEncoder {
     fn default() -> Self {
         Self {
+            decks: Decks::default(),
             deck_index: 0,
-            deck1_value: 63,
-            deck2_value: 63,
-            deck3_value: 63,
+            deck1_value: 63.0,
+            deck2_value: 63.0,
+            deck3_value: 63.0,
             prev_value: 63,
+            range_config: None,
+            range_indices: [0; 3],
+            blink: None,
+            leds: LedConfig::default(),
+            scaling: OutputScaling::default(),
+            nrpn: None,
+            selects: [0; 3],
+            fine_config: None,
+            fine: false,
+            sent: [None; 3],
+            link_note: None,
+            linked: false,
+            armed: [false; DECK_COUNT],
         }
     }
 }
 
+// Every continuous value and toggle in State, which can be recalled later.
+#[derive(Clone, Default)]
+struct StateSnapshot {
+    pots: HashMap<u8, f32>,
+    filter_decks: [bool; 3],
+    filter_state: u8,
+    tempo_deck_index: usize,
+    tempo_values: [f32; 3],
+    fx: Option<(usize, Vec<Vec<u8>>)>,
+    banks: Vec<(usize, Vec<Vec<u8>>)>,
+}
+
+// A snapshot slot, stored and recalled by pads.
+struct SnapshotPads {
+    config: SnapshotPadConfig,
+    leds: LedConfig,
+    snapshot: Option<StateSnapshot>,
+}
+
+impl SnapshotPads {
+    fn light_led(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        let color = if self.snapshot.is_some() {
+            self.leds.snapshot_stored
+        } else {
+            self.leds.snapshot_empty
+        };
+        Led::new(self.config.recall_note).set(color, color_out)
+    }
+
+    fn clear_led(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        Led::new(self.config.recall_note).set(LedColor::Off, color_out)
+    }
+}
+
 struct State {
-    headphones_mix: FakePotEncoder,
-    headphones_volume: FakePotEncoder,
-    master_volume: FakePotEncoder,
+    // Keyed by input CC.
+    pots: HashMap<u8, FakePotEncoder>,
+    controls: HashMap<u8, ControlConfig>,
+    button_channels: HashMap<u8, u8>,
+    // Keyed by input note, with the policy for every other button.
+    release_policies: HashMap<u8, ReleasePolicy>,
+    release_policy: ReleasePolicy,
+    faders: HashMap<u8, FaderCurve>,
     filter_encoder: FilterEncoder,
     tempo_encoder: TempoEncoder,
+    fx_section: Option<FxSection>,
+    banks: Vec<FocusedBank>,
+    pad_row: Option<PadRow>,
+    buttons: Vec<ButtonWithFeedback>,
+    snapshot_pads: Vec<SnapshotPads>,
+    browse: Option<BrowseEncoder>,
+    key_control: Option<KeyControl>,
+    talkover: Option<Talkover>,
+    repeat_buttons: Vec<RepeatButton>,
+    decks: Decks,
+    fake_buttons: FakeButtonConfig,
+    target: Target,
+    relative_mode: RelativeMode,
+    velocity_curve: VelocityCurve,
+    input: InputConfig,
+    last_message: Option<([u8; 3], Instant)>,
+    panic_note: Option<u8>,
+    resync_note: Option<u8>,
+    resync_probe: Option<ResyncProbe>,
+    // When everything was last re-sent, and whether the probe has asked for it to be again.
+    last_resync: Option<Instant>,
+    resync_pending: bool,
+    note_map: HashMap<u8, u8>,
+    shift: Option<ShiftConfig>,
+    shift_active: bool,
+    // The note each held physical note was pressed as, so it's released as the same one even if
+    // shift changed in between.
+    held_notes: HashMap<u8, u8>,
+    // Notes sent to Rekordbox which it's still holding.
+    rekordbox_held: HeldNotes,
+    aftertouch: HashMap<u8, AftertouchConfig>,
+    program_changes: HashMap<u8, ProgramChangeConfig>,
+    pitch_bend: HashMap<u8, PitchBendConfig>,
+    feedback_rules: Vec<FeedbackRule>,
+    meters: Vec<Meter>,
+    clock_rules: Vec<ClockRule>,
+    // Whether external gear was last started, so that repeated feedback doesn't restart it.
+    clock_running: bool,
+    // The port messages for Rekordbox on each routed channel go to instead, keyed by channel.
+    routes: HashMap<u8, String>,
+    // Covers everything sent to the device, from either direction.
+    echo_guard: EchoGuard,
+    keepalive: Option<Duration>,
+    // When the device last sent anything, or was last sent a keepalive.
+    last_activity: Instant,
+    // Messages which fell through to being forwarded as-is, in total and by status and code.
+    verbatim_count: u64,
+    // Relative values no control understood, and CCs no control handled.
+    unknown_values: u64,
+    unhandled_ccs: u64,
+    unrecognized: HashMap<(u8, Option<u8>), u64>,
+    // The state at startup, which a panic resets to.
+    defaults: StateSnapshot,
 }
 
 impl State {
-    fn new() -> Self {
-        Self {
-            headphones_mix: FakePotEncoder::default(),
-            headphones_volume: FakePotEncoder::default(),
-            master_volume: FakePotEncoder::default(),
-            filter_encoder: FilterEncoder::default(),
-            tempo_encoder: TempoEncoder::default(),
-        }
+    fn new(config: &Config, target: Target) -> Self {
+        let mut state = Self {
+            pots: config
+                .pot_controls()
+                .iter()
+                .map(|pot| {
+                    let scaling = config.output.pots.get(&pot.cc).copied().unwrap_or_default();
+                    let pot_encoder =
+                        FakePotEncoder::new(pot, config.throttle.pots, scaling, &config.leds);
+                    (pot.cc, pot_encoder)
+                })
+                .collect(),
+            controls: config.controls.clone(),
+            button_channels: config.button_channels.clone(),
+            release_policies: config.release_policies.clone(),
+            release_policy: config.release_policy,
+            faders: config
+                .faders
+                .iter()
+                .map(|fader| (fader.cc, FaderCurve::new(fader.clone())))
+                .collect(),
+            filter_encoder: FilterEncoder {
+                throttle: Throttle::new(config.throttle.filter),
+                leds: config.leds,
+                config: config.filter,
+                decks: config.decks,
+                scaling: config.output.filter,
+                nrpn: config.output.filter_nrpn,
+                trims: [config
+                    .filter
+                    .makeup
+                    .map_or(63, |makeup| makeup.default_trim); DECK_COUNT],
+                ..FilterEncoder::default()
+            },
+            tempo_encoder: TempoEncoder {
+                decks: config.decks,
+                scaling: config.output.tempo,
+                nrpn: config.output.tempo_nrpn,
+                fine_config: config.tempo_fine,
+                link_note: config.tempo_link_note,
+                armed: [config.tempo_arm_on_first_touch; DECK_COUNT],
+                ..TempoEncoder::new(
+                    config.tempo_range.clone(),
+                    config.leds,
+                    config.initial_tempo_deck - 1,
+                )
+            },
+            fx_section: config.fx.clone().map(|fx| {
+                let releases = fx.buttons.iter().map(|&x| config.release_policy(x));
+                FxSection::new(fx.clone(), config.leds, releases.collect())
+            }),
+            banks: config
+                .banks
+                .iter()
+                .map(|bank| FocusedBank::new(bank.clone(), config.leds))
+                .collect(),
+            pad_row: config.pad_row.map(|pad_row| {
+                let releases = pad_row.notes.map(|x| config.release_policy(x));
+                PadRow::new(pad_row, config.leds, releases)
+            }),
+            buttons: config
+                .buttons
+                .iter()
+                .map(|&button| {
+                    let release = config.release_policy(button.note);
+                    ButtonWithFeedback::new(button, config.leds, release)
+                })
+                .collect(),
+            snapshot_pads: config
+                .snapshot_pads
+                .iter()
+                .map(|&pads| SnapshotPads {
+                    config: pads,
+                    leds: config.leds,
+                    snapshot: None,
+                })
+                .collect(),
+            browse: config.browse.map(BrowseEncoder::new),
+            key_control: config
+                .key_control
+                .map(|key_control| KeyControl::new(key_control, config.leds)),
+            talkover: config.talkover.map(|talkover| {
+                let release = config.release_policy(talkover.out_note);
+                Talkover::new(talkover, config.leds, release)
+            }),
+            repeat_buttons: config
+                .repeat_buttons
+                .iter()
+                .map(|&button| RepeatButton::new(button, config.release_policy(button.note)))
+                .collect(),
+            decks: config.decks,
+            fake_buttons: config.fake_buttons,
+            target,
+            relative_mode: config.mixxx.relative_mode,
+            velocity_curve: config.velocity_curve.clone(),
+            input: config.input.clone(),
+            last_message: None,
+            panic_note: config.panic_note,
+            resync_note: config.resync_note,
+            resync_probe: config.resync_probe,
+            last_resync: None,
+            resync_pending: false,
+            note_map: config.note_map.clone(),
+            shift: config.shift.clone(),
+            shift_active: false,
+            held_notes: HashMap::new(),
+            rekordbox_held: HeldNotes::new(
+                (config.stuck_note_secs > 0).then(|| Duration::from_secs(config.stuck_note_secs)),
+                config.unreleased_notes(),
+            ),
+            aftertouch: config.aftertouch.clone(),
+            program_changes: config.program_changes.clone(),
+            pitch_bend: config.pitch_bend.clone(),
+            feedback_rules: config.feedback.clone(),
+            meters: config
+                .meters
+                .iter()
+                .map(|&meter| Meter::new(meter))
+                .collect(),
+            clock_rules: config.clock.clone(),
+            routes: config.routes(),
+            clock_running: false,
+            echo_guard: EchoGuard::new(Duration::from_millis(config.echo_window_ms)),
+            keepalive: (config.keepalive_secs > 0)
+                .then(|| Duration::from_secs(config.keepalive_secs)),
+            last_activity: Instant::now(),
+            verbatim_count: 0,
+            unknown_values: 0,
+            unhandled_ccs: 0,
+            unrecognized: HashMap::new(),
+            defaults: StateSnapshot::default(),
+        };
+        state.defaults = state.snapshot();
+        state
     }
 
     fn transform(
         &mut self,
         message: &[u8],
-        out: &mut MidiOutputConnection,
-        color_out: &mut MidiOutputConnection,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
     ) -> Result<()> {
+        self.last_activity = now;
+
+        // Checked before anything else, so no control ever sees a message on an excluded channel.
+        if let Some(&status) = message.first() {
+            if (NOTE_OFF..0xF0).contains(&status) && !self.input.accepts(status & 0x0F) {
+                return match self.input.excluded_channels {
+                    ChannelPolicy::Forward => self.forward_verbatim(message, out),
+                    ChannelPolicy::Drop => {
+                        status::message(format_args!("Dropped {:?} (EXCLUDED CHANNEL)", message));
+                        Ok(())
+                    }
+                };
+            }
+        }
+
+        // A data byte with the high bit set would otherwise be read as a huge value by the controls.
+        if is_malformed(message) {
+            status::message(format_args!(
+                "Malformed {:?} (DATA BYTE OUT OF RANGE)",
+                message
+            ));
+            return self.forward_verbatim(message, out);
+        }
+
+        // Calibrated before anything else, so that every control and anything forwarded gets the
+        // full range.
+        let calibrated;
+        let message = match *message {
+            [status, cc, data] if status & 0xF0 == CONTROL_CHANGE => {
+                calibrated = [status, cc, self.input.scale(cc, data)];
+                &calibrated[..]
+            }
+            _ => message,
+        };
+
+        // Likewise, so that the rest of the mapping only ever sees the shifted control.
+        let shifted;
+        let shift_note = self.shift.as_ref().map(|shift| shift.note);
+        let message = match *message {
+            [status, code, data]
+                if matches!(status & 0xF0, CONTROL_CHANGE | NOTE_ON | NOTE_OFF) =>
+            {
+                let kind = status & 0xF0;
+                if kind != CONTROL_CHANGE && Some(code) == shift_note {
+                    self.shift_active = kind == NOTE_ON && data > 0;
+                    return Ok(());
+                }
+                shifted = [status, self.shifted(kind, code, data), data];
+                &shifted[..]
+            }
+            _ => message,
+        };
+
+        if let [status, data] = *message {
+            let handled = match status & 0xF0 {
+                CHANNEL_PRESSURE => self.handle_aftertouch(status & 0x0F, data, out)?,
+                PROGRAM_CHANGE => self.handle_program_change(status & 0x0F, data, out)?,
+                _ => false,
+            };
+            if handled {
+                return Ok(());
+            }
+        }
+
         if message.len() == 3 {
-            match message[0] & !DEVICE_CHANNEL {
+            let kind = message[0] & 0xF0;
+            let channel = message[0] & 0x0F;
+            if matches!(kind, CONTROL_CHANGE | NOTE_ON | NOTE_OFF) {
+                if !self.input.listens(kind, message[1], channel) {
+                    return match self.input.other_channels {
+                        ChannelPolicy::Forward => self.forward_verbatim(message, out),
+                        ChannelPolicy::Drop => {
+                            status::message(format_args!(
+                                "Dropped {:?} (UNMAPPED CHANNEL)",
+                                message
+                            ));
+                            Ok(())
+                        }
+                    };
+                }
+
+                if self.is_duplicate([message[0], message[1], message[2]], now) {
+                    status::message(format_args!("Dropped {:?} (DUPLICATE)", message));
+                    return Ok(());
+                }
+            }
+
+            if kind != CONTROL_CHANGE && Some(message[1]) == self.panic_note {
+                if kind == NOTE_ON && message[2] > 0 {
+                    self.panic(out, color_out)?;
+                }
+                return Ok(());
+            }
+
+            if kind != CONTROL_CHANGE && Some(message[1]) == self.resync_note {
+                if kind == NOTE_ON && message[2] > 0 {
+                    self.resync(now, out, color_out)?;
+                }
+                return Ok(());
+            }
+
+            match kind {
+                PITCH_BEND if self.handle_pitch_bend(channel, message[1], message[2], out)? => {
+                    return Ok(());
+                }
                 CONTROL_CHANGE => {
-                    if self.handle_cc(message[1], message[2], out)? {
+                    if self.handle_cc(message[1], message[2], now, out)? {
                         return Ok(());
                     }
+                    self.unhandled_ccs += 1;
+                    match self.input.unknown_ccs {
+                        UnknownCcPolicy::Forward => (),
+                        UnknownCcPolicy::Drop => {
+                            status::message(format_args!("Dropped {:?} (UNMAPPED CC)", message));
+                            return Ok(());
+                        }
+                        UnknownCcPolicy::Log => status::message(format_args!(
+                            "Unmapped CC {} on channel {} (value {})",
+                            message[1], channel, message[2]
+                        )),
+                    }
                 }
                 state @ (NOTE_ON | NOTE_OFF) => {
-                    let state = state == NOTE_ON;
+                    // A note on with velocity 0 is a release, the same as a note off.
+                    let state = state == NOTE_ON && message[2] > 0;
+                    let release = self
+                        .release_policies
+                        .get(&message[1])
+                        .copied()
+                        .unwrap_or(self.release_policy);
+                    let mapped_note = self
+                        .note_map
+                        .get(&message[1])
+                        .copied()
+                        .unwrap_or(message[1]);
+                    for pot_encoder in self.pots.values_mut() {
+                        if pot_encoder.reset(message[1], state, now, out, color_out)? {
+                            return Ok(());
+                        }
+                    }
+
                     if self
                         .filter_encoder
-                        .toggle(message[1], state, out, color_out)?
+                        .toggle(mapped_note, state, now, out, color_out)?
+                    {
+                        if !state && self.filter_encoder.config.forward_release {
+                            self.forward_verbatim(message, out)?;
+                        }
+                        return Ok(());
+                    }
+
+                    if self.tempo_encoder.select_deck(mapped_note, color_out)?
+                        || self
+                            .tempo_encoder
+                            .toggle_fine(message[1], state, color_out)?
+                        || self
+                            .tempo_encoder
+                            .toggle_link(message[1], state, color_out)?
                     {
                         return Ok(());
                     }
 
-                    if self.tempo_encoder.select_deck(message[1], color_out)? {
+                    if self
+                        .tempo_encoder
+                        .reset_tempo(message[1], state, now, out, color_out)?
+                    {
+                        return Ok(());
+                    }
+
+                    if self
+                        .tempo_encoder
+                        .cycle_range(message[1], state, release, now, out)?
+                    {
                         return Ok(());
                     }
 
                     if self
                         .tempo_encoder
-                        .handle_button(message[1], state, out, color_out)?
+                        .handle_button(message[1], state, release, out, color_out)?
                     {
                         return Ok(());
                     }
 
-                    return handle_button(message[1], message[2], out);
+                    if let Some(browse) = &mut self.browse {
+                        if browse.handle_button(message[1], state, now, out)? {
+                            return Ok(());
+                        }
+                    }
+
+                    for button in &mut self.repeat_buttons {
+                        if button.handle_button(message[1], state, now, out)? {
+                            return Ok(());
+                        }
+                    }
+
+                    if let Some(key_control) = &mut self.key_control {
+                        if key_control.handle_button(message[1], state, now, out, color_out)? {
+                            return Ok(());
+                        }
+                    }
+
+                    if let Some(talkover) = &mut self.talkover {
+                        if talkover.handle_button(message[1], state, now, out, color_out)? {
+                            return Ok(());
+                        }
+                    }
+
+                    if let Some(pad_row) = &mut self.pad_row {
+                        if pad_row.handle_button(message[1], state, out, color_out)? {
+                            return Ok(());
+                        }
+                    }
+
+                    for button in &self.buttons {
+                        if button.handle_button(message[1], state, out)? {
+                            return Ok(());
+                        }
+                    }
+
+                    if self.handle_snapshot_pad(message[1], state, out, color_out)? {
+                        return Ok(());
+                    }
+
+                    for bank in &mut self.banks {
+                        if bank.select_deck(message[1], color_out)? {
+                            return Ok(());
+                        }
+                    }
+
+                    if let Some(fx_section) = &mut self.fx_section {
+                        if fx_section.select_unit(message[1], color_out)?
+                            || fx_section.handle_button(message[1], state, out)?
+                        {
+                            return Ok(());
+                        }
+                    }
+
+                    let channel = self
+                        .button_channels
+                        .get(&mapped_note)
+                        .copied()
+                        .unwrap_or(DEVICE_CHANNEL);
+                    return handle_button(
+                        channel,
+                        mapped_note,
+                        message[2],
+                        state,
+                        release,
+                        self.target,
+                        &self.velocity_curve,
+                        out,
+                    );
                 }
                 _ => (),
             }
         }
 
         // If the handling above fails, just forward the message as-is.
+        self.forward_verbatim(message, out)
+    }
+
+    fn forward_verbatim(&mut self, message: &[u8], out: &mut dyn MidiSink) -> Result<()> {
         out.send(message)?;
-        println!("PartySaver->RekordBox: {:?} (VERBATIM)", message);
+
+        let Some(&status) = message.first() else {
+            return Ok(());
+        };
+        self.verbatim_count += 1;
+        // Bounded, so that a device sending every possible message can't grow it forever. Once
+        // full, messages not yet seen aren't counted (or sampled) individually.
+        let key = (status, message.get(1).copied());
+        let count =
+            if self.unrecognized.len() < MAX_UNRECOGNIZED || self.unrecognized.contains_key(&key) {
+                let count = self.unrecognized.entry(key).or_default();
+                *count += 1;
+                Some(*count)
+            } else {
+                None
+            };
+
+        match (logging::verbatim(), count) {
+            (VerbatimLog::Off, _) => (),
+            (VerbatimLog::Sample, Some(count)) if !is_sampled(count) => (),
+            (VerbatimLog::Sample, Some(count)) => status::message(format_args!(
+                "PartySaver->RekordBox: {} (VERBATIM, {} so far)",
+                logging::format(message),
+                count
+            )),
+            (VerbatimLog::Sample, None) | (VerbatimLog::All, _) => status::message(format_args!(
+                "PartySaver->RekordBox: {} (VERBATIM)",
+                logging::format(message)
+            )),
+        }
+        session_log::traffic(format_args!(
+            "PartySaver->RekordBox: {:?} (VERBATIM)",
+            message
+        ));
         Ok(())
     }
 
-    fn handle_cc(&mut self, cc: u8, data: u8, out: &mut MidiOutputConnection) -> Result<bool> {
-        let pot_encoder = match cc {
-            HEADPHONE_MIX_CC => &mut self.headphones_mix,
-            HEADPHONE_VOLUME_CC => &mut self.headphones_volume,
-            MASTER_VOLUME_CC => &mut self.master_volume,
-            DECK1_LOOP_CC | DECK2_LOOP_CC | DECK3_LOOP_CC => {
-                handle_fake_button(cc, data, out)?;
+    // The CC or note a physical control is treated as, in the layer it belongs to. Encoder and
+    // fader movement goes to whichever layer is active when it arrives, whereas a note is released
+    // in the layer it was pressed in, so nothing is left held by shift changing in between.
+    fn shifted(&mut self, kind: u8, code: u8, data: u8) -> u8 {
+        let Some(shift) = &self.shift else {
+            return code;
+        };
+        let layer = if kind == CONTROL_CHANGE {
+            &shift.ccs
+        } else {
+            &shift.notes
+        };
+        let current = match self.shift_active {
+            true => layer.get(&code).copied().unwrap_or(code),
+            false => code,
+        };
+
+        match kind {
+            CONTROL_CHANGE => current,
+            NOTE_ON if data > 0 => {
+                self.held_notes.insert(code, current);
+                current
+            }
+            _ => self.held_notes.remove(&code).unwrap_or(current),
+        }
+    }
+
+    // Whether this message repeats the previous one within the dedupe window.
+    fn is_duplicate(&mut self, message: [u8; 3], now: Instant) -> bool {
+        let window = self.input.dedupe_window();
+        let duplicate = self
+            .last_message
+            .is_some_and(|(last, at)| last == message && now.duration_since(at) < window);
+        self.last_message = Some((message, now));
+        duplicate
+    }
+
+    fn handle_cc(
+        &mut self,
+        cc: u8,
+        data: u8,
+        now: Instant,
+        out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        if self.target == Target::Mixxx
+            && (self
+                .pots
+                .get(&cc)
+                .is_some_and(|pot_encoder| pot_encoder.mode == PotMode::Relative)
+                || self.decks.loop_deck(cc).is_some()
+                || matches!(self.controls.get(&cc), Some(ControlConfig::Button { .. })))
+        {
+            handle_relative(cc, data, self.relative_mode, out)?;
+            return Ok(true);
+        }
+
+        match self.controls.get(&cc) {
+            Some(&ControlConfig::Absolute { out_cc, channel }) => {
+                let channel = channel.unwrap_or(DEVICE_CHANNEL);
+                log_send(channel, CONTROL_CHANGE, out_cc.unwrap_or(cc), data, out)?;
                 return Ok(true);
             }
+            Some(&ControlConfig::Button {
+                note,
+                up_channel,
+                down_channel,
+            }) => {
+                let channels = ControlConfig::button_channels(up_channel, down_channel);
+                if !handle_fake_button(cc, note, data, &self.fake_buttons, channels, out)? {
+                    self.unknown_values += 1;
+                }
+                return Ok(true);
+            }
+            Some(ControlConfig::Pot) | None => (),
+        }
+
+        if let Some(deck) = self.decks.loop_deck(cc) {
+            if !handle_fake_button(
+                cc,
+                deck.loop_out_note,
+                data,
+                &self.fake_buttons,
+                FAKE_BUTTON_CHANNELS,
+                out,
+            )? {
+                self.unknown_values += 1;
+            }
+            return Ok(true);
+        }
+
+        if self.filter_encoder.trim(cc, data, out)? {
+            return Ok(true);
+        }
+
+        match cc {
             FILTER_CC => {
-                self.filter_encoder.adjust(data, out)?;
+                self.filter_encoder.adjust(data, now, out)?;
                 return Ok(true);
             }
             TEMPO_CC => {
                 self.tempo_encoder.adjust(data, out)?;
                 return Ok(true);
             }
-            _ => return Ok(false),
-        };
+            _ => (),
+        }
 
-        pot_encoder.add(data);
-        pot_encoder.send(cc, out)?;
-        Ok(true)
-    }
-}
+        if let Some(fader) = self.faders.get_mut(&cc) {
+            fader.handle(data, out)?;
+            return Ok(true);
+        }
 
-fn main() -> Result<()> {
-    // First, connect to an actual device.
-    let device_in = MidiInput::new("PartySaver device in")?;
-    let device_in_port = select_port(&device_in, "input")?;
-    println!();
-    let passthrough_device_out = MidiOutput::new("PartySaver device out")?;
-    let device_out_port = select_port(&passthrough_device_out, "output")?;
-    println!();
+        if let Some(browse) = &self.browse {
+            if let Some(understood) = browse.handle_cc(cc, data, &self.fake_buttons, out)? {
+                if !understood {
+                    self.unknown_values += 1;
+                }
+                return Ok(true);
+            }
+        }
 
-    println!("Opening connections");
+        if let Some(fx_section) = &mut self.fx_section {
+            if fx_section.adjust(cc, data, out)? {
+                return Ok(true);
+            }
+        }
 
-    // Transform messages from the device to Rekordbox.
-    let mut color_out =
-        MidiOutput::new("PartySaver color out")?.connect(&device_out_port, "party-saver-color")?;
-    let mut rb_out = MidiOutput::new("Rekordbox Out")?.create_virtual("PartySaver")?;
-    let _conn_in = device_in.connect(
-        &device_in_port,
-        "party-saver",
-        move |stamp, message, state| {
-            println!(
-                "Device->PartySaver {}: {:?} (len={})",
-                stamp,
-                message,
-                message.len()
-            );
-            state
-                .transform(message, &mut rb_out, &mut color_out)
-                .unwrap_or_else(|e| {
-                    println!("Failed to forward MIDI message to main thread: {}", e)
-                });
-        },
-        State::new(),
-    )?;
+        for bank in &mut self.banks {
+            if bank.adjust(cc, data, out)? {
+                return Ok(true);
+            }
+        }
 
-    // Forward all messages from rekordbox straight to the device.
-    let mut passthrough_conn_out =
-        passthrough_device_out.connect(&device_out_port, "party-saver")?;
-    let _rb_in = MidiInput::new("Rekordbox In")?.create_virtual(
-        "PartySaver",
-        move |stamp, message, _| {
-            passthrough_conn_out
-                .send(message)
-                .unwrap_or_else(|_| println!("Error when forwarding message ..."));
-            println!(
-                "Rekordbox->Device {}: {:?} (len = {})",
-                stamp,
-                message,
-                message.len()
-            );
-        },
-        (),
-    )?;
+        let Some(pot_encoder) = self.pots.get_mut(&cc) else {
+            return Ok(false);
+        };
+        let previous = pot_encoder.value;
+        let understood = pot_encoder.add(data);
+        if !understood {
+            self.unknown_values += 1;
+        }
+        // A repeated value, or turning further past either end, leaves nothing new to send.
+        if pot_encoder.value != previous {
+            pot_encoder.update(now, out)?;
+        } else if understood && pot_encoder.mode == PotMode::Relative {
+            pot_encoder.signal_limit(out)?;
+        }
+        Ok(true)
+    }
 
-    let mut input = String::new();
-    stdin().read_line(&mut input)?; // wait for next enter key press
+    // Handles a message from Rekordbox to the device, rewriting it if a feedback rule matches.
+    fn feedback(
+        &mut self,
+        message: &[u8],
+        now: Instant,
+        device_out: &mut dyn MidiSink,
+        clock_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        self.handle_clock_rules(message, clock_out)?;
+        // Left to the next tick, which can send to Rekordbox as well as the device.
+        if let Some(probe) = &self.resync_probe {
+            let cooldown = Duration::from_millis(probe.cooldown_ms);
+            if probe.matches(message)
+                && self
+                    .last_resync
+                    .is_none_or(|last| now.duration_since(last) >= cooldown)
+            {
+                self.last_resync = Some(now);
+                self.resync_pending = true;
+            }
+        }
+        if let Some(key_control) = &mut self.key_control {
+            key_control.follow(message, device_out)?;
+        }
 
-    Ok(())
-}
+        // Feedback for a button is meant for a different pad than the note it arrives on.
+        let mut recorded = RecordingSink::default();
+        for button in &mut self.buttons {
+            if button.handle_feedback(message, &mut recorded)? {
+                self.echo_guard.filter(&mut recorded, now);
+                return send_recorded(recorded, device_out);
+            }
+        }
 
-fn select_port<T: MidiIO>(midi_io: &T, descr: &str) -> Result<T::Port> {
-    println!("Available {} ports:", descr);
-    let midi_ports = midi_io.ports();
-    for (i, p) in midi_ports.iter().enumerate() {
-        println!("{}: {}", i, midi_io.port_name(p)?);
-    }
-    print!("Please select {} port: ", descr);
-    stdout().flush()?;
-    let mut input = String::new();
-    stdin().read_line(&mut input)?;
-    let port = midi_ports
-        .get(input.trim().parse::<usize>()?)
-        .ok_or("Invalid port number")?;
-    Ok(port.clone())
+        // Not through the echo guard, as a meter legitimately repeats levels.
+        for meter in &mut self.meters {
+            if meter.handle_feedback(message, now, device_out)? {
+                return Ok(());
+            }
+        }
+
+        if let [status, cc, value] = *message {
+            if status & 0xF0 == CONTROL_CHANGE {
+                for (in_cc, pot_encoder) in &mut self.pots {
+                    if pot_encoder.follow(status & 0x0F, cc, value) {
+                        status::message(format_args!(
+                            "Pot CC {} followed Rekordbox to {}",
+                            in_cc,
+                            pot_encoder.position()
+                        ));
+                    }
+                }
+            }
+        }
+
+        let remapped = match *message {
+            [status, cc, value] if status & 0xF0 == CONTROL_CHANGE => {
+                let channel = status & 0x0F;
+                self.feedback_rules
+                    .iter()
+                    .find(|rule| rule.matches(channel, cc))
+                    .map(|rule| {
+                        rule.apply(channel, cc, value).build().inspect_err(|e| {
+                            let control = logging::describe(channel, CONTROL_CHANGE, cc);
+                            session_log::event(format_args!("Refused to remap {}: {}", control, e));
+                        })
+                    })
+                    .transpose()?
+            }
+            _ => None,
+        };
+        let message = match &remapped {
+            Some(remapped) => {
+                status::message(format_args!(
+                    "PartySaver->Device: {:?} (REMAPPED)",
+                    remapped
+                ));
+                remapped.as_slice()
+            }
+            None => message,
+        };
+
+        if self.echo_guard.allow(message, now) {
+            device_out.send(message)?;
+        }
+        Ok(())
+    }
+
+    fn handle_clock_rules(&mut self, message: &[u8], clock_out: &mut dyn MidiSink) -> Result<()> {
+        let Some(rule) = self.clock_rules.iter().find(|rule| rule.matches(message)) else {
+            return Ok(());
+        };
+
+        let running = rule.action != ClockAction::Stop;
+        if running == self.clock_running {
+            return Ok(());
+        }
+        self.clock_running = running;
+
+        status::message(format_args!("PartySaver->Clock: {:?}", rule.action));
+        clock_out.send(&[rule.action.message()])
+    }
+
+    // Does time-based work: sending throttled values which have since settled, blinking LEDs, and
+    // releasing notes held for too long.
+    fn tick(
+        &mut self,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        if std::mem::take(&mut self.resync_pending) {
+            self.resync(now, out, color_out)?;
+        }
+        for (channel, note) in self.rekordbox_held.expired(now) {
+            session_log::event(format_args!(
+                "Warning: {} was held for too long, releasing it",
+                logging::describe(channel, NOTE_ON, note)
+            ));
+            self.release_note(channel, note, out)?;
+        }
+        if let Some(talkover) = &mut self.talkover {
+            talkover.tick(now);
+        }
+        self.apply_duck(out)?;
+        for pot_encoder in self.pots.values_mut() {
+            pot_encoder.flush(now, out)?;
+            pot_encoder.tick(now, color_out)?;
+        }
+
+        if let Some(browse) = &mut self.browse {
+            browse.tick(now, out)?;
+        }
+        if let Some(key_control) = &mut self.key_control {
+            key_control.tick(now, out, color_out)?;
+        }
+        for button in &mut self.repeat_buttons {
+            button.tick(now, out)?;
+        }
+        for meter in &mut self.meters {
+            meter.tick(now, color_out)?;
+        }
+        self.filter_encoder.flush(now, out, color_out)?;
+        self.tempo_encoder.tick(now, color_out)
+    }
+
+    // Takes the talkover's duck off its pot, and any other pot's duck off entirely.
+    fn apply_duck(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        let talkover = self
+            .talkover
+            .as_ref()
+            .map(|talkover| (talkover.config.pot, talkover.duck()));
+        for (&cc, pot_encoder) in &mut self.pots {
+            let duck = match talkover {
+                Some((pot, duck)) if pot == cc => duck,
+                _ => 0,
+            };
+            pot_encoder.set_duck(duck, out)?;
+        }
+        Ok(())
+    }
+
+    fn handle_aftertouch(&self, channel: u8, pressure: u8, out: &mut dyn MidiSink) -> Result<bool> {
+        let Some(config) = self.aftertouch.get(&channel) else {
+            return Ok(false);
+        };
+
+        let out_channel = config.channel.unwrap_or(channel);
+        log_send(out_channel, CONTROL_CHANGE, config.cc, pressure, out)?;
+        Ok(true)
+    }
+
+    fn handle_program_change(
+        &self,
+        channel: u8,
+        program: u8,
+        out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        let Some(config) = self
+            .program_changes
+            .get(&program)
+            .filter(|config| config.in_channel.is_none_or(|x| x == channel))
+        else {
+            return Ok(false);
+        };
+
+        // There's no release to wait for, so the note is pressed and released straight away.
+        let mut note = config.note;
+        if config.follow_deck {
+            note += self.tempo_encoder.deck_index as u8;
+        }
+        let release = self
+            .release_policies
+            .get(&config.note)
+            .copied()
+            .unwrap_or(self.release_policy);
+        log_send(config.channel(), NOTE_ON, note, 127, out)?;
+        send_button(config.channel(), note, 0, false, release, out)?;
+        Ok(true)
+    }
+
+    fn handle_pitch_bend(
+        &self,
+        channel: u8,
+        lsb: u8,
+        msb: u8,
+        out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        match self.pitch_bend.get(&channel) {
+            None => return Ok(false),
+            Some(&PitchBendConfig::Channel { channel }) => {
+                log_send(channel, PITCH_BEND, lsb, msb, out)?;
+            }
+            Some(&PitchBendConfig::Cc {
+                cc,
+                channel: out_channel,
+            }) => {
+                let out_channel = out_channel.unwrap_or(channel);
+                log_send(out_channel, CONTROL_CHANGE, cc, msb, out)?;
+                log_send(out_channel, CONTROL_CHANGE, cc + 32, lsb, out)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Resets every control to how it was at startup, and re-sends everything, for when Rekordbox or
+    // the device have gotten out of sync with us.
+    fn panic(&mut self, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        session_log::event(format_args!("Panic: resetting every control"));
+        self.release_notes(out, color_out)?;
+        self.all_notes_off(out)?;
+        self.blackout(color_out)?;
+        if let Some(talkover) = &mut self.talkover {
+            talkover.reset(out)?;
+            talkover.light_led(color_out)?;
+        }
+        // Sent unducked along with every other pot below.
+        for pot_encoder in self.pots.values_mut() {
+            pot_encoder.duck = 0;
+        }
+        let defaults = self.defaults.clone();
+        self.recall(&defaults, out, color_out)
+    }
+
+    // Re-sends every value and LED, for when Rekordbox has reconnected and forgotten them.
+    fn resync(
+        &mut self,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        session_log::event(format_args!("Resync: re-sending every value"));
+        self.last_resync = Some(now);
+        self.sync(out, color_out)
+    }
+
+    // Sends All Notes Off and All Sound Off on every channel, for anything held in Rekordbox which
+    // party-saver doesn't know about (e.g. forwarded as-is).
+    fn all_notes_off(&self, out: &mut dyn MidiSink) -> Result<()> {
+        for channel in 0..16 {
+            log_send(channel, CONTROL_CHANGE, ALL_NOTES_OFF_CC, 0, out)?;
+            log_send(channel, CONTROL_CHANGE, ALL_SOUND_OFF_CC, 0, out)?;
+        }
+
+        Ok(())
+    }
+
+    // Turns off every LED on the device, including ones driven by Rekordbox, apart from buttons
+    // showing Rekordbox's last feedback. The rest party-saver manages are re-sent by the caller.
+    fn blackout(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for note in 0..128 {
+            Led::new(note).set(LedColor::Off, color_out)?;
+        }
+        for button in &self.buttons {
+            button.light_led(color_out)?;
+        }
+
+        Ok(())
+    }
+
+    // Releases every note this has left held in Rekordbox, and the controls holding them.
+    fn release_notes(
+        &mut self,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        for (channel, note) in self.rekordbox_held.take() {
+            self.release_note(channel, note, out)?;
+        }
+        if let Some(pad_row) = &mut self.pad_row {
+            pad_row.release(color_out)?;
+        }
+        for button in &mut self.repeat_buttons {
+            button.stop();
+        }
+        if let Some(key_control) = &mut self.key_control {
+            key_control.release();
+        }
+        Ok(())
+    }
+
+    // Sends a release for a note held in Rekordbox, as the target expects it.
+    fn release_note(&self, channel: u8, note: u8, out: &mut dyn MidiSink) -> Result<()> {
+        let kind = match self.target {
+            Target::Rekordbox => NOTE_ON,
+            Target::Mixxx => NOTE_OFF,
+        };
+        log_send(channel, kind, note, 0, out)
+    }
+
+    // Follows a message sent to Rekordbox, for the notes it leaves held.
+    fn sent(&mut self, message: &[u8], now: Instant) {
+        self.rekordbox_held.sent(message, now);
+    }
+
+    // Where a message produced for Rekordbox goes: Rekordbox, unless its channel is routed elsewhere.
+    fn route(&self, message: &[u8]) -> Dest {
+        let port = message
+            .first()
+            .filter(|&&status| (NOTE_OFF..0xF0).contains(&status))
+            .and_then(|status| self.routes.get(&(status & 0x0F)));
+        match port {
+            Some(port) => Dest::Route(port.clone()),
+            None => Dest::Rekordbox,
+        }
+    }
+
+    // Shows the initial selections on the device's LEDs, without sending anything to Rekordbox.
+    fn boot(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for button in &self.buttons {
+            button.light_led(color_out)?;
+        }
+        for pads in &self.snapshot_pads {
+            pads.light_led(color_out)?;
+        }
+        if let Some(key_control) = &self.key_control {
+            key_control.light_leds(color_out)?;
+        }
+        for bank in &self.banks {
+            bank.light_selected(color_out)?;
+        }
+        if let Some(talkover) = &self.talkover {
+            talkover.light_led(color_out)?;
+        }
+        self.tempo_encoder.light_selected(color_out)
+    }
+
+    // Re-sends every LED party-saver manages itself, without sending anything to Rekordbox.
+    fn sync_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        if let Some(fx_section) = &self.fx_section {
+            fx_section.light_selected(color_out)?;
+        }
+        for bank in &self.banks {
+            bank.light_selected(color_out)?;
+        }
+        for button in &self.buttons {
+            button.light_led(color_out)?;
+        }
+        for pads in &self.snapshot_pads {
+            pads.light_led(color_out)?;
+        }
+        if let Some(key_control) = &self.key_control {
+            key_control.light_leds(color_out)?;
+        }
+        if let Some(talkover) = &self.talkover {
+            talkover.light_led(color_out)?;
+        }
+        self.filter_encoder.light_leds(color_out)?;
+        self.tempo_encoder.light_selected(color_out)
+    }
+
+    // Swaps in `state`, built from a new config, keeping the values and statistics of controls
+    // which still exist.
+    fn reload(
+        &mut self,
+        mut state: State,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        // Held notes and lit LEDs may belong to controls which no longer exist.
+        self.release_notes(out, color_out)?;
+        self.clear_leds(color_out)?;
+
+        state.restore(&self.snapshot());
+        for (cc, pot_encoder) in &mut state.pots {
+            if let Some(old) = self.pots.get(cc) {
+                pot_encoder.ticks = old.ticks;
+                pot_encoder.duck = old.duck;
+            }
+        }
+        state.filter_encoder.toggles = self.filter_encoder.toggles;
+        // The boosts carry over to the same trims, and are brought in line with the new curve below.
+        let same_trims = match (
+            self.filter_encoder.config.makeup,
+            state.filter_encoder.config.makeup,
+        ) {
+            (Some(old), Some(new)) => old.trim_ccs == new.trim_ccs,
+            _ => false,
+        };
+        if same_trims {
+            state.filter_encoder.trims = self.filter_encoder.trims;
+            state.filter_encoder.boosts = self.filter_encoder.boosts;
+        } else {
+            self.filter_encoder.clear_makeup(out)?;
+        }
+        state.tempo_encoder.selects = self.tempo_encoder.selects;
+        if state
+            .tempo_encoder
+            .fine_config
+            .is_some_and(|x| !x.momentary)
+        {
+            state.tempo_encoder.fine = self.tempo_encoder.fine;
+        }
+        if state.tempo_encoder.link_note.is_some() {
+            state.tempo_encoder.linked = self.tempo_encoder.linked;
+        }
+        // Only the first movement since startup is armed, not the first since reloading.
+        for (armed, was_armed) in state
+            .tempo_encoder
+            .armed
+            .iter_mut()
+            .zip(self.tempo_encoder.armed)
+        {
+            *armed &= was_armed;
+        }
+        state.verbatim_count = self.verbatim_count;
+        state.unknown_values = self.unknown_values;
+        state.unhandled_ccs = self.unhandled_ccs;
+        state.last_activity = self.last_activity;
+        state.clock_running = self.clock_running;
+        state.last_resync = self.last_resync;
+        state.shift_active = self.shift_active && state.shift.is_some();
+        state.held_notes = std::mem::take(&mut self.held_notes);
+        if let (Some(key_control), Some(old)) = (&mut state.key_control, &self.key_control) {
+            key_control.shift = old.shift;
+        }
+        // The mic is left on if a talkover with the same pot takes over, and otherwise turned off.
+        // Either way the duck is brought in line below.
+        if let Some(old) = &mut self.talkover {
+            match &mut state.talkover {
+                Some(talkover) if talkover.config.pot == old.config.pot => {
+                    talkover.restore(old, Instant::now())
+                }
+                _ => old.reset(out)?,
+            }
+        }
+        for pads in &mut state.snapshot_pads {
+            if let Some(old) = self.snapshot_pads.iter_mut().find(|old| {
+                old.config.store_note == pads.config.store_note
+                    && old.config.recall_note == pads.config.recall_note
+            }) {
+                pads.snapshot = old.snapshot.take();
+            }
+        }
+
+        // Rekordbox won't report a state again until it changes.
+        for button in &mut state.buttons {
+            if let Some(old) = self.buttons.iter().find(|old| {
+                old.config.feedback_channel() == button.config.feedback_channel()
+                    && old.config.feedback_note() == button.config.feedback_note()
+            }) {
+                button.value = old.value;
+            }
+        }
+        state.unrecognized = std::mem::take(&mut self.unrecognized);
+
+        // Rekordbox still has each deck on the same range, as long as it's cycling the same ones.
+        let tempo = &mut state.tempo_encoder;
+        let old_tempo = &self.tempo_encoder;
+        if let (Some(new), Some(old)) = (&tempo.range_config, &old_tempo.range_config) {
+            if new.ranges == old.ranges {
+                tempo.range_indices = old_tempo.range_indices;
+            }
+        }
+
+        *self = state;
+        self.apply_duck(out)?;
+        self.filter_encoder.update_makeup(out)?;
+        self.sync_leds(color_out)
+    }
+
+    // Whether the device has been quiet for long enough that it should be sent a keepalive.
+    fn keepalive_due(&mut self, now: Instant) -> bool {
+        let Some(interval) = self.keepalive else {
+            return false;
+        };
+        if now.duration_since(self.last_activity) < interval {
+            return false;
+        }
+        self.last_activity = now;
+        true
+    }
+
+    // Turns off every LED party-saver manages itself.
+    fn clear_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        if let Some(fx_section) = &self.fx_section {
+            fx_section.clear_leds(color_out)?;
+        }
+        for bank in &self.banks {
+            bank.clear_leds(color_out)?;
+        }
+        for button in &self.buttons {
+            button.clear_led(color_out)?;
+        }
+        for pads in &self.snapshot_pads {
+            pads.clear_led(color_out)?;
+        }
+        if let Some(key_control) = &self.key_control {
+            key_control.clear_leds(color_out)?;
+        }
+        if let Some(talkover) = &self.talkover {
+            talkover.clear_led(color_out)?;
+        }
+        if let Some(pad_row) = &self.pad_row {
+            pad_row.clear_leds(color_out)?;
+        }
+        for meter in &self.meters {
+            meter.clear(color_out)?;
+        }
+        self.filter_encoder.clear_leds(color_out)?;
+        self.tempo_encoder.clear_leds(color_out)
+    }
+
+    // Re-sends every continuous value and LED, so Rekordbox and the device match our state.
+    fn sync(&mut self, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        for pot_encoder in self.pots.values_mut() {
+            pot_encoder.send(out)?;
+        }
+
+        if let Some(fx_section) = &self.fx_section {
+            fx_section.sync(out, color_out)?;
+        }
+        for bank in &self.banks {
+            bank.sync(out, color_out)?;
+        }
+        self.filter_encoder.sync(out, color_out)?;
+        self.tempo_encoder.sync(out, color_out)
+    }
+
+    // Shows the selected tempo deck and the filters on the status line.
+    fn publish_status(&self) {
+        let filter = &self.filter_encoder;
+        status::set_decks(
+            self.tempo_encoder.deck_index,
+            self.tempo_encoder.caught(),
+            [filter.deck1, filter.deck2, filter.deck3],
+        );
+    }
+
+    fn stats(&self) -> SessionStats {
+        SessionStats {
+            pot_ticks: self
+                .pots
+                .iter()
+                .map(|(&cc, pot_encoder)| (cc, pot_encoder.ticks))
+                .collect(),
+            filter_toggles: self.filter_encoder.toggles,
+            tempo_deck_selects: self.tempo_encoder.selects,
+            verbatim: self.verbatim_count,
+            unknown_values: self.unknown_values,
+            unhandled_ccs: self.unhandled_ccs,
+            unrecognized: SessionStats::top_unrecognized(&self.unrecognized),
+            coverage: SessionStats::sorted_unrecognized(&self.unrecognized),
+        }
+    }
+
+    fn snapshot(&self) -> StateSnapshot {
+        let filter = &self.filter_encoder;
+        let tempo = &self.tempo_encoder;
+        StateSnapshot {
+            pots: self
+                .pots
+                .iter()
+                .map(|(&cc, pot_encoder)| (cc, pot_encoder.value))
+                .collect(),
+            filter_decks: [filter.deck1, filter.deck2, filter.deck3],
+            filter_state: filter.state,
+            tempo_deck_index: tempo.deck_index,
+            tempo_values: [tempo.deck1_value, tempo.deck2_value, tempo.deck3_value],
+            fx: self
+                .fx_section
+                .as_ref()
+                .map(|fx| (fx.unit_index, fx.values.clone())),
+            banks: self
+                .banks
+                .iter()
+                .map(|bank| (bank.deck_index, bank.values.clone()))
+                .collect(),
+        }
+    }
+
+    fn handle_snapshot_pad(
+        &mut self,
+        note: u8,
+        state: bool,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        let Some(i) = self
+            .snapshot_pads
+            .iter()
+            .position(|pads| note == pads.config.store_note || note == pads.config.recall_note)
+        else {
+            return Ok(false);
+        };
+        if !state {
+            return Ok(true);
+        }
+
+        if note == self.snapshot_pads[i].config.store_note {
+            self.snapshot_pads[i].snapshot = Some(self.snapshot());
+            status::line(format_args!("Snapshot {} stored", i + 1));
+            self.snapshot_pads[i].light_led(color_out)?;
+        } else if let Some(snapshot) = self.snapshot_pads[i].snapshot.take() {
+            self.recall(&snapshot, out, color_out)?;
+            status::line(format_args!("Snapshot {} recalled", i + 1));
+            self.snapshot_pads[i].snapshot = Some(snapshot);
+        } else {
+            status::line(format_args!("Snapshot {} is empty", i + 1));
+        }
+        Ok(true)
+    }
+
+    // Restores a snapshot, and sends everything in it.
+    fn recall(
+        &mut self,
+        snapshot: &StateSnapshot,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        self.restore(snapshot);
+        self.filter_encoder.update_makeup(out)?;
+        self.sync(out, color_out)
+    }
+
+    // Restores a snapshot's values, without sending anything.
+    fn restore(&mut self, snapshot: &StateSnapshot) {
+        for (cc, value) in &snapshot.pots {
+            if let Some(pot_encoder) = self.pots.get_mut(cc) {
+                pot_encoder.value = *value;
+                pot_encoder.throttle.sync(pot_encoder.position());
+            }
+        }
+
+        let filter = &mut self.filter_encoder;
+        [filter.deck1, filter.deck2, filter.deck3] = snapshot.filter_decks;
+        filter.state = snapshot.filter_state;
+        filter.mixes = snapshot.filter_decks.map(|x| if x { 1.0 } else { 0.0 });
+        filter.ramps = [None; 3];
+
+        // The fader is likely somewhere else now, so pickup will kick in before it takes over.
+        let tempo = &mut self.tempo_encoder;
+        tempo.deck_index = snapshot.tempo_deck_index;
+        tempo.blink = None;
+        tempo.sent = [None; 3];
+        [tempo.deck1_value, tempo.deck2_value, tempo.deck3_value] = snapshot.tempo_values;
+
+        // The snapshot may have been taken with a differently laid out FX section, before a reload.
+        if let (Some(fx_section), Some((unit_index, values))) = (&mut self.fx_section, &snapshot.fx)
+        {
+            let same_layout = values.len() == fx_section.values.len()
+                && values.first().map(Vec::len) == fx_section.values.first().map(Vec::len);
+            if same_layout {
+                fx_section.unit_index = *unit_index;
+                fx_section.values.clone_from(values);
+            }
+        }
+        for (bank, (deck_index, values)) in self.banks.iter_mut().zip(&snapshot.banks) {
+            if values.first().map(Vec::len) == bank.values.first().map(Vec::len) {
+                bank.deck_index = *deck_index;
+                bank.values.clone_from(values);
+            }
+        }
+    }
+}
+
+// Runs `f` against the state and queues whatever it produced for the output worker. The batch is
+// queued before the state's lock is released, so that batches are sent in the order they were
+// produced.
+fn update(
+    state: &Mutex<State>,
+    queue: &OutputQueue,
+    f: impl FnOnce(&mut State, &mut dyn MidiSink, &mut dyn MidiSink) -> Result<()>,
+) -> Result<()> {
+    let batch = SharedBatch::default();
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = f(
+        &mut state,
+        &mut batch.sink(Dest::Rekordbox),
+        &mut batch.sink(Dest::Device),
+    );
+    if status::enabled() {
+        state.publish_status();
+    }
+
+    // Queue what was produced even if `f` failed partway, as the state already reflects it.
+    let mut batch = batch.take();
+    let now = Instant::now();
+    for (dest, message) in &batch {
+        if *dest == Dest::Rekordbox {
+            state.sent(message, now);
+        }
+    }
+    batch
+        .retain(|(dest, message)| *dest == Dest::Rekordbox || state.echo_guard.allow(message, now));
+    for (dest, message) in &mut batch {
+        if *dest == Dest::Rekordbox {
+            *dest = state.route(message);
+        }
+    }
+    queue.push(batch);
+    result
+}
+
+fn send_recorded(recorded: RecordingSink, out: &mut dyn MidiSink) -> Result<()> {
+    for message in recorded.messages {
+        out.send(&message)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        None => run(cli.run),
+        Some(Command::Run(args)) => run(*args),
+        Some(Command::ListPorts { json }) => ports::list(json),
+        Some(Command::CheckConfig { file }) => check_config(&file),
+        Some(Command::Calibrate {
+            file,
+            device_in,
+            ccs,
+        }) => calibrate::run(&file, device_in.as_deref(), &ccs),
+        Some(Command::SelfTest {
+            config,
+            device_in,
+            device_out,
+            listen_secs,
+        }) => self_test::run(
+            config.as_deref(),
+            device_in.as_deref(),
+            device_out.as_deref(),
+            Duration::from_secs(listen_secs),
+        ),
+        Some(Command::Learn { file, device_in }) => learn::run(&file, device_in.as_deref()),
+        Some(Command::Replay { fixtures, target }) => replay::run(&fixtures, target),
+    };
+    match result {
+        Ok(()) => {
+            session_log::flush();
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            session_log::error(format_args!("Error: {}", e));
+            session_log::flush();
+            ExitCode::from(shutdown::exit_code(&*e))
+        }
+    }
+}
+
+fn check_config(path: &Path) -> Result<()> {
+    Config::load(path)?;
+    println!("{} is valid", path.display());
+    Ok(())
+}
+
+fn run(args: Args) -> Result<()> {
+    logging::set_format(args.log_format);
+    logging::set_verbatim(args.log_verbatim);
+    if args.json_events {
+        events::enable();
+    }
+    if args.inject.is_empty() && !args.no_log_file {
+        if let Some(path) = args.log_file.clone().or_else(session_log::default_path) {
+            session_log::open(
+                path,
+                args.log_file_max_kb * 1024,
+                args.log_file_keep,
+                args.log_traffic,
+            )?;
+            session_log::record(format_args!("Starting party-saver {}", VERSION));
+        }
+    }
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    if !args.inject.is_empty() {
+        let stats = inject(&config, &args)?;
+        report_stats(&stats, &args);
+        return Ok(());
+    }
+
+    let aliases = match &args.aliases {
+        Some(path) => Aliases::load(path).map_err(exit_with(EXIT_PORT_SELECTION))?,
+        None => Aliases::default(),
+    };
+
+    // First, connect to an actual device.
+    let device_in = MidiInput::new("PartySaver device in")?;
+    let passthrough_device_out = MidiOutput::new("PartySaver device out")?;
+    let (device_in_port, device_out_port) = match &args.wait_for_device {
+        Some(name) => ports::wait_for_device(
+            &device_in,
+            &passthrough_device_out,
+            aliases.resolve(name),
+            args.wait_timeout.map(Duration::from_secs),
+        ),
+        None => select_ports(
+            &device_in,
+            &passthrough_device_out,
+            args.device_in.as_deref().map(|x| aliases.resolve(x)),
+            args.device_out.as_deref().map(|x| aliases.resolve(x)),
+        ),
+    }
+    .map_err(exit_with(EXIT_PORT_SELECTION))?;
+    let device_in_name = device_in.port_name(&device_in_port)?;
+    let device_out_name = passthrough_device_out.port_name(&device_out_port)?;
+    let clock_out = match &args.clock_out {
+        Some(name) => {
+            let clock_out = MidiOutput::new("PartySaver clock out")?;
+            let port = ports::find_unique_port(&clock_out, "output", aliases.resolve(name))
+                .map_err(exit_with(EXIT_PORT_SELECTION))?;
+            Some((clock_out.port_name(&port)?, clock_out, port))
+        }
+        None => None,
+    };
+    let clock_out_name = clock_out.as_ref().map(|(name, _, _)| name.clone());
+    let rekordbox_out = match &args.rekordbox_out {
+        Some(name) => {
+            let rekordbox_out = MidiOutput::new("Rekordbox Out")?;
+            let port = ports::find_unique_port(&rekordbox_out, "output", aliases.resolve(name))
+                .map_err(exit_with(EXIT_PORT_SELECTION))?;
+            Some((rekordbox_out.port_name(&port)?, rekordbox_out, port))
+        }
+        None => None,
+    };
+    // Each port routes are sent to, opened once however many routes share it.
+    let mut route_outs = Vec::new();
+    for route in &config.routes {
+        if route_outs.iter().any(|(x, _, _, _)| x == &route.port) {
+            continue;
+        }
+        let route_out = MidiOutput::new("PartySaver route out")?;
+        let port = ports::find_unique_port(&route_out, "output", aliases.resolve(&route.port))
+            .map_err(exit_with(EXIT_PORT_SELECTION))?;
+        let name = route_out.port_name(&port)?;
+        route_outs.push((route.port.clone(), name, route_out, port));
+    }
+    let route_names: Vec<_> = route_outs
+        .iter()
+        .map(|(_, name, _, _)| name.clone())
+        .collect();
+    let opened_routes: HashSet<_> = route_outs.iter().map(|(x, _, _, _)| x.clone()).collect();
+    let rb_out_name = match &rekordbox_out {
+        Some((name, _, _)) => name.clone(),
+        None => args.virtual_out_name.clone(),
+    };
+
+    // Other programs see the virtual output as an input port, and vice versa.
+    if !args.dry_run {
+        if rekordbox_out.is_none() {
+            ports::check_name_free(&device_in, "input", &args.virtual_out_name)
+                .map_err(exit_with(EXIT_PORT_SELECTION))?;
+        }
+        if !args.no_passthrough {
+            ports::check_name_free(&passthrough_device_out, "output", &args.virtual_in_name)
+                .map_err(exit_with(EXIT_PORT_SELECTION))?;
+        }
+    }
+
+    println!("Opening connections");
+
+    // Transform messages from the device to Rekordbox.
+    let outputs = if args.dry_run {
+        println!("DRY RUN: Nothing will be sent to Rekordbox or the device");
+        Outputs {
+            rb_out: Box::new(DryRunSink::new(None)),
+            color_out: Box::new(DryRunSink::new(Some("PartySaver->Device"))),
+            clock_out: None,
+            routes: HashMap::new(),
+        }
+    } else {
+        Outputs {
+            rb_out: Box::new(
+                open_rekordbox_out(rekordbox_out, &args.virtual_out_name)
+                    .map_err(exit_with(EXIT_CONNECTION))?,
+            ),
+            color_out: Box::new(
+                MidiOutput::new("PartySaver color out")?
+                    .connect(&device_out_port, "party-saver-color")
+                    .map_err(exit_with(EXIT_CONNECTION))?,
+            ),
+            clock_out: match clock_out {
+                Some((_, clock_out, port)) => Some(Box::new(
+                    clock_out
+                        .connect(&port, "party-saver-clock")
+                        .map_err(exit_with(EXIT_CONNECTION))?,
+                )),
+                None => None,
+            },
+            routes: route_outs
+                .into_iter()
+                .map(|(route, _, route_out, port)| {
+                    let route_out = route_out
+                        .connect(&port, "party-saver-route")
+                        .map_err(exit_with(EXIT_CONNECTION))?;
+                    Ok((route, Box::new(route_out) as Box<dyn MidiSink>))
+                })
+                .collect::<Result<_>>()?,
+        }
+    };
+
+    // Shared by both MIDI callbacks, the tick thread (which does any time-based work) and commands.
+    let state = Arc::new(Mutex::new(State::new(&config, args.target)));
+    let connection_lost = Arc::new(AtomicBool::new(false));
+    let queue = OutputQueue::default();
+    let output_worker = output::spawn_worker(queue.clone(), outputs, connection_lost.clone());
+    update(&state, &queue, |state, _, color_out| state.boot(color_out))?;
+    let conn_in = device_in
+        .connect(
+            &device_in_port,
+            "party-saver",
+            move |stamp, message, (state, queue, latency)| {
+                let received = Instant::now();
+                status::received();
+                status::message(format_args!(
+                    "Device->PartySaver {}: {:?} (len={})",
+                    stamp,
+                    message,
+                    message.len()
+                ));
+                session_log::traffic(format_args!("Device->PartySaver: {:?}", message));
+                update(state, queue, |state, rb_out, color_out| {
+                    state.transform(message, received, rb_out, color_out)
+                })
+                .unwrap_or_else(|e| {
+                    session_log::event(format_args!(
+                        "Failed to forward MIDI message to main thread: {}",
+                        e
+                    ))
+                });
+
+                // Includes the logging, as that's part of the delay too.
+                if let Some(latency) = latency {
+                    let now = Instant::now();
+                    latency.record(now.duration_since(received), now);
+                }
+            },
+            (
+                state.clone(),
+                queue.clone(),
+                args.measure_latency
+                    .then(|| LatencyStats::new(Instant::now())),
+            ),
+        )
+        .map_err(exit_with(EXIT_CONNECTION))?;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let tick_thread = {
+        let state = state.clone();
+        let queue = queue.clone();
+        let running = running.clone();
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                thread::sleep(TICK_INTERVAL);
+                update(&state, &queue, |state, rb_out, color_out| {
+                    state.tick(Instant::now(), rb_out, color_out)
+                })
+                .unwrap_or_else(|e| {
+                    session_log::event(format_args!("Failed to run timed updates: {}", e))
+                });
+            }
+        })
+    };
+
+    // Always started, so that a keepalive enabled by reloading the config takes effect.
+    let keepalive_thread = keepalive(state.clone(), queue.clone(), running.clone());
+
+    let config_watcher = args.config.clone().map(|path| {
+        watch_config(
+            path,
+            args.target,
+            opened_routes,
+            state.clone(),
+            queue.clone(),
+            running.clone(),
+        )
+    });
+
+    let device_output = args
+        .device_output
+        .unwrap_or_else(DeviceOutput::platform_default);
+    let rb_in = if args.no_passthrough || args.dry_run {
+        None
+    } else {
+        let passthrough_out = match device_output {
+            DeviceOutput::Shared => None,
+            DeviceOutput::Separate => Some(
+                passthrough_device_out
+                    .connect(&device_out_port, "party-saver")
+                    .map_err(exit_with(EXIT_CONNECTION))?,
+            ),
+        };
+        Some(open_passthrough(
+            &args.virtual_in_name,
+            passthrough_out,
+            state.clone(),
+            queue.clone(),
+        )?)
+    };
+
+    println!();
+    println!("Connected:");
+    println!("  Device in:    {}", device_in_name);
+    println!("  Device out:   {}", device_out_name);
+    if !args.dry_run {
+        match args.rekordbox_out {
+            Some(_) => println!("  Rekordbox:    {}", rb_out_name),
+            None => println!("  Virtual out:  {}", rb_out_name),
+        }
+    }
+    if rb_in.is_some() {
+        println!("  Virtual in:   {}", args.virtual_in_name);
+    }
+    if let Some(clock_out_name) = &clock_out_name {
+        println!("  Clock out:    {}", clock_out_name);
+    }
+    for name in &route_names {
+        println!("  Route out:    {}", name);
+    }
+    println!(
+        "  Passthrough:  {}",
+        match (&rb_in, device_output) {
+            (None, _) => "off",
+            (Some(_), DeviceOutput::Shared) => "on, sharing the LEDs' connection",
+            (Some(_), DeviceOutput::Separate) => "on, with its own connection",
+        }
+    );
+    println!(
+        "  Profile:      {}",
+        args.config
+            .as_ref()
+            .map_or("default".into(), |path| path.display().to_string())
+    );
+    session_log::record(format_args!(
+        "Connected: device in {}, device out {}, passthrough {}",
+        device_in_name,
+        device_out_name,
+        if rb_in.is_some() { "on" } else { "off" }
+    ));
+    println!("  Target:       {:?}", args.target);
+    println!("  Decks:        {}", DECK_COUNT);
+    if args.dry_run {
+        println!("  DRY RUN:      nothing is sent");
+    }
+    println!();
+    if !args.no_status_line && !args.dry_run && !args.json_events {
+        status::enable();
+    }
+
+    let mut snapshot = None;
+    let shutdown = shutdown::wait(
+        !args.run_forever,
+        &device_in_name,
+        &connection_lost,
+        |command| {
+            update(&state, &queue, |state, rb_out, color_out| {
+                run_command(command, state, &mut snapshot, rb_out, color_out)
+            })
+            .unwrap_or_else(|e| {
+                session_log::event(format_args!("Failed to run command \"{}\": {}", command, e))
+            });
+        },
+    );
+    status::disable();
+    let shutdown = shutdown?;
+    session_log::event(format_args!("Shutting down"));
+
+    // Tear down in a fixed order: stop taking input from the device, turn off the LEDs we manage,
+    // then close the virtual ports, and finally the connections to the device.
+    conn_in.close();
+    running.store(false, Ordering::Relaxed);
+    tick_thread
+        .join()
+        .map_err(|_| "INTERNAL ERROR: Tick thread panicked")?;
+    keepalive_thread
+        .join()
+        .map_err(|_| "INTERNAL ERROR: Keepalive thread panicked")?;
+    if let Some(config_watcher) = config_watcher {
+        config_watcher
+            .join()
+            .map_err(|_| "INTERNAL ERROR: Config watcher thread panicked")?;
+    }
+    update(&state, &queue, |state, rb_out, color_out| {
+        state.release_notes(rb_out, color_out)?;
+        state.clear_leds(color_out)
+    })
+    .unwrap_or_else(|e| {
+        session_log::event(format_args!(
+            "Failed to release notes and clear LEDs: {}",
+            e
+        ))
+    });
+    let stats = state.lock().unwrap_or_else(PoisonError::into_inner).stats();
+    if let Some(rb_in) = rb_in {
+        rb_in.close();
+    }
+    queue.close();
+    output_worker
+        .join()
+        .map_err(|_| "INTERNAL ERROR: Output worker thread panicked")?;
+
+    report_stats(&stats, &args);
+
+    match shutdown {
+        Shutdown::Requested => Ok(()),
+        Shutdown::ConnectionLost => {
+            Err(exit_with(EXIT_CONNECTION)(format!("Lost connection to {}", device_in_name)).into())
+        }
+    }
+}
+
+// Polls the config file for changes, and swaps the new mapping into the state when it does. A
+// config which fails to load is reported, and the previous one kept.
+fn watch_config(
+    path: PathBuf,
+    target: Target,
+    opened_routes: HashSet<String>,
+    state: Arc<Mutex<State>>,
+    queue: OutputQueue,
+    running: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut modified = Config::modified(&path);
+        while running.load(Ordering::Relaxed) {
+            thread::sleep(CONFIG_POLL_INTERVAL);
+            let now_modified = Config::modified(&path);
+            if now_modified == modified {
+                continue;
+            }
+            modified = now_modified;
+
+            // Built before taking the lock, so the callbacks aren't held up by parsing.
+            let new_state = match Config::load(&path) {
+                Ok(config) => {
+                    for route in &config.routes {
+                        if !opened_routes.contains(&route.port) {
+                            session_log::error(format_args!(
+                                "!!! {} wasn't routed to at startup, so its route is sent to Rekordbox until restarted",
+                                route.port
+                            ));
+                        }
+                    }
+                    State::new(&config, target)
+                }
+                Err(e) => {
+                    session_log::error(format_args!(
+                        "!!! Config reload failed, keeping the previous config: {}",
+                        e
+                    ));
+                    continue;
+                }
+            };
+            match update(&state, &queue, |state, rb_out, color_out| {
+                state.reload(new_state, rb_out, color_out)
+            }) {
+                Ok(()) => session_log::event(format_args!("Reloaded config {}", path.display())),
+                Err(e) => session_log::event(format_args!("Failed to reload config: {}", e)),
+            }
+        }
+    })
+}
+
+// Sends the device a keepalive whenever it's been quiet for too long. The output worker flags the
+// connection as lost if that fails.
+fn keepalive(
+    state: Arc<Mutex<State>>,
+    queue: OutputQueue,
+    running: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while running.load(Ordering::Relaxed) {
+            thread::sleep(KEEPALIVE_POLL_INTERVAL);
+            let due = state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .keepalive_due(Instant::now());
+            if !due {
+                continue;
+            }
+
+            // Not through `update`, as the echo guard would swallow repeats.
+            queue.push(vec![(Dest::Device, vec![ACTIVE_SENSING])]);
+        }
+    })
+}
+
+// Handles a line entered on stdin while running.
+fn run_command(
+    command: &str,
+    state: &mut State,
+    snapshot: &mut Option<StateSnapshot>,
+    out: &mut dyn MidiSink,
+    color_out: &mut dyn MidiSink,
+) -> Result<()> {
+    match command {
+        "snapshot" => {
+            *snapshot = Some(state.snapshot());
+            status::line(format_args!("Snapshot taken"));
+        }
+        "recall" => {
+            let snapshot = snapshot.as_ref().ok_or("No snapshot has been taken")?;
+            state.recall(snapshot, out, color_out)?;
+            status::line(format_args!("Snapshot recalled"));
+        }
+        "p" | "panic" => state.panic(out, color_out)?,
+        "stats" => state.stats().print(),
+        "sync" => state.resync(Instant::now(), out, color_out)?,
+        _ => println!(
+            "Unknown command \"{}\". Available commands: snapshot, recall, sync, p/panic, stats (or enter to exit)",
+            command
+        ),
+    }
+
+    Ok(())
+}
+
+// Connects to the existing port given with --rekordbox-out, or otherwise creates a virtual port
+// named `virtual_name` for Rekordbox to receive from.
+fn open_rekordbox_out(
+    existing: Option<(String, MidiOutput, MidiOutputPort)>,
+    virtual_name: &str,
+) -> Result<MidiOutputConnection> {
+    if let Some((name, midi_out, port)) = existing {
+        return midi_out
+            .connect(&port, "party-saver")
+            .map_err(|e| format!("Failed to connect to \"{}\": {}", name, e).into());
+    }
+    MidiOutput::new("Rekordbox Out")?
+        .create_virtual(virtual_name)
+        .map_err(|e| {
+            format!(
+                "Failed to create the virtual port \"{}\": {}. Pass --rekordbox-out to use an \
+                 existing port (e.g. from loopMIDI) instead",
+                virtual_name, e
+            )
+            .into()
+        })
+}
+
+// Forward all messages from rekordbox to the device, on `passthrough_out` if given, and otherwise
+// through the output worker.
+fn open_passthrough(
+    name: &str,
+    mut passthrough_out: Option<MidiOutputConnection>,
+    state: Arc<Mutex<State>>,
+    queue: OutputQueue,
+) -> Result<MidiInputConnection<Arc<Mutex<State>>>> {
+    let rb_in = MidiInput::new("Rekordbox In")?
+        .create_virtual(
+            name,
+            move |stamp, message, state| {
+                status::message(format_args!(
+                    "Rekordbox->Device {}: {:?} (len = {})",
+                    stamp,
+                    message,
+                    message.len()
+                ));
+                session_log::traffic(format_args!("Rekordbox->Device: {:?}", message));
+
+                let passthrough_out = passthrough_out.as_mut().map(|x| x as &mut dyn MidiSink);
+                forward_feedback(state, &queue, message, passthrough_out).unwrap_or_else(|e| {
+                    session_log::event(format_args!("Error when forwarding message: {}", e))
+                });
+            },
+            state,
+        )
+        .map_err(exit_with(EXIT_CONNECTION))?;
+    Ok(rb_in)
+}
+
+// Runs a message from Rekordbox through `state`, sending what it produces for the device on
+// `passthrough_out` if given, and otherwise through the output worker.
+fn forward_feedback(
+    state: &Mutex<State>,
+    queue: &OutputQueue,
+    message: &[u8],
+    passthrough_out: Option<&mut dyn MidiSink>,
+) -> Result<()> {
+    // Queued while the lock is still held, as in `update`, so that output from another callback
+    // can't be queued in between. Clock messages go through the output worker, which owns the clock
+    // connection.
+    let batch = SharedBatch::default();
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = state.feedback(
+        message,
+        Instant::now(),
+        &mut batch.sink(Dest::Device),
+        &mut batch.sink(Dest::Clock),
+    );
+    let (passthrough, queued): (Batch, Batch) = batch
+        .take()
+        .into_iter()
+        .partition(|(dest, _)| passthrough_out.is_some() && *dest == Dest::Device);
+    queue.push(queued);
+    drop(state);
+
+    // A separate connection isn't owned by the worker, so it's sent to directly, but not while
+    // holding the lock.
+    result?;
+    match passthrough_out {
+        Some(passthrough_out) => passthrough
+            .into_iter()
+            .try_for_each(|(_, message)| passthrough_out.send(&message)),
+        None => Ok(()),
+    }
+}
+
+// Uses the ports matching `in_pattern` and `out_pattern` where given, and prompts for the others.
+fn select_ports(
+    device_in: &MidiInput,
+    device_out: &MidiOutput,
+    in_pattern: Option<&str>,
+    out_pattern: Option<&str>,
+) -> Result<(MidiInputPort, MidiOutputPort)> {
+    let in_port = match in_pattern {
+        Some(pattern) => ports::find_unique_port(device_in, "input", pattern)?,
+        None => {
+            let in_port = ports::select_port(device_in, "input", None)?;
+            println!();
+            in_port
+        }
+    };
+
+    // Devices usually name their input and output ports the same.
+    let out_port = match out_pattern.or(in_pattern) {
+        Some(pattern) => ports::find_unique_port(device_out, "output", pattern)?,
+        None => {
+            let in_name = ports::port_key(device_in, &in_port)?;
+            let out_port = ports::select_port(device_out, "output", Some(&in_name))?;
+            println!();
+            out_port
+        }
+    };
+    Ok((in_port, out_port))
+}
+
+// Runs the given hex-encoded messages through a fresh State, printing everything emitted.
+fn inject(config: &Config, args: &Args) -> Result<SessionStats> {
+    let mut state = State::new(config, args.target);
+    let mut rb_out = RecordingSink::default();
+    let mut color_out = RecordingSink::default();
+    state.boot(&mut color_out)?;
+    print_recorded(&state, &mut rb_out, &mut color_out);
+
+    for text in &args.inject {
+        let message = parse_hex(text)?;
+        println!("Injecting {:?}", message);
+        if args.inject_feedback {
+            // Clock messages are already logged as they're produced.
+            state.feedback(
+                &message,
+                Instant::now(),
+                &mut color_out,
+                &mut RecordingSink::default(),
+            )?;
+        } else {
+            state.transform(&message, Instant::now(), &mut rb_out, &mut color_out)?;
+        }
+        for message in &rb_out.messages {
+            state.sent(message, Instant::now());
+        }
+        print_recorded(&state, &mut rb_out, &mut color_out);
+    }
+
+    // Treat every control as having gone idle, so throttled values show up too.
+    state.tick(
+        Instant::now() + Duration::from_secs(3600),
+        &mut rb_out,
+        &mut color_out,
+    )?;
+    print_recorded(&state, &mut rb_out, &mut color_out);
+
+    Ok(state.stats())
+}
+
+// A failure to write the stats file shouldn't turn an otherwise clean exit into an error.
+fn report_stats(stats: &SessionStats, args: &Args) {
+    println!();
+    stats.print();
+    if args.coverage {
+        println!();
+        stats.print_coverage();
+    }
+    if let Some(path) = &args.stats_out {
+        stats
+            .write(path)
+            .unwrap_or_else(|e| println!("Failed to write stats: {}", e));
+    }
+}
+
+// Messages to Rekordbox are already logged as they're sent, but where they're routed and LED
+// messages to the device aren't.
+fn print_recorded(state: &State, rb_out: &mut RecordingSink, color_out: &mut RecordingSink) {
+    for message in rb_out.messages.drain(..) {
+        if let Dest::Route(port) = state.route(&message) {
+            println!("Routed to {}: {:?}", port, message);
+        }
+    }
+    for message in color_out.messages.drain(..) {
+        println!("PartySaver->Device: {:?}", message);
+    }
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let message = text
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid hex message \"{}\": {}", text, e))?;
+    if message.is_empty() {
+        return Err("Injected message is empty".into());
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn state(config: &str) -> State {
+        State::new(&Config::from_text(config).unwrap(), Target::Rekordbox)
+    }
+
+    // Runs each device message through `state` at its time in milliseconds, then lets every control
+    // go idle, returning what was sent to Rekordbox.
+    fn play(state: &mut State, input: &[(u64, &[u8])]) -> Vec<Vec<u8>> {
+        let start = Instant::now();
+        let mut rb_out = RecordingSink::default();
+        let mut device_out = RecordingSink::default();
+        let mut sent = 0;
+        for &(at, message) in input {
+            let now = start + Duration::from_millis(at);
+            state.tick(now, &mut rb_out, &mut device_out).unwrap();
+            state
+                .transform(message, now, &mut rb_out, &mut device_out)
+                .unwrap();
+            for message in &rb_out.messages[sent..] {
+                state.sent(message, now);
+            }
+            sent = rb_out.messages.len();
+        }
+        let idle = start + Duration::from_secs(3600);
+        state.tick(idle, &mut rb_out, &mut device_out).unwrap();
+        rb_out.messages
+    }
+
+    proptest! {
+        // Whatever a device sends, nothing panics, and every message party-saver builds itself starts
+        // with a status byte and has data bytes within 0-127. What it doesn't understand, malformed
+        // or not, is forwarded as it arrived.
+        #[test]
+        fn transform_sends_valid_data_bytes(
+            messages in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..=64), 1..16),
+        ) {
+            let mut state = state("");
+            let start = Instant::now();
+            let mut rb_out = RecordingSink::default();
+            let mut device_out = RecordingSink::default();
+            for (i, message) in messages.iter().enumerate() {
+                let now = start + Duration::from_millis(i as u64 * 10);
+                state.transform(message, now, &mut rb_out, &mut device_out).unwrap();
+                state.tick(now, &mut rb_out, &mut device_out).unwrap();
+            }
+            let constructed = rb_out
+                .messages
+                .iter()
+                .chain(&device_out.messages)
+                .filter(|x| !messages.contains(x));
+            for message in constructed {
+                prop_assert!(message[0] > 127, "{:?} has no status byte", message);
+                prop_assert!(
+                    message[1..].iter().all(|&x| x <= 127),
+                    "{:?} has a data byte above 127",
+                    message
+                );
+            }
+        }
+    }
+
+    // What an unmapped CC under each `unknown_ccs` policy sends to Rekordbox.
+    fn unknown_cc(policy: &str) -> Vec<Vec<u8>> {
+        let mut state = state(&format!("[input]\nunknown_ccs = \"{}\"\n", policy));
+        let sent = play(&mut state, &[(0, &[0xBF, 0x50, 0x40])]);
+        assert_eq!(state.unhandled_ccs, 1);
+        sent
+    }
+
+    #[test]
+    fn unknown_ccs_are_forwarded() {
+        assert_eq!(unknown_cc("forward"), [[0xBF, 0x50, 0x40]]);
+    }
+
+    #[test]
+    fn unknown_ccs_are_dropped() {
+        assert!(unknown_cc("drop").is_empty());
+    }
+
+    #[test]
+    fn unknown_ccs_are_logged_and_forwarded() {
+        assert_eq!(unknown_cc("log"), [[0xBF, 0x50, 0x40]]);
+    }
+
+    #[test]
+    fn malformed_messages_are_forwarded_verbatim() {
+        // A tempo fader value with the high bit set.
+        let mut state = state("");
+        let tempo = state.tempo_encoder.deck1_value;
+        let sent = play(&mut state, &[(0, &[0xBF, 0x13, 0xC0])]);
+        assert_eq!(sent, [[0xBF, 0x13, 0xC0]]);
+        assert_eq!(state.tempo_encoder.deck1_value, tempo);
+        assert_eq!(state.verbatim_count, 1);
+
+        // So the next movement is handled as though it never arrived.
+        let sent = play(&mut state, &[(0, &[0xBF, 0x13, 0x40])]);
+        assert_eq!(sent, [[0xBB, 0x01, 0x3F]]);
+    }
+
+    #[test]
+    fn device_and_feedback_threads_share_state() {
+        let state = Arc::new(Mutex::new(state("")));
+        let queue = OutputQueue::default();
+        let outputs = Outputs {
+            rb_out: Box::new(RecordingSink::default()),
+            color_out: Box::new(RecordingSink::default()),
+            clock_out: None,
+            routes: HashMap::new(),
+        };
+        let worker = output::spawn_worker(queue.clone(), outputs, Arc::default());
+        let (done, finished) = std::sync::mpsc::channel();
+
+        let device = thread::spawn({
+            let (state, queue, done) = (state.clone(), queue.clone(), done.clone());
+            move || {
+                for i in 0..2000 {
+                    let message = [0xBF, 0x0F, (i % 128) as u8];
+                    update(&state, &queue, |state, out, color_out| {
+                        state.transform(&message, Instant::now(), out, color_out)
+                    })
+                    .unwrap();
+                }
+                done.send(()).unwrap();
+            }
+        });
+        let rekordbox = thread::spawn({
+            let queue = queue.clone();
+            move || {
+                for i in 0..2000 {
+                    let message = [0x9F, 0x30, if i % 2 == 0 { 0x7F } else { 0x00 }];
+                    forward_feedback(&state, &queue, &message, None).unwrap();
+                }
+                done.send(()).unwrap();
+            }
+        });
+
+        for _ in 0..2 {
+            finished
+                .recv_timeout(Duration::from_secs(30))
+                .expect("the threads deadlocked");
+        }
+        device.join().unwrap();
+        rekordbox.join().unwrap();
+        queue.close();
+        worker.join().unwrap();
+    }
+
+    // With one connection to the device, LEDs and feedback from Rekordbox reach it in the order
+    // they were produced.
+    #[test]
+    fn shared_device_output_keeps_led_order() {
+        let state = Mutex::new(state(""));
+        let queue = OutputQueue::default();
+        let (device_out, device_in) = std::sync::mpsc::channel();
+        let outputs = Outputs {
+            rb_out: Box::new(RecordingSink::default()),
+            color_out: Box::new(device_out),
+            clock_out: None,
+            routes: HashMap::new(),
+        };
+        let worker = output::spawn_worker(queue.clone(), outputs, Arc::default());
+
+        let select_deck = |note| {
+            update(&state, &queue, |state, out, color_out| {
+                state.transform(&[0x9F, note, 0x7F], Instant::now(), out, color_out)
+            })
+            .unwrap()
+        };
+        let feedback = |message: &[u8]| forward_feedback(&state, &queue, message, None).unwrap();
+        select_deck(0x1F);
+        feedback(&[0x9F, 0x30, 0x7F]);
+        select_deck(0x23);
+        feedback(&[0x8F, 0x30, 0x00]);
+        queue.close();
+        worker.join().unwrap();
+
+        let sent: Vec<_> = device_in.try_iter().collect();
+        assert_eq!(
+            sent,
+            [
+                [0x8F, 0x23, 0x7F],
+                [0x9F, 0x1F, 0x7F],
+                [0x8F, 0x27, 0x7F],
+                [0x9F, 0x30, 0x7F],
+                [0x9F, 0x23, 0x7F],
+                [0x8F, 0x1F, 0x7F],
+                [0x8F, 0x27, 0x7F],
+                [0x8F, 0x30, 0x00],
+            ]
+        );
+    }
+
+    #[test]
+    fn momentary_filter_release_returns_to_neutral() {
+        // Released with a note off, then with a note on of velocity 0.
+        for release in [[0x8F, 0x29, 0x00], [0x9F, 0x29, 0x00]] {
+            let mut state = state("[filter]\nmomentary = true\n");
+            let sent = play(
+                &mut state,
+                &[
+                    (0, &[0xBF, 0x0F, 0x50]),
+                    (10, &[0x9F, 0x29, 0x7F]),
+                    (20, &release),
+                ],
+            );
+            let deck1: Vec<_> = sent.iter().filter(|m| m[..2] == [0xBC, 0x01]).collect();
+            // Neutral until held, the encoder's value while held, and neutral again once released.
+            assert_eq!(
+                deck1,
+                [
+                    &[0xBC, 0x01, 0x3F],
+                    &[0xBC, 0x01, 0x50],
+                    &[0xBC, 0x01, 0x3F]
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_values_are_sent_once() {
+        let filter = [0xBF, 0x0F, 0x50];
+        let sent = play(&mut state(""), &[(0, &filter), (100, &filter)]);
+        assert_eq!(
+            sent,
+            [[0xBC, 0x01, 0x3F], [0xBC, 0x02, 0x3F], [0xBC, 0x00, 0x3F]]
+        );
+
+        // Turning the master volume past its end leaves nothing new to send either.
+        let mut state = state("[[pots]]\ncc = 3\ndefault = 126\n");
+        let up = [0xBF, 0x03, 0x01];
+        let sent = play(&mut state, &[(0, &up), (100, &up), (200, &up)]);
+        assert_eq!(sent, [[0xBF, 0x03, 0x7F]]);
+    }
+
+    #[test]
+    fn pot_sub_steps_add_up() {
+        let mut state = state("[output.pots.3]\nout_max = 12\n");
+        let ticks: Vec<_> = (0..10).map(|i| (i * 20, &[0xBF, 0x03, 0x01][..])).collect();
+        assert_eq!(
+            play(&mut state, &ticks),
+            [
+                [0xBF, 0x03, 0x06],
+                [0xBF, 0x03, 0x07],
+                [0xBF, 0x03, 0x08],
+                [0xBF, 0x03, 0x09]
+            ]
+        );
+    }
+
+    // Deck 1's filter toggle, pressed on an excluded channel.
+    fn excluded_button(policy: &str) -> Vec<Vec<u8>> {
+        let config = format!("[input]\ndeny_channels = [15]\nexcluded_channels = \"{policy}\"\n");
+        let mut state = state(&config);
+        let sent = play(
+            &mut state,
+            &[(0, &[0x9F, 0x29, 0x7F]), (100, &[0x8F, 0x29, 0x00])],
+        );
+        assert_eq!(state.filter_encoder.toggles, [0; 3]);
+        sent
+    }
+
+    #[test]
+    fn excluded_channels_skip_mapped_buttons() {
+        assert_eq!(excluded_button("drop"), Vec::<Vec<u8>>::new());
+        assert_eq!(
+            excluded_button("forward"),
+            [[0x9F, 0x29, 0x7F], [0x8F, 0x29, 0x00]]
+        );
+    }
+
+    #[test]
+    fn absolute_controls_are_forwarded() {
+        let mut state = state(
+            "[controls]\n3 = { type = \"absolute\" }\n\
+             4 = { type = \"absolute\", out_cc = 30, channel = 5 }\n",
+        );
+        let input = [(0, &[0xBF, 0x03, 0x40][..]), (100, &[0xBF, 0x04, 0x7F])];
+        assert_eq!(
+            play(&mut state, &input),
+            [[0xBF, 0x03, 0x40], [0xB5, 0x1E, 0x7F]]
+        );
+        assert!(
+            Config::from_text("[controls]\n4 = { type = \"absolute\", out_cc = 128 }").is_err()
+        );
+    }
+
+    #[test]
+    fn absolute_pots_pass_values_through() {
+        let mut state = state("[[pots]]\ncc = 20\nmode = \"absolute\"\n");
+        let input = [
+            (0, &[0xBF, 0x14, 0x00][..]),
+            (100, &[0xBF, 0x14, 0x40]),
+            (200, &[0xBF, 0x14, 0x7F]),
+        ];
+        assert_eq!(
+            play(&mut state, &input),
+            [[0xBF, 0x14, 0x00], [0xBF, 0x14, 0x40], [0xBF, 0x14, 0x7F]]
+        );
+        assert_eq!(state.unknown_values, 0);
+    }
+
+    #[test]
+    fn relative_pots_step_by_three() {
+        let mut state = state("[[pots]]\ncc = 20\ndefault = 64\n");
+        let input = [
+            (0, &[0xBF, 0x14, 0x01][..]),
+            (100, &[0xBF, 0x14, 0x01]),
+            (200, &[0xBF, 0x14, 0x7F]),
+            (300, &[0xBF, 0x14, 0x05]),
+        ];
+        assert_eq!(
+            play(&mut state, &input),
+            [[0xBF, 0x14, 0x43], [0xBF, 0x14, 0x46], [0xBF, 0x14, 0x43]]
+        );
+        // Anything else is warned about, and leaves the pot where it was.
+        assert_eq!(state.unknown_values, 1);
+        assert_eq!(state.pots[&0x14].value, 67.0);
+    }
+
+    #[test]
+    fn armed_tempo_takes_over_on_the_first_coarse_movement() {
+        let mut state =
+            state("tempo_arm_on_first_touch = true\n[tempo_fine]\nnote = 0x0E\nfactor = 0.25\n");
+        let fine = [(0, &[0x9F, 0x0E, 0x7F][..]), (10, &[0x8F, 0x0E, 0x00])];
+        let mut input = fine.to_vec();
+        input.extend([(100, &[0xBF, 0x13, 0x40][..]), (120, &[0xBF, 0x13, 0x48])]);
+        input.extend(fine.iter().map(|&(ms, m)| (ms + 200, m)));
+        input.push((300, &[0xBF, 0x13, 0x50]));
+        // The fine movements only nudge the tempo, and the first coarse one then takes over
+        // straight away, even though the fader hasn't passed the tempo.
+        assert_eq!(
+            play(&mut state, &input),
+            [[0xBB, 0x01, 0x40], [0xBB, 0x01, 0x3E], [0xBB, 0x01, 0x2F]]
+        );
+
+        // After that, the fader has to pass the tempo again as usual.
+        let input = [
+            (0, &[0x9F, 0x0E, 0x7F][..]),
+            (10, &[0x8F, 0x0E, 0x00]),
+            (100, &[0xBF, 0x13, 0x60]),
+            (200, &[0x9F, 0x0E, 0x7F]),
+            (210, &[0x8F, 0x0E, 0x00]),
+            (300, &[0xBF, 0x13, 0x58]),
+            (400, &[0xBF, 0x13, 0x50]),
+        ];
+        assert_eq!(
+            play(&mut state, &input),
+            [[0xBB, 0x01, 0x2B], [0xBB, 0x01, 0x2F]]
+        );
+    }
+
+    #[test]
+    fn tempo_fine_sub_steps_add_up() {
+        let mut state = state("[tempo_fine]\nnote = 0x0E\nfactor = 0.25\n");
+        let mut input = vec![
+            (0, &[0xBF, 0x13, 0x40][..]),
+            (50, &[0x9F, 0x0E, 0x7F]),
+            (60, &[0x8F, 0x0E, 0x00]),
+        ];
+        let moves: Vec<_> = (0x41..=0x4A).map(|x| [0xBF, 0x13, x]).collect();
+        input.extend(moves.iter().zip(0..).map(|(m, i)| (120 + i * 20, &m[..])));
+        assert_eq!(
+            play(&mut state, &input),
+            [
+                [0xBB, 0x01, 0x3F],
+                [0xBB, 0x01, 0x3E],
+                [0xBB, 0x01, 0x3D],
+                [0xBB, 0x01, 0x3C]
+            ]
+        );
+    }
 }
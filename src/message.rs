@@ -0,0 +1,94 @@
+use crate::{CONTROL_CHANGE, NOTE_OFF, NOTE_ON, PITCH_BEND};
+use std::error::Error;
+use std::fmt;
+
+// A three byte channel message, checked before it's sent. A data byte with the high bit set would
+// be read as a new status byte, which can desync a parser badly enough that the port needs
+// reopening, so a config mistake or arithmetic bug must never get that far.
+#[derive(Debug, Clone, Copy)]
+pub struct OutgoingMessage {
+    kind: u8,
+    channel: u8,
+    data: [u8; 2],
+}
+
+impl OutgoingMessage {
+    pub fn new(kind: u8, channel: u8) -> Self {
+        Self {
+            kind,
+            channel,
+            data: [0, 0],
+        }
+    }
+
+    pub fn data(mut self, code: u8, value: u8) -> Self {
+        self.data = [code, value];
+        self
+    }
+
+    pub fn build(self) -> Result<[u8; 3], InvalidMessage> {
+        if !matches!(self.kind, NOTE_OFF | NOTE_ON | CONTROL_CHANGE | PITCH_BEND) {
+            return Err(InvalidMessage::Kind(self.kind));
+        }
+        if self.channel > 15 {
+            return Err(InvalidMessage::Channel(self.channel));
+        }
+        if let Some(&byte) = self.data.iter().find(|&&x| x > 127) {
+            return Err(InvalidMessage::Data(byte));
+        }
+        Ok([self.kind | self.channel, self.data[0], self.data[1]])
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidMessage {
+    Kind(u8),
+    Channel(u8),
+    Data(u8),
+}
+
+impl fmt::Display for InvalidMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidMessage::Kind(kind) => write!(f, "{:#04X} is not a channel message kind", kind),
+            InvalidMessage::Channel(channel) => write!(f, "channel {} is outside 0-15", channel),
+            InvalidMessage::Data(byte) => write!(f, "data byte {} is outside 0-127", byte),
+        }
+    }
+}
+
+impl Error for InvalidMessage {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Mostly valid bytes, so that valid messages are built often enough to check them too.
+    fn kind() -> impl Strategy<Value = u8> {
+        prop_oneof![
+            prop::sample::select(vec![NOTE_OFF, NOTE_ON, CONTROL_CHANGE, PITCH_BEND]),
+            any::<u8>(),
+        ]
+    }
+
+    fn byte(max: u8) -> impl Strategy<Value = u8> {
+        prop_oneof![3 => 0..=max, 1 => any::<u8>()]
+    }
+
+    proptest! {
+        // Whatever the builder is given, it either builds a valid message or says what's wrong.
+        #[test]
+        fn built_messages_are_valid(kind in kind(), channel in byte(15), a in byte(127), b in byte(127)) {
+            let built = OutgoingMessage::new(kind, channel).data(a, b).build();
+            let valid_kind = matches!(kind, NOTE_OFF | NOTE_ON | CONTROL_CHANGE | PITCH_BEND);
+            prop_assert_eq!(built.is_ok(), valid_kind && channel <= 15 && a <= 127 && b <= 127);
+            match built {
+                Ok(message) => prop_assert_eq!(message, [kind | channel, a, b]),
+                Err(InvalidMessage::Kind(x)) => prop_assert_eq!(x, kind),
+                Err(InvalidMessage::Channel(x)) => prop_assert_eq!(x, channel),
+                Err(InvalidMessage::Data(x)) => prop_assert!(x > 127 && (x == a || x == b)),
+            }
+        }
+    }
+}
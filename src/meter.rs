@@ -0,0 +1,91 @@
+use crate::config::MeterConfig;
+use crate::message::OutgoingMessage;
+use crate::sink::MidiSink;
+use crate::{Result, CONTROL_CHANGE};
+use std::time::{Duration, Instant};
+
+// Redraws a level meter from Rekordbox on the device's segment LEDs, optionally holding the peak.
+pub struct Meter {
+    config: MeterConfig,
+    // Segments lit by the latest level from Rekordbox.
+    level: u8,
+    // The highest level since it was last let go, and when it was reached.
+    peak: u8,
+    peak_at: Option<Instant>,
+    // What the device was last sent, so that levels which light the same segments aren't re-sent.
+    sent: Option<u8>,
+}
+
+impl Meter {
+    pub fn new(config: MeterConfig) -> Self {
+        Self {
+            config,
+            level: 0,
+            peak: 0,
+            peak_at: None,
+            sent: None,
+        }
+    }
+
+    // Takes `message` if it's this meter's level from Rekordbox.
+    pub fn handle_feedback(
+        &mut self,
+        message: &[u8],
+        now: Instant,
+        device_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        let [status, cc, value] = *message else {
+            return Ok(false);
+        };
+        if status & 0xF0 != CONTROL_CHANGE
+            || status & 0x0F != self.config.channel
+            || cc != self.config.cc
+        {
+            return Ok(false);
+        }
+
+        self.level = self.config.quantize(value);
+        if self.config.peak_hold && self.level >= self.peak {
+            self.peak = self.level;
+            self.peak_at = Some(now);
+        }
+        self.send(device_out)?;
+        Ok(true)
+    }
+
+    // Lets a held peak fall back to the level once it's been held long enough.
+    pub fn tick(&mut self, now: Instant, device_out: &mut dyn MidiSink) -> Result<()> {
+        let Some(peak_at) = self.peak_at else {
+            return Ok(());
+        };
+        if now.duration_since(peak_at) < Duration::from_millis(self.config.peak_hold_ms) {
+            return Ok(());
+        }
+
+        self.peak = self.level;
+        self.peak_at = Some(now);
+        self.send(device_out)
+    }
+
+    pub fn clear(&self, device_out: &mut dyn MidiSink) -> Result<()> {
+        device_out.send(&self.message(0)?)
+    }
+
+    fn send(&mut self, device_out: &mut dyn MidiSink) -> Result<()> {
+        let segments = self.level.max(self.peak);
+        if self.sent == Some(segments) {
+            return Ok(());
+        }
+
+        self.sent = Some(segments);
+        device_out.send(&self.message(segments)?)
+    }
+
+    fn message(&self, segments: u8) -> Result<[u8; 3]> {
+        Ok(
+            OutgoingMessage::new(CONTROL_CHANGE, self.config.out_channel())
+                .data(self.config.out_cc(), segments)
+                .build()?,
+        )
+    }
+}
@@ -0,0 +1,65 @@
+use crate::sink::MidiSink;
+use crate::{log_send, Result, CONTROL_CHANGE};
+
+// Selects the parameter, then sets its value, each as a most and least significant 7 bits.
+const PARAMETER_MSB_CC: u8 = 99;
+const PARAMETER_LSB_CC: u8 = 98;
+const DATA_ENTRY_MSB_CC: u8 = 6;
+const DATA_ENTRY_LSB_CC: u8 = 38;
+
+// The CCs an NRPN is sent as, in order.
+pub const CCS: [u8; 4] = [
+    PARAMETER_MSB_CC,
+    PARAMETER_LSB_CC,
+    DATA_ENTRY_MSB_CC,
+    DATA_ENTRY_LSB_CC,
+];
+
+// The largest parameter number or value.
+pub const MAX: u16 = 0x3FFF;
+
+// Maps a value within 0-127, which may fall between steps, onto the full 14 bits.
+pub fn from_7bit(value: f32) -> u16 {
+    (value.clamp(0.0, 127.0) / 127.0 * MAX as f32).round() as u16
+}
+
+// Sets `parameter` to `value` on `channel`.
+pub fn send(channel: u8, parameter: u16, value: u16, out: &mut dyn MidiSink) -> Result<()> {
+    let value = value.min(MAX);
+    let data = [parameter >> 7, parameter & 0x7F, value >> 7, value & 0x7F];
+    for (cc, data) in CCS.into_iter().zip(data) {
+        log_send(channel, CONTROL_CHANGE, cc, data as u8, out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn sends_parameter_then_value() {
+        let mut out = RecordingSink::default();
+        send(0x0B, 0x0123, 0x2ABC, &mut out).unwrap();
+        assert_eq!(
+            out.messages,
+            [
+                [0xBB, 99, 0x02],
+                [0xBB, 98, 0x23],
+                [0xBB, 6, 0x55],
+                [0xBB, 38, 0x3C],
+            ]
+        );
+    }
+
+    #[test]
+    fn values_are_limited_to_14_bits() {
+        let mut out = RecordingSink::default();
+        send(0x0C, 1, u16::MAX, &mut out).unwrap();
+        assert_eq!(out.messages[2..], [[0xBC, 6, 0x7F], [0xBC, 38, 0x7F]]);
+        assert_eq!(from_7bit(0.0), 0);
+        assert_eq!(from_7bit(127.0), MAX);
+        assert_eq!(from_7bit(200.0), MAX);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::nrpn;
+use crate::session_log;
+use crate::sink::MidiSink;
+use crate::status;
+use crate::{ACTIVE_SENSING, CHANNEL_PRESSURE, CONTROL_CHANGE, PITCH_BEND};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+// How many messages may be waiting to be sent before superseded continuous values start being
+// dropped.
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Dest {
+    Rekordbox,
+    // Another port messages for Rekordbox are routed to, by the route's port as configured.
+    Route(String),
+    Device,
+    // External gear started and stopped by clock rules.
+    Clock,
+}
+
+// Messages for either output, in the order they were produced.
+pub type Batch = Vec<(Dest, Vec<u8>)>;
+
+// A batch being recorded into by a sink per output, so that the relative order of messages to
+// each is kept. The sinks hold their own handle to it rather than borrowing it, so that they're
+// Send like any other sink.
+#[derive(Clone, Default)]
+pub struct SharedBatch(Arc<Mutex<Batch>>);
+
+impl SharedBatch {
+    pub fn sink(&self, dest: Dest) -> BatchSink {
+        BatchSink {
+            batch: self.clone(),
+            dest,
+        }
+    }
+
+    // Everything recorded so far, leaving the batch empty.
+    pub fn take(&self) -> Batch {
+        std::mem::take(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+pub struct BatchSink {
+    batch: SharedBatch,
+    dest: Dest,
+}
+
+impl MidiSink for BatchSink {
+    fn send(&mut self, message: &[u8]) -> crate::Result<()> {
+        self.batch
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((self.dest.clone(), message.to_vec()));
+        Ok(())
+    }
+}
+
+// The connections messages are sent on, owned by the output worker.
+pub struct Outputs {
+    pub rb_out: Box<dyn MidiSink>,
+    pub color_out: Box<dyn MidiSink>,
+    // Only opened with --clock-out.
+    pub clock_out: Option<Box<dyn MidiSink>>,
+    // Keyed by the route's port, as configured. Messages for a route which wasn't opened at startup
+    // go to Rekordbox.
+    pub routes: HashMap<String, Box<dyn MidiSink>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<(Dest, Vec<u8>)>,
+    closed: bool,
+}
+
+// Messages waiting for the output worker, so that the MIDI callbacks never block on sending.
+#[derive(Clone, Default)]
+pub struct OutputQueue {
+    shared: Arc<(Mutex<Queue>, Condvar)>,
+}
+
+impl OutputQueue {
+    pub fn push(&self, batch: Batch) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let (queue, ready) = &*self.shared;
+        let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.messages.extend(batch);
+        // A continuous control's value can be dropped once a later one for the same control is
+        // queued, whereas a lost note could leave something held. If nothing queued has been
+        // superseded, the queue is allowed to grow.
+        while queue.messages.len() > CAPACITY {
+            let Some(superseded) = oldest_superseded(&queue.messages) else {
+                break;
+            };
+            for (_, dropped) in queue.messages.drain(superseded) {
+                status::message(format_args!("Dropped {:?} (OUTPUT QUEUE FULL)", dropped));
+            }
+        }
+        ready.notify_one();
+    }
+
+    // Lets the worker send whatever is still queued, then exit.
+    pub fn close(&self) {
+        let (queue, ready) = &*self.shared;
+        queue.lock().unwrap_or_else(PoisonError::into_inner).closed = true;
+        ready.notify_one();
+    }
+
+    fn pop_all(&self) -> Option<VecDeque<(Dest, Vec<u8>)>> {
+        let (queue, ready) = &*self.shared;
+        let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+        while queue.messages.is_empty() && !queue.closed {
+            queue = ready.wait(queue).unwrap_or_else(PoisonError::into_inner);
+        }
+        if queue.messages.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut queue.messages))
+    }
+}
+
+fn is_continuous(message: &[u8]) -> bool {
+    message.first().is_some_and(|status| {
+        matches!(
+            status & 0xF0,
+            CONTROL_CHANGE | CHANNEL_PRESSURE | PITCH_BEND
+        )
+    })
+}
+
+// What a continuous value is for, so that a later value for the same thing supersedes it.
+#[derive(PartialEq, Eq, Hash)]
+enum Control {
+    // The controller, or none for channel pressure and pitch bend.
+    Code(Option<u8>),
+    // An NRPN's parameter, as its most and least significant 7 bits.
+    Nrpn(u8, u8),
+}
+
+// The oldest continuous value queued which a later one for the same control, status and output
+// supersedes. An NRPN's four messages are dropped together, or not at all.
+fn oldest_superseded(messages: &VecDeque<(Dest, Vec<u8>)>) -> Option<Range<usize>> {
+    let mut values = Vec::new();
+    let mut i = 0;
+    while i < messages.len() {
+        let (dest, message) = &messages[i];
+        let (len, control) = match nrpn_parameter(messages, i) {
+            Some((msb, lsb)) => (nrpn::CCS.len(), Some(Control::Nrpn(msb, lsb))),
+            // Part of an NRPN which isn't all queued, so it's left alone.
+            None if controller(message).is_some_and(|cc| nrpn::CCS.contains(&cc)) => (1, None),
+            None if is_continuous(message) => (1, Some(Control::Code(controller(message)))),
+            None => (1, None),
+        };
+        if let Some(control) = control {
+            values.push((i..i + len, (dest, message[0], control)));
+        }
+        i += len;
+    }
+
+    let mut later = HashSet::new();
+    let mut oldest = None;
+    for (range, control) in values.into_iter().rev() {
+        if !later.insert(control) {
+            oldest = Some(range);
+        }
+    }
+    oldest
+}
+
+// The parameter of an NRPN whose four messages start at `i`, all to the same output and channel.
+fn nrpn_parameter(messages: &VecDeque<(Dest, Vec<u8>)>, i: usize) -> Option<(u8, u8)> {
+    let (dest, first) = messages.get(i)?;
+    let mut data = [0; 4];
+    for (j, cc) in nrpn::CCS.into_iter().enumerate() {
+        let (x_dest, x) = messages.get(i + j)?;
+        if controller(x) != Some(cc) || x_dest != dest || x[0] != first[0] {
+            return None;
+        }
+        data[j] = x[2];
+    }
+    Some((data[0], data[1]))
+}
+
+fn controller(message: &[u8]) -> Option<u8> {
+    match message {
+        &[status, cc, _] if status & 0xF0 == CONTROL_CHANGE => Some(cc),
+        _ => None,
+    }
+}
+
+// Sends queued messages in order until the queue is closed and empty, then closes the connections.
+// A keepalive which fails to send flags the connection to the device as lost.
+pub fn spawn_worker(
+    queue: OutputQueue,
+    mut outputs: Outputs,
+    connection_lost: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while let Some(messages) = queue.pop_all() {
+            for (dest, message) in messages {
+                let out = match &dest {
+                    Dest::Rekordbox => &mut outputs.rb_out,
+                    Dest::Route(port) => match outputs.routes.get_mut(port) {
+                        Some(route_out) => route_out,
+                        None => &mut outputs.rb_out,
+                    },
+                    Dest::Device => &mut outputs.color_out,
+                    Dest::Clock => match &mut outputs.clock_out {
+                        Some(clock_out) => clock_out,
+                        None => continue,
+                    },
+                };
+                let result = out.send(&message);
+                if result.is_ok() && matches!(dest, Dest::Rekordbox | Dest::Route(_)) {
+                    status::sent();
+                }
+                if let Err(e) = result {
+                    if dest == Dest::Device && message == [ACTIVE_SENSING] {
+                        session_log::error(format_args!(
+                            "!!! Keepalive to the device failed: {}",
+                            e
+                        ));
+                        connection_lost.store(true, Ordering::Relaxed);
+                    } else {
+                        session_log::event(format_args!(
+                            "Failed to send {:?} to {:?}: {}",
+                            message, dest, e
+                        ));
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn queued(queue: &OutputQueue) -> Vec<Vec<u8>> {
+        let messages = &queue.shared.0.lock().unwrap().messages;
+        messages.iter().map(|(_, x)| x.clone()).collect()
+    }
+
+    fn nrpn(parameter: u16, value: u16) -> Batch {
+        let mut out = RecordingSink::default();
+        nrpn::send(0x0C, parameter, value, &mut out).unwrap();
+        out.messages
+            .into_iter()
+            .map(|x| (Dest::Rekordbox, x))
+            .collect()
+    }
+
+    #[test]
+    fn full_queue_only_drops_superseded_values() {
+        let queue = OutputQueue::default();
+        let notes = (0..CAPACITY).map(|i| (Dest::Device, vec![0x9F, (i % 128) as u8, 0x7F]));
+        queue.push(notes.collect());
+        queue.push(vec![
+            (Dest::Rekordbox, vec![0xBF, 0x03, 0x10]),
+            (Dest::Rekordbox, vec![0xBF, 0x14, 0x10]),
+            (Dest::Device, vec![0xBF, 0x03, 0x11]),
+            (Dest::Rekordbox, vec![0xBF, 0x03, 0x12]),
+        ]);
+
+        // Only the first CC 3 to Rekordbox has a later value to the same place.
+        let messages = queued(&queue);
+        assert_eq!(messages.len(), CAPACITY + 3);
+        assert_eq!(
+            messages[CAPACITY..],
+            [[0xBF, 0x14, 0x10], [0xBF, 0x03, 0x11], [0xBF, 0x03, 0x12]]
+        );
+    }
+
+    #[test]
+    fn full_queue_drops_whole_nrpns() {
+        let queue = OutputQueue::default();
+        let notes = (0..CAPACITY - 8).map(|i| (Dest::Device, vec![0x9F, (i % 128) as u8, 0x7F]));
+        queue.push(notes.collect());
+        queue.push(nrpn(1, 100));
+        queue.push(nrpn(2, 100));
+        queue.push(nrpn(1, 200));
+
+        let messages = queued(&queue);
+        assert_eq!(messages.len(), CAPACITY);
+        let mut expected = nrpn(2, 100);
+        expected.extend(nrpn(1, 200));
+        let expected: Vec<_> = expected.into_iter().map(|(_, x)| x).collect();
+        assert_eq!(messages[CAPACITY - 8..], expected);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config::{LedConfig, PadRowConfig, ReleasePolicy};
+use crate::led::{Led, LedColor};
+use crate::sink::MidiSink;
+use crate::{send_button, Result};
+
+// A row of momentary pads, e.g. for beat loop rolls: each is held in Rekordbox exactly as long as
+// it's held on the device, and lit while held.
+pub struct PadRow {
+    config: PadRowConfig,
+    leds: LedConfig,
+    releases: [ReleasePolicy; 4],
+    held: [bool; 4],
+}
+
+impl PadRow {
+    pub fn new(config: PadRowConfig, leds: LedConfig, releases: [ReleasePolicy; 4]) -> Self {
+        Self {
+            config,
+            leds,
+            releases,
+            held: [false; 4],
+        }
+    }
+
+    pub fn handle_button(
+        &mut self,
+        note: u8,
+        state: bool,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        let Some(i) = self.config.notes.iter().position(|&x| x == note) else {
+            return Ok(false);
+        };
+
+        // Repeated presses or releases would otherwise retrigger or double-release the roll.
+        if self.held[i] != state {
+            self.held[i] = state;
+            self.send(i, out, color_out)?;
+        }
+        Ok(true)
+    }
+
+    fn send(&self, i: usize, out: &mut dyn MidiSink, color_out: &mut dyn MidiSink) -> Result<()> {
+        let (data, color) = if self.held[i] {
+            (127, self.leds.pad_held)
+        } else {
+            (0, self.leds.pad_released)
+        };
+        send_button(
+            self.config.channel,
+            self.config.out_notes[i],
+            data,
+            self.held[i],
+            self.releases[i],
+            out,
+        )?;
+        Led::new(self.config.notes[i]).set(color, color_out)
+    }
+
+    // Forgets any pads still held, once their notes have been released in Rekordbox.
+    pub fn release(&mut self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for (i, held) in self.held.iter_mut().enumerate() {
+            if std::mem::take(held) {
+                Led::new(self.config.notes[i]).set(self.leds.pad_released, color_out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn clear_leds(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        for note in self.config.notes {
+            Led::new(note).set(LedColor::Off, color_out)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,423 @@
+use crate::session_log;
+use crate::Result;
+use midir::{MidiIO, MidiInput, MidiInputPort, MidiOutput, MidiOutputPort};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often ports are re-listed while waiting for a device to appear.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How many invalid answers to a port prompt are tolerated before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+
+// The names ports are listed and matched by: each port's own name, followed by " #N" if another port
+// has the same one (e.g. two identical controllers), N counting those ports in the order the OS
+// lists them.
+fn port_names<T: MidiIO>(midi_io: &T, ports: &[T::Port]) -> Result<Vec<String>> {
+    let names = ports
+        .iter()
+        .map(|p| midi_io.port_name(p))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            if names.iter().filter(|other| *other == name).count() == 1 {
+                return name.clone();
+            }
+            let n = names[..i].iter().filter(|other| *other == name).count() + 1;
+            format!("{} #{}", name, n)
+        })
+        .collect())
+}
+
+// The name `port` is listed and matched by, which tells it apart from identically named ports.
+pub fn port_key<T: MidiIO>(midi_io: &T, port: &T::Port) -> Result<String>
+where
+    T::Port: PartialEq,
+{
+    let ports = midi_io.ports();
+    let names = port_names(midi_io, &ports)?;
+    match ports.iter().position(|p| p == port) {
+        Some(i) => Ok(names[i].clone()),
+        None => Ok(midi_io.port_name(port)?),
+    }
+}
+
+// Prompts on stdin until a port is chosen. Entering nothing picks the only port if there's just one,
+// or otherwise the one named `default`, if it exists.
+pub fn select_port<T: MidiIO>(midi_io: &T, descr: &str, default: Option<&str>) -> Result<T::Port> {
+    select_port_with(midi_io, descr, default, &mut stdin().lock(), &mut stdout())
+}
+
+fn select_port_with<T: MidiIO>(
+    midi_io: &T,
+    descr: &str,
+    default: Option<&str>,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<T::Port> {
+    for _ in 0..MAX_ATTEMPTS {
+        // Listed again on every attempt, in case the device was plugged in after seeing the list.
+        let midi_ports = midi_io.ports();
+        if midi_ports.is_empty() {
+            let mut message = format!("No {} ports found — is your device connected?", descr);
+            if descr == "output" {
+                message
+                    .push_str(" On Windows, a loopback port (e.g. from loopMIDI) is also needed.");
+            }
+            return Err(message.into());
+        }
+
+        let names = port_names(midi_io, &midi_ports)?;
+        let default_index = if names.len() == 1 {
+            Some(0)
+        } else {
+            default.and_then(|default| names.iter().position(|name| name == default))
+        };
+
+        writeln!(output, "Available {} ports:", descr)?;
+        for (i, name) in names.iter().enumerate() {
+            writeln!(output, "{}: {}", i, name)?;
+        }
+        match default_index {
+            Some(i) => write!(output, "Please select {} port [{}]: ", descr, i)?,
+            None => write!(output, "Please select {} port: ", descr)?,
+        }
+        output.flush()?;
+
+        let mut line = String::new();
+        // Stdin is closed, e.g. from /dev/null or under a service manager, so there's nothing to
+        // wait for.
+        if input.read_line(&mut line)? == 0 {
+            return Err(format!(
+                "No {} port selected, as stdin was closed before one was chosen. Without a terminal, \
+                 choose the ports with --device-in and --device-out instead",
+                descr
+            )
+            .into());
+        }
+        match parse_choice(line.trim(), &names, default_index) {
+            Ok(i) => return Ok(midi_ports[i].clone()),
+            Err(e) => writeln!(output, "{}\n", e)?,
+        }
+    }
+
+    Err(format!(
+        "No valid {} port selected after {} attempts",
+        descr, MAX_ATTEMPTS
+    )
+    .into())
+}
+
+// Resolves what was entered at the prompt, either a port number or part of a port's name, to an
+// index into `names`. A whole name is taken even if it's also part of others, so that "Port #1" isn't
+// ambiguous with "Port #10".
+fn parse_choice(
+    choice: &str,
+    names: &[String],
+    default: Option<usize>,
+) -> std::result::Result<usize, String> {
+    let help = format!(
+        "Please enter a number between 0 and {}, or part of a port's name",
+        names.len() - 1
+    );
+    if choice.is_empty() {
+        return default.ok_or(help);
+    }
+    if let Ok(i) = choice.parse::<usize>() {
+        return if i < names.len() {
+            Ok(i)
+        } else {
+            Err(format!("There is no port {}. {}", i, help))
+        };
+    }
+
+    if let Some(i) = names.iter().position(|name| name == choice) {
+        return Ok(i);
+    }
+    let matches: Vec<_> = (0..names.len())
+        .filter(|&i| names[i].contains(choice))
+        .collect();
+    match matches[..] {
+        [i] => Ok(i),
+        [] => Err(format!("No port's name contains \"{}\". {}", choice, help)),
+        _ => Err(format!("More than one port's name contains \"{}\"", choice)),
+    }
+}
+
+#[derive(Serialize)]
+struct PortList {
+    inputs: Vec<Port>,
+    outputs: Vec<Port>,
+}
+
+#[derive(Serialize)]
+struct Port {
+    index: usize,
+    name: String,
+    // The name with the port's number among identically named ports, if there are any, which can be
+    // passed wherever a port name is taken.
+    key: String,
+}
+
+fn ports<T: MidiIO>(midi_io: &T) -> Result<Vec<Port>> {
+    let midi_ports = midi_io.ports();
+    let keys = port_names(midi_io, &midi_ports)?;
+    midi_ports
+        .iter()
+        .zip(keys)
+        .enumerate()
+        .map(|(index, (p, key))| {
+            Ok(Port {
+                index,
+                name: midi_io.port_name(p)?,
+                key,
+            })
+        })
+        .collect()
+}
+
+// Prints every input and output port. The indices are the same ones the port prompts accept.
+pub fn list(json: bool) -> Result<()> {
+    let list = PortList {
+        inputs: ports(&MidiInput::new("PartySaver list in")?)?,
+        outputs: ports(&MidiOutput::new("PartySaver list out")?)?,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&list)?);
+        return Ok(());
+    }
+    for (descr, ports) in [("input", &list.inputs), ("output", &list.outputs)] {
+        println!("Available {} ports:", descr);
+        for port in ports {
+            println!("{}: {}", port.index, port.key);
+        }
+    }
+    Ok(())
+}
+
+// Friendly names for ports, each standing for part of a port's name, so that launch scripts don't
+// depend on names the OS may change, e.g. `main-controller = "XONE:K2"`.
+#[derive(Debug, Default, Deserialize)]
+pub struct Aliases(HashMap<String, String>);
+
+impl Aliases {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read aliases {}: {}", path.display(), e))?;
+        let aliases = toml::from_str(&text)
+            .map_err(|e| format!("Invalid aliases {}: {}", path.display(), e))?;
+        Ok(aliases)
+    }
+
+    // The pattern `name` stands for, or `name` itself if it isn't an alias.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.0.get(name).map_or(name, String::as_str)
+    }
+}
+
+// Returns the only port whose name contains `pattern`, or an error listing the candidates if there
+// are several. Identically named ports are told apart by their number, as listed.
+pub fn find_unique_port<T: MidiIO>(midi_io: &T, descr: &str, pattern: &str) -> Result<T::Port> {
+    let ports = midi_io.ports();
+    let names = port_names(midi_io, &ports)?;
+    if let Some(i) = names.iter().position(|name| name == pattern) {
+        return Ok(ports[i].clone());
+    }
+    let mut matches: Vec<_> = ports
+        .into_iter()
+        .zip(names)
+        .filter(|(_, name)| name.contains(pattern))
+        .collect();
+
+    match matches.len() {
+        1 => Ok(matches.remove(0).0),
+        0 => Err(format!("No {} port's name contains \"{}\"", descr, pattern).into()),
+        _ => {
+            let names: Vec<_> = matches.into_iter().map(|(_, name)| name).collect();
+            Err(format!(
+                "More than one {} port's name contains \"{}\": {}",
+                descr,
+                pattern,
+                names.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+// Returns the first port whose name contains `pattern`, telling identically named ports apart by
+// their number.
+pub fn find_port<T: MidiIO>(midi_io: &T, pattern: &str) -> Option<T::Port> {
+    let ports = midi_io.ports();
+    let names = port_names(midi_io, &ports).ok()?;
+    ports
+        .into_iter()
+        .zip(names)
+        .find(|(_, name)| name.contains(pattern))
+        .map(|(port, _)| port)
+}
+
+// Errors if an existing port's name contains `name`, as a virtual port of that name couldn't be
+// told apart from it.
+pub fn check_name_free<T: MidiIO>(midi_io: &T, descr: &str, name: &str) -> Result<()> {
+    if let Some(port) = find_port(midi_io, name) {
+        return Err(format!(
+            "Can't create a virtual port named \"{}\", as the {} port \"{}\" already exists",
+            name,
+            descr,
+            midi_io.port_name(&port)?
+        )
+        .into());
+    }
+    Ok(())
+}
+
+pub fn has_port<T: MidiIO>(midi_io: &T, name: &str) -> bool {
+    midi_io
+        .ports()
+        .iter()
+        .any(|p| midi_io.port_name(p).is_ok_and(|n| n == name))
+}
+
+// Polls until both an input and output port whose names contain `pattern` exist, or `timeout`
+// passes.
+pub fn wait_for_device(
+    midi_in: &MidiInput,
+    midi_out: &MidiOutput,
+    pattern: &str,
+    timeout: Option<Duration>,
+) -> Result<(MidiInputPort, MidiOutputPort)> {
+    let start = Instant::now();
+    loop {
+        if let (Some(in_port), Some(out_port)) =
+            (find_port(midi_in, pattern), find_port(midi_out, pattern))
+        {
+            return Ok((in_port, out_port));
+        }
+
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            return Err(format!("Timed out waiting for a device matching \"{}\"", pattern).into());
+        }
+
+        session_log::event(format_args!(
+            "Waiting for device matching \"{}\"...",
+            pattern
+        ));
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midir::PortInfoError;
+    use std::io::Cursor;
+
+    // Ports known only by their names, each port being its index.
+    struct FakePorts(Vec<&'static str>);
+
+    impl MidiIO for FakePorts {
+        type Port = usize;
+
+        fn ports(&self) -> Vec<usize> {
+            (0..self.0.len()).collect()
+        }
+
+        fn port_count(&self) -> usize {
+            self.0.len()
+        }
+
+        fn port_name(&self, port: &usize) -> std::result::Result<String, PortInfoError> {
+            Ok(self.0[*port].to_string())
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|x| x.to_string()).collect()
+    }
+
+    // Answers the prompt with `input`, returning the port chosen and everything printed.
+    fn select(
+        ports: &[&'static str],
+        default: Option<&str>,
+        input: &str,
+    ) -> (Result<usize>, String) {
+        let mut output = Vec::new();
+        let port = select_port_with(
+            &FakePorts(ports.to_vec()),
+            "input",
+            default,
+            &mut Cursor::new(input),
+            &mut output,
+        );
+        (port, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn choices() {
+        let names = names(&["DDJ-400", "Traktor Kontrol S2", "Traktor Kontrol S4"]);
+        assert_eq!(parse_choice("2", &names, None), Ok(2));
+        assert!(parse_choice("3", &names, None)
+            .unwrap_err()
+            .contains("no port 3"));
+        assert_eq!(parse_choice("", &names, Some(1)), Ok(1));
+        assert!(parse_choice("", &names, None).is_err());
+        assert_eq!(parse_choice("DDJ", &names, None), Ok(0));
+        assert_eq!(parse_choice("S4", &names, None), Ok(2));
+        assert!(parse_choice("Traktor", &names, None)
+            .unwrap_err()
+            .contains("More than one"));
+        assert!(parse_choice("Launchpad", &names, None).is_err());
+
+        // A whole name is taken even though it's also part of another.
+        let names = self::names(&["Port #1", "Port #10"]);
+        assert_eq!(parse_choice("Port #1", &names, None), Ok(0));
+    }
+
+    #[test]
+    fn invalid_choices_are_prompted_again() {
+        let ports = ["DDJ-400", "Traktor S2", "Traktor S4"];
+        let (port, output) = select(&ports, None, "7\nTraktor\nS2\n");
+        assert_eq!(port.unwrap(), 1);
+        assert!(output.contains("There is no port 7"));
+        assert!(output.contains("More than one port's name contains \"Traktor\""));
+        assert_eq!(output.matches("Please select input port: ").count(), 3);
+    }
+
+    #[test]
+    fn empty_choices_pick_the_default() {
+        let (port, output) = select(&["DDJ-400"], None, "\n");
+        assert_eq!(port.unwrap(), 0);
+        assert!(output.contains("Please select input port [0]: "));
+
+        let (port, _) = select(&["DDJ-400", "Traktor S2"], Some("Traktor S2"), "\n");
+        assert_eq!(port.unwrap(), 1);
+    }
+
+    #[test]
+    fn closed_stdin_selects_nothing() {
+        let (port, _) = select(&["DDJ-400", "Traktor S2"], None, "");
+        assert!(port
+            .unwrap_err()
+            .to_string()
+            .contains("No input port selected"));
+
+        // Likewise after an invalid choice.
+        let (port, output) = select(&["DDJ-400", "Traktor S2"], None, "9\n");
+        assert!(port.is_err());
+        assert!(output.contains("There is no port 9"));
+    }
+
+    #[test]
+    fn too_many_invalid_choices_give_up() {
+        let (port, _) = select(&["DDJ-400", "Traktor S2"], None, &"9\n".repeat(10));
+        assert!(port.unwrap_err().to_string().contains("after 5 attempts"));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::config::{ReleasePolicy, RepeatConfig};
+use crate::sink::MidiSink;
+use crate::{log_send, send_button, Result, NOTE_ON};
+use std::time::{Duration, Instant};
+
+// A button which keeps re-pressing its note while held, e.g. for beat jumps.
+pub struct RepeatButton {
+    config: RepeatConfig,
+    release: ReleasePolicy,
+    // When the note is next re-pressed, while the button is held.
+    next_at: Option<Instant>,
+}
+
+impl RepeatButton {
+    pub fn new(config: RepeatConfig, release: ReleasePolicy) -> Self {
+        Self {
+            config,
+            release,
+            next_at: None,
+        }
+    }
+
+    pub fn handle_button(
+        &mut self,
+        note: u8,
+        state: bool,
+        now: Instant,
+        out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        if note != self.config.note {
+            return Ok(false);
+        }
+
+        if state {
+            // The first press is sent straight away, and only a hold longer than the delay repeats.
+            self.next_at = Some(now + Duration::from_millis(self.config.delay_ms()));
+            self.press(out)?;
+        } else {
+            self.release(out)?;
+        }
+        Ok(true)
+    }
+
+    pub fn tick(&mut self, now: Instant, out: &mut dyn MidiSink) -> Result<()> {
+        let Some(next_at) = self.next_at else {
+            return Ok(());
+        };
+        if now < next_at {
+            return Ok(());
+        }
+
+        // Ticks can lag behind, so the next repeat is scheduled from now rather than catching up.
+        self.next_at = Some(now + Duration::from_millis(self.config.interval_ms));
+        self.send_release(out)?;
+        self.press(out)
+    }
+
+    // Stops repeating, e.g. when the release may never arrive.
+    pub fn release(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        if self.next_at.take().is_some() {
+            self.send_release(out)?;
+        }
+        Ok(())
+    }
+
+    fn send_release(&self, out: &mut dyn MidiSink) -> Result<()> {
+        let (channel, note) = (self.config.channel(), self.config.out_note());
+        send_button(channel, note, 0, false, self.release, out)
+    }
+
+    // Stops repeating without sending anything, once the note has been released in Rekordbox.
+    pub fn stop(&mut self) {
+        self.next_at = None;
+    }
+
+    fn press(&self, out: &mut dyn MidiSink) -> Result<()> {
+        log_send(
+            self.config.channel(),
+            NOTE_ON,
+            self.config.out_note(),
+            127,
+            out,
+        )
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::Config;
+use crate::output::Dest;
+use crate::sink::RecordingSink;
+use crate::status;
+use crate::{parse_hex, Result, State, Target};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Where fixtures are looked for when none are given.
+const FIXTURES_DIR: &str = "tests/fixtures";
+
+// Set to write each fixture's golden file from what it produced, rather than comparing with it.
+const BLESS_VAR: &str = "PARTY_SAVER_BLESS";
+
+// How many lines are shown from where a fixture's output first differs from its golden file.
+const DIFF_LINES: usize = 10;
+
+// Runs each fixture through a fresh State, and compares everything it sent with the fixture's golden
+// file. Errors if any differed.
+//
+// A fixture (`name.fixture`) is a message per line: the milliseconds since the start, where it
+// comes from (`device`, or `rekordbox` for feedback) and its hex bytes, e.g. `250 device BF 0F 5A`.
+// Blank lines and those starting with `#` are skipped. If `name.toml` exists next to it, that's
+// used as the config. The golden file (`name.golden`) lists each message sent, prefixed by where it
+// went, under the input message which caused it.
+pub fn run(fixtures: &[PathBuf], target: Target) -> Result<()> {
+    let fixtures = if fixtures.is_empty() {
+        find_fixtures(Path::new(FIXTURES_DIR))?
+    } else {
+        fixtures.to_vec()
+    };
+    if fixtures.is_empty() {
+        return Err(format!("No fixtures found in {}", FIXTURES_DIR).into());
+    }
+
+    // The per-message log would bury the results.
+    status::quiet();
+    let bless = std::env::var_os(BLESS_VAR).is_some();
+    let mut failed = 0;
+    for fixture in &fixtures {
+        let golden_path = fixture.with_extension("golden");
+        let output = replay(fixture, target)?;
+        if bless {
+            std::fs::write(&golden_path, &output)?;
+            println!("BLESSED  {}", fixture.display());
+            continue;
+        }
+
+        let golden = std::fs::read_to_string(&golden_path).map_err(|e| {
+            format!(
+                "Failed to read {} (set {}=1 to write it): {}",
+                golden_path.display(),
+                BLESS_VAR,
+                e
+            )
+        })?;
+        if output == golden {
+            println!("PASS     {}", fixture.display());
+        } else {
+            failed += 1;
+            println!("FAIL     {}", fixture.display());
+            print_diff(&golden, &output);
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!(
+            "{} of {} fixtures differed from their golden files (set {}=1 to update them)",
+            failed,
+            fixtures.len(),
+            BLESS_VAR
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn find_fixtures(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut fixtures = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to list fixtures in {}: {}", dir.display(), e))?
+    {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == "fixture") {
+            fixtures.push(path);
+        }
+    }
+    fixtures.sort();
+    Ok(fixtures)
+}
+
+// Runs a fixture's messages at their times, returning what the golden file should contain.
+fn replay(path: &Path, target: Target) -> Result<String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read fixture {}: {}", path.display(), e))?;
+    let config_path = path.with_extension("toml");
+    let config = if config_path.exists() {
+        Config::load(&config_path)?
+    } else {
+        Config::default()
+    };
+
+    let start = Instant::now();
+    let mut state = State::new(&config, target);
+    let mut rb_out = RecordingSink::default();
+    let mut device_out = RecordingSink::default();
+    let mut clock_out = RecordingSink::default();
+    let mut output = String::new();
+    state.boot(&mut device_out)?;
+    writeln!(output, "# boot")?;
+    record(
+        &mut output,
+        &mut state,
+        start,
+        &mut rb_out,
+        &mut device_out,
+        &mut clock_out,
+    );
+
+    let mut last = Duration::ZERO;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let context = |e: &dyn std::fmt::Display| format!("{}:{}: {}", path.display(), i + 1, e);
+        let (at, rest) = line
+            .split_once(' ')
+            .ok_or_else(|| context(&"expected a time"))?;
+        let (source, hex) = rest
+            .trim_start()
+            .split_once(' ')
+            .ok_or_else(|| context(&"expected a source and a message"))?;
+        let at = Duration::from_millis(at.parse().map_err(|_| context(&"invalid time"))?);
+        if at < last {
+            return Err(context(&"times must not go backwards").into());
+        }
+        last = at;
+        let message = parse_hex(hex).map_err(|e| context(&e))?;
+
+        let now = start + at;
+        // Whatever would have happened on its own by now, e.g. a throttled value being sent.
+        state.tick(now, &mut rb_out, &mut device_out)?;
+        record(
+            &mut output,
+            &mut state,
+            now,
+            &mut rb_out,
+            &mut device_out,
+            &mut clock_out,
+        );
+        writeln!(output, "# {}", line)?;
+        match source {
+            "device" => state.transform(&message, now, &mut rb_out, &mut device_out)?,
+            "rekordbox" => state.feedback(&message, now, &mut device_out, &mut clock_out)?,
+            _ => return Err(context(&"the source must be device or rekordbox").into()),
+        }
+        record(
+            &mut output,
+            &mut state,
+            now,
+            &mut rb_out,
+            &mut device_out,
+            &mut clock_out,
+        );
+    }
+
+    // Treat every control as having gone idle, so throttled values show up too.
+    writeln!(output, "# idle")?;
+    let idle = start + last + Duration::from_secs(3600);
+    state.tick(idle, &mut rb_out, &mut device_out)?;
+    record(
+        &mut output,
+        &mut state,
+        idle,
+        &mut rb_out,
+        &mut device_out,
+        &mut clock_out,
+    );
+    Ok(output)
+}
+
+// Moves each sink's messages to `output`, a line per message, letting `state` follow what was sent
+// to Rekordbox and route it as it does when running.
+fn record(
+    output: &mut String,
+    state: &mut State,
+    now: Instant,
+    rb_out: &mut RecordingSink,
+    device_out: &mut RecordingSink,
+    clock_out: &mut RecordingSink,
+) {
+    for message in rb_out.messages.drain(..) {
+        state.sent(&message, now);
+        match state.route(&message) {
+            Dest::Route(port) => write_message(output, &format!("route {:?}", port), &message),
+            _ => write_message(output, "rekordbox", &message),
+        }
+    }
+    for (dest, sink) in [("device", device_out), ("clock", clock_out)] {
+        for message in sink.messages.drain(..) {
+            write_message(output, dest, &message);
+        }
+    }
+}
+
+fn write_message(output: &mut String, dest: &str, message: &[u8]) {
+    let hex: Vec<_> = message.iter().map(|x| format!("{:02X}", x)).collect();
+    let _ = writeln!(output, "{} {}", dest, hex.join(" "));
+}
+
+// Shows the lines from where `actual` first differs from `expected`.
+fn print_diff(expected: &str, actual: &str) {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+    let first = expected
+        .iter()
+        .zip(&actual)
+        .position(|(a, b)| a != b)
+        .unwrap_or(expected.len().min(actual.len()));
+    println!("  First difference at line {}:", first + 1);
+    for line in expected.iter().skip(first).take(DIFF_LINES) {
+        println!("  - {}", line);
+    }
+    for line in actual.iter().skip(first).take(DIFF_LINES) {
+        println!("  + {}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replays everything in tests/fixtures, so `cargo test` catches a change in what's sent. Set
+    // PARTY_SAVER_BLESS=1 to update the golden files instead.
+    #[test]
+    fn fixtures_match_golden_files() {
+        run(&[], Target::Rekordbox).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use crate::config::Config;
+use crate::led::{Led, LedColor};
+use crate::{select_ports, Result, CONTROL_CHANGE, NOTE_OFF, NOTE_ON};
+use midir::{Ignore, MidiInput, MidiOutput};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+// How long each LED is lit for while stepping through them.
+const LED_STEP: Duration = Duration::from_millis(150);
+
+// What a single note or CC has sent since connecting.
+#[derive(Default)]
+struct Events {
+    presses: u32,
+    releases: u32,
+    values: BTreeSet<u8>,
+}
+
+// Everything received, keyed by NOTE_ON or CONTROL_CHANGE and the note or CC. Channels are ignored.
+type Collected = Arc<Mutex<HashMap<(u8, u8), Events>>>;
+
+// Lights every LED the config uses in turn, to check the LED mapping by eye, then listens while the
+// controls are moved and reports which behaved as the mapping expects. Errors if any didn't.
+pub fn run(
+    config_path: Option<&Path>,
+    device_in: Option<&str>,
+    device_out: Option<&str>,
+    listen: Duration,
+) -> Result<()> {
+    let config = match config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    let mut midi_in = MidiInput::new("PartySaver self-test in")?;
+    midi_in.ignore(Ignore::All);
+    let midi_out = MidiOutput::new("PartySaver self-test out")?;
+    let (in_port, out_port) = select_ports(&midi_in, &midi_out, device_in, device_out)?;
+
+    let collected = Collected::default();
+    let _conn_in = midi_in.connect(
+        &in_port,
+        "party-saver-self-test",
+        |_, message, collected| {
+            if let [status, code, value] = *message {
+                let mut collected = collected.lock().unwrap_or_else(PoisonError::into_inner);
+                match status & 0xF0 {
+                    NOTE_ON if value > 0 => {
+                        collected.entry((NOTE_ON, code)).or_default().presses += 1
+                    }
+                    NOTE_ON | NOTE_OFF => {
+                        collected.entry((NOTE_ON, code)).or_default().releases += 1
+                    }
+                    CONTROL_CHANGE => {
+                        collected
+                            .entry((CONTROL_CHANGE, code))
+                            .or_default()
+                            .values
+                            .insert(value);
+                    }
+                    _ => (),
+                }
+            }
+        },
+        collected.clone(),
+    )?;
+    let mut color_out = midi_out.connect(&out_port, "party-saver-self-test")?;
+
+    let mut leds: Vec<_> = config
+        .input_notes()
+        .into_iter()
+        .chain(config.led_notes())
+        .map(|(control, note)| (note, control))
+        .collect();
+    leds.sort();
+    leds.dedup_by_key(|(note, _)| *note);
+    println!("Lighting {} LEDs in turn:", leds.len());
+    for (note, control) in leds {
+        println!("  LED {:#04X} ({})", note, control);
+        let led = Led::new(note);
+        led.set(config.leds.button_active, &mut color_out)?;
+        thread::sleep(LED_STEP);
+        led.set(LedColor::Off, &mut color_out)?;
+    }
+
+    println!(
+        "\nMove every control now, and press and release every button. Listening for {} seconds...",
+        listen.as_secs()
+    );
+    thread::sleep(listen);
+
+    let mut collected = collected.lock().unwrap_or_else(PoisonError::into_inner);
+    let relative_ccs = config.relative_ccs();
+    let mut results = Vec::new();
+    for (control, note) in config.input_notes() {
+        let name = format!("{} (note {:#04X})", control, note);
+        let result = match collected.get(&(NOTE_ON, note)) {
+            None => None,
+            Some(events) if events.presses == 0 => Some(Err("released without a press")),
+            Some(events) if events.releases == 0 => Some(Err("pressed without a release")),
+            Some(_) => Some(Ok(())),
+        };
+        results.push((name, result));
+    }
+    for (control, cc) in config.input_ccs() {
+        let name = format!("{} (CC {})", control, cc);
+        let result = collected.get(&(CONTROL_CHANGE, cc)).map(|events| {
+            // A relative encoder's steps should all be ticks one way or the other.
+            let ranges = config.fake_buttons;
+            let understood = |&value: &u8| ranges.up.contains(value) || ranges.down.contains(value);
+            if relative_ccs.contains(&cc) && !events.values.iter().all(understood) {
+                Err("sent values which are neither up nor down ticks")
+            } else {
+                Ok(())
+            }
+        });
+        results.push((name, result));
+    }
+
+    let (mut passed, mut failed, mut untested) = (0, 0, 0);
+    println!();
+    for (name, result) in results {
+        match result {
+            Some(Ok(())) => {
+                passed += 1;
+                println!("PASS      {}", name);
+            }
+            Some(Err(problem)) => {
+                failed += 1;
+                println!("FAIL      {}: {}", name, problem);
+            }
+            None => {
+                untested += 1;
+                println!("UNTESTED  {}", name);
+            }
+        }
+    }
+
+    // Anything left over wasn't expected by the mapping at all.
+    let mapped: Vec<_> = config
+        .input_notes()
+        .into_iter()
+        .map(|(_, note)| (NOTE_ON, note))
+        .chain(
+            config
+                .input_ccs()
+                .into_iter()
+                .map(|(_, cc)| (CONTROL_CHANGE, cc)),
+        )
+        .collect();
+    collected.retain(|key, _| !mapped.contains(key));
+    let mut unmapped: Vec<_> = collected.keys().copied().collect();
+    unmapped.sort();
+    for (kind, code) in unmapped {
+        if kind == NOTE_ON {
+            println!("UNMAPPED  note {:#04X}", code);
+        } else {
+            println!("UNMAPPED  CC {}", code);
+        }
+    }
+
+    println!(
+        "\n{} passed, {} failed, {} untested",
+        passed, failed, untested
+    );
+    if failed > 0 {
+        return Err(format!("{} controls failed the self-test", failed).into());
+    }
+    Ok(())
+}
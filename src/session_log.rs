@@ -0,0 +1,178 @@
+use crate::status;
+use crate::Result;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How often buffered lines are written to the file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Lines beyond this many waiting to be written are dropped, oldest first, should writing stall.
+const MAX_PENDING: usize = 10_000;
+
+// A record of connection events, errors and warnings which outlives the console, for finding out
+// afterwards what went wrong during a set. Lines are only buffered when logged, and written by a
+// separate thread, so logging never blocks the MIDI callbacks on the disk.
+struct SessionLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+    traffic: bool,
+    pending: Mutex<Vec<String>>,
+    // Held while writing, so that lines taken by concurrent flushes are written in order.
+    writing: Mutex<()>,
+}
+
+static LOG: OnceLock<SessionLog> = OnceLock::new();
+
+// Starts logging to `path`, rotating it once it reaches `max_bytes` and keeping `keep` old files.
+// Message traffic is only included if `traffic` is set. Buffered lines are written every
+// FLUSH_INTERVAL, and by `flush`.
+pub fn open(path: PathBuf, max_bytes: u64, keep: u32, traffic: bool) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create log directory {}: {}", dir.display(), e))?;
+    }
+    let _ = LOG.set(SessionLog {
+        path,
+        max_bytes,
+        keep,
+        traffic,
+        pending: Mutex::new(Vec::new()),
+        writing: Mutex::new(()),
+    });
+
+    // Never joined, as there's a final flush at shutdown anyway.
+    thread::spawn(|| loop {
+        thread::sleep(FLUSH_INTERVAL);
+        flush();
+    });
+    Ok(())
+}
+
+// The default log file, under $XDG_STATE_HOME or ~/.local/state.
+pub fn default_path() -> Option<PathBuf> {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state_dir.join("party-saver").join("party-saver.log"))
+}
+
+// Prints a line to stdout, and records it in the log file.
+pub fn event(args: fmt::Arguments) {
+    status::line(args);
+    record(args);
+}
+
+// Prints a line to stderr, and records it in the log file.
+pub fn error(args: fmt::Arguments) {
+    status::error_line(args);
+    record(args);
+}
+
+// Records message traffic, if enabled. It's already printed as it's sent.
+pub fn traffic(args: fmt::Arguments) {
+    if LOG.get().is_some_and(|log| log.traffic) {
+        record(args);
+    }
+}
+
+// Records a line in the log file only.
+pub fn record(args: fmt::Arguments) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let line = format!("{} {}\n", timestamp(SystemTime::now()), args);
+    let mut pending = log.pending.lock().unwrap_or_else(PoisonError::into_inner);
+    if pending.len() >= MAX_PENDING {
+        pending.remove(0);
+    }
+    pending.push(line);
+}
+
+// Writes everything buffered so far to the file.
+pub fn flush() {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let _writing = log.writing.lock().unwrap_or_else(PoisonError::into_inner);
+    let lines = std::mem::take(&mut *log.pending.lock().unwrap_or_else(PoisonError::into_inner));
+    if lines.is_empty() {
+        return;
+    }
+    if let Err(e) = log.write(&lines) {
+        eprintln!("Failed to write log {}: {}", log.path.display(), e);
+    }
+}
+
+impl SessionLog {
+    fn write(&self, lines: &[String]) -> std::io::Result<()> {
+        let size = fs::metadata(&self.path).map_or(0, |x| x.len());
+        let adding: u64 = lines.iter().map(|x| x.len() as u64).sum();
+        if size > 0 && size + adding > self.max_bytes {
+            self.rotate()?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut file = BufWriter::new(file);
+        for line in lines {
+            file.write_all(line.as_bytes())?;
+        }
+        file.flush()
+    }
+
+    // Shifts party-saver.log to party-saver.log.1, .1 to .2 and so on, dropping the oldest.
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            return File::create(&self.path).map(drop);
+        }
+
+        let _ = fs::remove_file(self.rotated(self.keep));
+        for i in (1..self.keep).rev() {
+            let _ = fs::rename(self.rotated(i), self.rotated(i + 1));
+        }
+        fs::rename(&self.path, self.rotated(1))
+    }
+
+    fn rotated(&self, i: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", i));
+        path.into()
+    }
+}
+
+// UTC, as e.g. 2024-05-01 22:13:07.512.
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Converts days since the epoch to a civil date, from Howard Hinnant's date algorithms.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -0,0 +1,124 @@
+use crate::ports::has_port;
+use crate::session_log;
+use crate::status;
+use crate::Result;
+use midir::MidiInput;
+use std::error::Error;
+use std::fmt;
+use std::io::{stdin, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Process exit codes. A clean shutdown exits with 0.
+pub const EXIT_ERROR: u8 = 1;
+pub const EXIT_PORT_SELECTION: u8 = 2;
+pub const EXIT_CONNECTION: u8 = 3;
+
+// How often the device's input port is checked for having disappeared.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// An error which should terminate the process with a specific exit code.
+#[derive(Debug)]
+pub struct ExitError {
+    pub code: u8,
+    error: Box<dyn Error>,
+}
+
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl Error for ExitError {}
+
+pub fn exit_with<E: Into<Box<dyn Error>>>(code: u8) -> impl FnOnce(E) -> ExitError {
+    move |error| ExitError {
+        code,
+        error: error.into(),
+    }
+}
+
+pub fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    error
+        .downcast_ref::<ExitError>()
+        .map_or(EXIT_ERROR, |e| e.code)
+}
+
+pub enum Shutdown {
+    Requested,
+    ConnectionLost,
+}
+
+enum Event {
+    Stop,
+    Command(String),
+}
+
+// Blocks until SIGINT/SIGTERM is received, enter is pressed on an empty line (if `listen_stdin` is
+// set and stdin is a terminal), or the device's input port disappears or `connection_lost` is set.
+// Any other line entered is passed to `on_command`. Meanwhile the status line is redrawn.
+pub fn wait(
+    listen_stdin: bool,
+    device_in_name: &str,
+    connection_lost: &AtomicBool,
+    mut on_command: impl FnMut(&str),
+) -> Result<Shutdown> {
+    let (tx, rx) = mpsc::channel();
+    let signal_tx = tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = signal_tx.send(Event::Stop);
+    })?;
+
+    // Under a supervisor stdin is usually closed, which would otherwise look like a request to exit.
+    if listen_stdin && !stdin().is_terminal() {
+        session_log::event(format_args!(
+            "Stdin isn't a terminal, so running until interrupted (Ctrl+C or SIGTERM)"
+        ));
+    } else if listen_stdin {
+        thread::spawn(move || loop {
+            let mut input = String::new();
+            let event = match stdin().read_line(&mut input) {
+                Ok(0) => {
+                    session_log::event(format_args!(
+                        "Stdin was closed, shutting down. Pass --run-forever to keep running without it"
+                    ));
+                    Event::Stop
+                }
+                Err(e) => {
+                    session_log::event(format_args!("Failed to read stdin, shutting down: {}", e));
+                    Event::Stop
+                }
+                Ok(_) if input.trim().is_empty() => Event::Stop,
+                Ok(_) => Event::Command(input.trim().to_string()),
+            };
+            let stop = matches!(event, Event::Stop);
+            if tx.send(event).is_err() || stop {
+                break;
+            }
+        });
+    }
+
+    let watcher = MidiInput::new("PartySaver watch")?;
+    let mut watched = Instant::now();
+    loop {
+        match rx.recv_timeout(status::REDRAW_INTERVAL) {
+            Ok(Event::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                return Ok(Shutdown::Requested)
+            }
+            Ok(Event::Command(command)) => on_command(&command),
+            Err(RecvTimeoutError::Timeout) => {
+                status::redraw();
+                if watched.elapsed() < WATCH_INTERVAL {
+                    continue;
+                }
+                watched = Instant::now();
+                if connection_lost.load(Ordering::Relaxed) || !has_port(&watcher, device_in_name) {
+                    return Ok(Shutdown::ConnectionLost);
+                }
+            }
+        }
+    }
+}
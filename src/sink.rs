@@ -0,0 +1,58 @@
+use crate::Result;
+use midir::MidiOutputConnection;
+use std::sync::mpsc::Sender;
+
+// Anything MIDI messages can be sent to. This decouples the translation logic from midir, so it
+// can be driven without real ports, and from any thread rather than only midir's callbacks.
+pub trait MidiSink: Send {
+    fn send(&mut self, message: &[u8]) -> Result<()>;
+}
+
+impl MidiSink for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        MidiOutputConnection::send(self, message)?;
+        Ok(())
+    }
+}
+
+// Hands messages to another thread, e.g. an event loop which owns the actual connections.
+impl MidiSink for Sender<Vec<u8>> {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        Sender::send(self, message.to_vec())?;
+        Ok(())
+    }
+}
+
+// Collects messages instead of sending them anywhere.
+#[derive(Default)]
+pub struct RecordingSink {
+    pub messages: Vec<Vec<u8>>,
+}
+
+impl MidiSink for RecordingSink {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        self.messages.push(message.to_vec());
+        Ok(())
+    }
+}
+
+// Discards messages, for --dry-run. Messages to Rekordbox are already logged as they're produced,
+// so only those given a `label` are logged here.
+pub struct DryRunSink {
+    label: Option<&'static str>,
+}
+
+impl DryRunSink {
+    pub fn new(label: Option<&'static str>) -> Self {
+        Self { label }
+    }
+}
+
+impl MidiSink for DryRunSink {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        if let Some(label) = self.label {
+            println!("{}: {:?} (DRY RUN)", label, message);
+        }
+        Ok(())
+    }
+}
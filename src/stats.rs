@@ -0,0 +1,115 @@
+use crate::{
+    Result, CHANNEL_PRESSURE, CONTROL_CHANGE, NOTE_OFF, NOTE_ON, PITCH_BEND, PROGRAM_CHANGE,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// How many of the most common unrecognized messages are reported.
+const TOP_UNRECOGNIZED: usize = 10;
+
+// How much each control was used over a session, to help prune the mapping.
+#[derive(Serialize)]
+pub struct SessionStats {
+    // Ticks received by each fake pot, keyed by input CC.
+    pub pot_ticks: BTreeMap<u8, u64>,
+    pub filter_toggles: [u64; 3],
+    pub tempo_deck_selects: [u64; 3],
+    // Messages which fell through to being forwarded as-is.
+    pub verbatim: u64,
+    // Relative values no control understood, and CCs no control handled (which are among those
+    // forwarded verbatim, unless dropped).
+    pub unknown_values: u64,
+    pub unhandled_ccs: u64,
+    pub unrecognized: Vec<Unrecognized>,
+    // Every kind of message forwarded verbatim, for --coverage.
+    #[serde(skip)]
+    pub coverage: Vec<Unrecognized>,
+}
+
+#[derive(Serialize)]
+pub struct Unrecognized {
+    pub status: u8,
+    // The note or CC number, if the message had one.
+    pub code: Option<u8>,
+    pub count: u64,
+}
+
+impl SessionStats {
+    // Keeps only the most common unrecognized messages, ordered by count.
+    pub fn top_unrecognized(counts: &HashMap<(u8, Option<u8>), u64>) -> Vec<Unrecognized> {
+        let mut unrecognized = Self::sorted_unrecognized(counts);
+        unrecognized.truncate(TOP_UNRECOGNIZED);
+        unrecognized
+    }
+
+    pub fn sorted_unrecognized(counts: &HashMap<(u8, Option<u8>), u64>) -> Vec<Unrecognized> {
+        let mut unrecognized: Vec<_> = counts
+            .iter()
+            .map(|(&(status, code), &count)| Unrecognized {
+                status,
+                code,
+                count,
+            })
+            .collect();
+        unrecognized.sort_by_key(|x| (std::cmp::Reverse(x.count), x.status, x.code));
+        unrecognized
+    }
+
+    pub fn print(&self) {
+        println!("Session statistics:");
+        for (cc, ticks) in &self.pot_ticks {
+            println!("  Pot CC {}: {} ticks", cc, ticks);
+        }
+        println!("  Filter toggles:     {:?}", self.filter_toggles);
+        println!("  Tempo deck selects: {:?}", self.tempo_deck_selects);
+        println!("  Unknown values:     {}", self.unknown_values);
+        println!("  Unhandled CCs:      {}", self.unhandled_ccs);
+        println!("  Forwarded verbatim: {}", self.verbatim);
+        for x in &self.unrecognized {
+            match x.code {
+                Some(code) => println!("    {:02X} {:02X}: {}", x.status, code, x.count),
+                None => println!("    {:02X}: {}", x.status, x.count),
+            }
+        }
+    }
+
+    pub fn print_coverage(&self) {
+        println!("Coverage (messages forwarded verbatim, most common first):");
+        if self.coverage.is_empty() {
+            println!("  None, everything was mapped");
+        }
+        for x in &self.coverage {
+            println!("  {:>8}  {}", x.count, guess(x.status, x.code));
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("Failed to write stats to {}: {}", path.display(), e))?;
+        Ok(())
+    }
+}
+
+// What a message forwarded verbatim probably is, e.g. "CC 7 on channel 1" or "note 60 (C4)".
+fn guess(status: u8, code: Option<u8>) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    let channel = status & 0x0F;
+    let described = match (status & 0xF0, code) {
+        (NOTE_ON | NOTE_OFF, Some(note)) => format!(
+            "note {} ({}{})",
+            note,
+            NAMES[note as usize % 12],
+            note as i32 / 12 - 1
+        ),
+        (CONTROL_CHANGE, Some(cc)) => format!("CC {}", cc),
+        (PROGRAM_CHANGE, Some(program)) => format!("program change {}", program),
+        (CHANNEL_PRESSURE, _) => "channel pressure".into(),
+        (PITCH_BEND, _) => "pitch bend".into(),
+        _ => return format!("status {:02X}", status),
+    };
+    format!("{} on channel {}", described, channel)
+}
@@ -0,0 +1,181 @@
+use crate::events;
+use crate::DECK_COUNT;
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// How often the status line is redrawn.
+pub const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+// Clears the terminal's current line, and returns to its start.
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// Updated from the MIDI callbacks and output worker, and read when redrawing.
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+static SENT: AtomicU64 = AtomicU64::new(0);
+static TEMPO_DECK: AtomicUsize = AtomicUsize::new(0);
+static TEMPO_CAUGHT: AtomicBool = AtomicBool::new(true);
+// Bit N is set while the filter is enabled on deck N + 1.
+static FILTERS: AtomicU8 = AtomicU8::new(0);
+
+// The status line on screen, and the counts it was drawn from, for the next redraw's rates.
+struct Drawn {
+    text: String,
+    received: u64,
+    sent: u64,
+    at: Instant,
+}
+
+static DRAWN: Mutex<Option<Drawn>> = Mutex::new(None);
+
+// Replaces the line printed per message with a status line redrawn in place, unless stdout isn't a
+// terminal. Returns whether it did.
+pub fn enable() -> bool {
+    if !io::stdout().is_terminal() {
+        return false;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    true
+}
+
+// Removes the status line, and goes back to printing a line per message.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+    if DRAWN
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .is_some()
+    {
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "{}", CLEAR_LINE);
+        let _ = stdout.flush();
+    }
+}
+
+// Stops printing lines about single messages at all, e.g. while replaying fixtures.
+pub fn quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Counts a message received from the device.
+pub fn received() {
+    RECEIVED.fetch_add(1, Ordering::Relaxed);
+}
+
+// Counts a message sent to Rekordbox.
+pub fn sent() {
+    SENT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn set_decks(tempo_deck: usize, tempo_caught: bool, filters: [bool; DECK_COUNT]) {
+    TEMPO_DECK.store(tempo_deck, Ordering::Relaxed);
+    TEMPO_CAUGHT.store(tempo_caught, Ordering::Relaxed);
+    let bits = filters
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &enabled)| bits | (enabled as u8) << i);
+    FILTERS.store(bits, Ordering::Relaxed);
+}
+
+// Prints a line about a single message, unless the status line or JSON events have taken their
+// place.
+pub fn message(args: fmt::Arguments) {
+    if !enabled() && !events::enabled() && !QUIET.load(Ordering::Relaxed) {
+        println!("{}", args);
+    }
+}
+
+// Prints a line to stdout, above the status line if it's shown.
+pub fn line(args: fmt::Arguments) {
+    print_above(args, false);
+}
+
+// Prints a line to stderr, above the status line if it's shown.
+pub fn error_line(args: fmt::Arguments) {
+    print_above(args, true);
+}
+
+fn print_above(args: fmt::Arguments, error: bool) {
+    let drawn = DRAWN.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(drawn) = &*drawn else {
+        if error {
+            eprintln!("{}", args);
+        } else {
+            println!("{}", args);
+        }
+        return;
+    };
+
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "{}", CLEAR_LINE);
+    if error {
+        let _ = stdout.flush();
+        eprintln!("{}", args);
+    } else {
+        let _ = writeln!(stdout, "{}", args);
+    }
+    let _ = write!(stdout, "{}", drawn.text);
+    let _ = stdout.flush();
+}
+
+// Redraws the status line with the rates since it was last drawn, if it's enabled.
+pub fn redraw() {
+    if !enabled() {
+        return;
+    }
+
+    let now = Instant::now();
+    let received = RECEIVED.load(Ordering::Relaxed);
+    let sent = SENT.load(Ordering::Relaxed);
+    let mut drawn = DRAWN.lock().unwrap_or_else(PoisonError::into_inner);
+    let (in_rate, out_rate) = match &*drawn {
+        Some(last) => {
+            let seconds = now.duration_since(last.at).as_secs_f64().max(0.001);
+            (
+                (received - last.received) as f64 / seconds,
+                (sent - last.sent) as f64 / seconds,
+            )
+        }
+        None => (0.0, 0.0),
+    };
+
+    let filters = FILTERS.load(Ordering::Relaxed);
+    let filters: String = (0..DECK_COUNT)
+        .map(|i| {
+            let enabled = filters & 1 << i != 0;
+            format!("{}{}", i + 1, if enabled { '●' } else { '○' })
+        })
+        .collect();
+    let text = format!(
+        "IN {:.0} msg/s | OUT {:.0} msg/s | deck {} tempo {} | filters: {}",
+        in_rate,
+        out_rate,
+        TEMPO_DECK.load(Ordering::Relaxed) + 1,
+        if TEMPO_CAUGHT.load(Ordering::Relaxed) {
+            "caught"
+        } else {
+            "waiting"
+        },
+        filters
+    );
+
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "{}{}", CLEAR_LINE, text);
+    let _ = stdout.flush();
+    *drawn = Some(Drawn {
+        text,
+        received,
+        sent,
+        at: now,
+    });
+}
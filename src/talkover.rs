@@ -0,0 +1,134 @@
+use crate::config::{LedConfig, ReleasePolicy, TalkoverConfig};
+use crate::led::{Led, LedColor};
+use crate::sink::MidiSink;
+use crate::{log_send, send_button, Result, NOTE_ON};
+use std::time::{Duration, Instant};
+
+// A button which toggles the mic in Rekordbox, and while it's on ducks a pot (usually the master
+// volume). The duck ramps in and out, and is taken off the pot's value rather than replacing it,
+// so the pot can still be turned while talking.
+pub struct Talkover {
+    pub config: TalkoverConfig,
+    leds: LedConfig,
+    release: ReleasePolicy,
+    pub engaged: bool,
+    // How far the pot is currently ducked.
+    duck: f32,
+    // Where the current ramp started from, and when. None once it's reached where it's going.
+    ramp: Option<(f32, Instant)>,
+}
+
+impl Talkover {
+    pub fn new(config: TalkoverConfig, leds: LedConfig, release: ReleasePolicy) -> Self {
+        Self {
+            config,
+            leds,
+            release,
+            engaged: false,
+            duck: 0.0,
+            ramp: None,
+        }
+    }
+
+    pub fn handle_button(
+        &mut self,
+        note: u8,
+        state: bool,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<bool> {
+        if note != self.config.note {
+            return Ok(false);
+        }
+        if state {
+            self.toggle(now, out, color_out)?;
+        }
+        Ok(true)
+    }
+
+    // Turns the mic on or off, and heads back the other way from wherever a ramp has got to.
+    pub fn toggle(
+        &mut self,
+        now: Instant,
+        out: &mut dyn MidiSink,
+        color_out: &mut dyn MidiSink,
+    ) -> Result<()> {
+        self.engaged = !self.engaged;
+        self.ramp = Some((self.duck, now));
+        self.send_toggle(out)?;
+        self.light_led(color_out)
+    }
+
+    // Rekordbox's mic button toggles, so it's pressed and released straight away.
+    fn send_toggle(&self, out: &mut dyn MidiSink) -> Result<()> {
+        log_send(
+            self.config.channel(),
+            NOTE_ON,
+            self.config.out_note,
+            127,
+            out,
+        )?;
+        let channel = self.config.channel();
+        send_button(channel, self.config.out_note, 0, false, self.release, out)
+    }
+
+    // Moves the duck along its ramp.
+    pub fn tick(&mut self, now: Instant) {
+        let Some((from, started)) = self.ramp else {
+            return;
+        };
+        let target = if self.engaged {
+            self.config.duck as f32
+        } else {
+            0.0
+        };
+
+        // A reversed ramp takes only as long as the distance it has to go back.
+        let full = Duration::from_millis(self.config.ramp_ms).as_secs_f32();
+        let duration = full * (target - from).abs() / (self.config.duck as f32).max(1.0);
+        let elapsed = now.saturating_duration_since(started).as_secs_f32();
+        if elapsed >= duration {
+            self.duck = target;
+            self.ramp = None;
+        } else {
+            self.duck = from + (target - from) * elapsed / duration;
+        }
+    }
+
+    // Turns the mic off straight away if it's on, with no ramp back.
+    pub fn reset(&mut self, out: &mut dyn MidiSink) -> Result<()> {
+        if self.engaged {
+            self.engaged = false;
+            self.send_toggle(out)?;
+        }
+        self.duck = 0.0;
+        self.ramp = None;
+        Ok(())
+    }
+
+    pub fn duck(&self) -> u8 {
+        self.duck.round() as u8
+    }
+
+    // Takes over from `old`, built from a previous config with the same pot. The duck ramps from
+    // where it was to this config's.
+    pub fn restore(&mut self, old: &Talkover, now: Instant) {
+        self.engaged = old.engaged;
+        self.duck = old.duck;
+        self.ramp = Some((old.duck, now));
+    }
+
+    pub fn light_led(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        let color = if self.engaged {
+            self.leds.button_active
+        } else {
+            self.leds.button_inactive
+        };
+        Led::new(self.config.note).set(color, color_out)
+    }
+
+    pub fn clear_led(&self, color_out: &mut dyn MidiSink) -> Result<()> {
+        Led::new(self.config.note).set(LedColor::Off, color_out)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::config::Deadband;
+use std::time::Instant;
+
+// Suppresses small back-and-forth changes in a control's output value, while guaranteeing that
+// the value it eventually settles on is still sent.
+#[derive(Default)]
+pub struct Throttle {
+    policy: Option<Deadband>,
+    sent: Option<u8>,
+    pending_since: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(policy: Option<Deadband>) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    // Returns whether `value` should be sent right away. If not, it's held until `flush`.
+    pub fn update(&mut self, value: u8, now: Instant) -> bool {
+        let Some(policy) = self.policy else {
+            return true;
+        };
+
+        match self.sent {
+            Some(sent) if sent.abs_diff(value) <= policy.threshold => {
+                // Restart the idle timer on every tick, so chatter never gets through.
+                self.pending_since = Some(now);
+                false
+            }
+            _ => {
+                self.sent = Some(value);
+                self.pending_since = None;
+                true
+            }
+        }
+    }
+
+    // Treats `value` as already sent, e.g. because it came from Rekordbox in the first place.
+    pub fn sync(&mut self, value: u8) {
+        self.sent = Some(value);
+        self.pending_since = None;
+    }
+
+    // Returns whether the control has settled on `value` and it still needs to be sent.
+    pub fn flush(&mut self, value: u8, now: Instant) -> bool {
+        match (self.policy, self.pending_since) {
+            (Some(policy), Some(since)) if now.duration_since(since) >= policy.idle() => {
+                self.pending_since = None;
+                if self.sent == Some(value) {
+                    return false;
+                }
+                self.sent = Some(value);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn jitter_sends_one_trailing_value() {
+        let mut throttle = Throttle::new(Some(Deadband {
+            threshold: 2,
+            idle_ms: 100,
+        }));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(throttle.update(64, at(0)));
+
+        // Every tick is polled for, as the idle tick does while the control is moving.
+        let mut sent = Vec::new();
+        for (i, value) in [65, 63, 65, 64, 66, 65, 66].into_iter().enumerate() {
+            let now = at(10 * (i as u64 + 1));
+            if throttle.update(value, now) || throttle.flush(value, now) {
+                sent.push(value);
+            }
+        }
+        assert!(sent.is_empty());
+
+        for ms in [150, 200, 500] {
+            if throttle.flush(66, at(ms)) {
+                sent.push(66);
+            }
+        }
+        assert_eq!(sent, [66]);
+    }
+}
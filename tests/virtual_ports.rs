@@ -0,0 +1,121 @@
+// Runs party-saver against virtual ports standing in for the device and Rekordbox, so the midir
+// connections are exercised too, not just the translation. Needs a MIDI subsystem with virtual
+// ports (e.g. ALSA's sequencer), so it's ignored by default:
+//
+//     cargo test --test virtual_ports -- --ignored
+#![cfg(unix)]
+
+use midir::os::unix::{VirtualInput, VirtualOutput};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long to wait for party-saver's ports to appear, and for each expected message.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Kills party-saver when the test ends, however it ends.
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Connects to the first input port whose name contains `name`, once it exists, collecting what
+// arrives on it.
+fn listen(name: &str) -> (MidiInputConnection<()>, Receiver<Vec<u8>>) {
+    let mut midi_in = MidiInput::new("party-saver test listener").unwrap();
+    midi_in.ignore(Ignore::None);
+    let start = Instant::now();
+    let port = loop {
+        let port = midi_in
+            .ports()
+            .into_iter()
+            .find(|port| midi_in.port_name(port).is_ok_and(|x| x.contains(name)));
+        match port {
+            Some(port) => break port,
+            None if start.elapsed() < TIMEOUT => thread::sleep(Duration::from_millis(50)),
+            None => panic!("No input port named {} appeared", name),
+        }
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let connection = midi_in
+        .connect(
+            &port,
+            name,
+            move |_, message, _| {
+                let _ = tx.send(message.to_vec());
+            },
+            (),
+        )
+        .unwrap();
+    (connection, rx)
+}
+
+// Waits for `expected` among what arrives on `rx`, skipping anything else.
+fn expect(rx: &Receiver<Vec<u8>>, expected: &[u8]) {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut seen = Vec::new();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match rx.recv_timeout(left) {
+            Ok(message) if message == expected => return,
+            Ok(message) => seen.push(message),
+            Err(_) => break,
+        }
+    }
+    panic!("{:02X?} never arrived, only {:02X?}", expected, seen);
+}
+
+#[test]
+#[ignore = "needs virtual MIDI ports"]
+fn tempo_fader_reaches_rekordbox() {
+    // Unique names, so that runs in parallel or left over from a crash don't match.
+    let id = std::process::id();
+    let device_name = format!("party-saver test device {}", id);
+    let leds_name = format!("party-saver test leds {}", id);
+    let rekordbox_name = format!("party-saver test rekordbox {}", id);
+
+    // The device's side: what it sends party-saver, and where party-saver's LEDs arrive.
+    let mut device = MidiOutput::new("party-saver test device")
+        .unwrap()
+        .create_virtual(&device_name)
+        .unwrap();
+    let (led_tx, leds) = mpsc::channel();
+    let _leds = MidiInput::new("party-saver test leds")
+        .unwrap()
+        .create_virtual(
+            &leds_name,
+            move |_, message, _| {
+                let _ = led_tx.send(message.to_vec());
+            },
+            (),
+        )
+        .unwrap();
+
+    let _party_saver = Running(
+        Command::new(env!("CARGO_BIN_EXE_party-saver"))
+            .args(["--device-in", &device_name, "--device-out", &leds_name])
+            .args(["--virtual-out-name", &rekordbox_name])
+            .args(["--virtual-in-name", &format!("{} in", rekordbox_name)])
+            .args(["--no-status-line", "--no-log-file"])
+            .env_remove("PARTY_SAVER_CONFIG")
+            .env_remove("PARTY_SAVER_ALIASES")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let (_rekordbox, rekordbox) = listen(&rekordbox_name);
+
+    // Deck 1's tempo LED lights up at startup.
+    expect(&leds, &[0x9F, 0x23, 0x7F]);
+
+    // The tempo fader at its middle is sent to deck 1's tempo, inverted.
+    device.send(&[0xBF, 0x13, 0x40]).unwrap();
+    expect(&rekordbox, &[0xBB, 0x01, 0x3F]);
+}